@@ -1,39 +1,30 @@
+use crate::claim_proof;
+use crate::claim_proof::{Claim, ClaimStatus, ClaimVerification};
+use crate::contracts::PolygonZkEVMBridgeV2::BridgeEvent;
 use crate::contracts::PolygonZkEVMBridgeV2::PolygonZkEVMBridgeV2Instance;
 use crate::contracts::PolygonZkEVMGlobalExitRootV2::PolygonZkEVMGlobalExitRootV2Instance;
+use crate::leaf_bridge::{LeafBridge, RawBridgeEvent, SovereignConfig, UnsetClaim, WrappedToken};
+use crate::leaf_l1infotree::L1InfoLeafData;
+use crate::merkle_tree::DEPTH;
 use crate::merkle_tree::MerkleForest;
 use crate::merkle_tree::TreeType;
-use alloy::primitives::B256;
-use alloy::providers::fillers::BlobGasFiller;
-use alloy::providers::fillers::ChainIdFiller;
-use alloy::providers::fillers::FillProvider;
-use alloy::providers::fillers::GasFiller;
-use alloy::providers::fillers::JoinFill;
-use alloy::providers::fillers::NonceFiller;
-use alloy::providers::{Identity, Provider, RootProvider};
+use crate::rollup::RollupInfo;
+use crate::rpc::{ProviderStack, RpcStats};
+use alloy::eips::BlockId;
+use alloy::primitives::{Address, B256, Bytes, U256};
+use alloy::providers::Provider;
 use axum::Router;
 use axum::extract::Query;
 use axum::extract::State;
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::routing::get;
+use axum::routing::{get, post};
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::sync::Arc;
-
-// TODO: take this from somewhere else
-const DEPTH: usize = 32;
-
-// TODO: Ugly
-pub type ProviderStack = Arc<
-    FillProvider<
-        JoinFill<
-            Identity,
-            JoinFill<GasFiller, JoinFill<BlobGasFiller, JoinFill<NonceFiller, ChainIdFiller>>>,
-        >,
-        RootProvider,
-    >,
->;
+use tokio_util::sync::CancellationToken;
 
 // TODO: Add multiple L2 sync status
 #[derive(Serialize)]
@@ -63,6 +54,196 @@ struct Proof {
     rollup_exit_root: String,
 }
 
+#[derive(Serialize)]
+struct L1InfoClaimProofResponse {
+    l1_info_tree_index: u32,
+    global_index: String,
+    proof_local_exit_root: Vec<String>,
+    proof_rollup_exit_root: Vec<String>,
+    mainnet_exit_root: String,
+    rollup_exit_root: String,
+    global_exit_root: String,
+    timestamp: u64,
+    prev_block_hash: String,
+}
+
+#[derive(Serialize)]
+struct L1InfoLeafResponse {
+    l1_info_tree_index: u32,
+    global_exit_root: String,
+    mainnet_exit_root: String,
+    rollup_exit_root: String,
+    prev_block_hash: String,
+    timestamp: u64,
+    block_number: u64,
+}
+
+impl L1InfoLeafResponse {
+    fn new(index: u32, leaf: &L1InfoLeafData) -> Self {
+        Self {
+            l1_info_tree_index: index,
+            global_exit_root: format!("0x{:02x}", leaf.ger()),
+            mainnet_exit_root: format!("0x{:02x}", leaf.mer),
+            rollup_exit_root: format!("0x{:02x}", leaf.rer),
+            prev_block_hash: format!("0x{:02x}", leaf.prev_block_hash),
+            timestamp: leaf.timestamp,
+            block_number: leaf.block,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BridgeEventResponse {
+    network_id: u32,
+    leaf_type: u8,
+    origin_network: u32,
+    origin_address: String,
+    destination_network: u32,
+    destination_address: String,
+    amount: String,
+    metadata: String,
+    deposit_count: u32,
+    tx_hash: String,
+    block_number: u64,
+    log_index: u64,
+}
+
+impl BridgeEventResponse {
+    fn new(network_id: u32, raw: &RawBridgeEvent) -> Self {
+        let event = &raw.bridge_event;
+        Self {
+            network_id,
+            leaf_type: event.leafType,
+            origin_network: event.originNetwork,
+            origin_address: event.originAddress.to_string(),
+            destination_network: event.destinationNetwork,
+            destination_address: event.destinationAddress.to_string(),
+            amount: event.amount.to_string(),
+            metadata: event.metadata.to_string(),
+            deposit_count: event.depositCount,
+            tx_hash: format!("0x{:02x}", raw.tx_hash),
+            block_number: raw.block_number,
+            log_index: raw.log_index,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ClaimStatusResponse {
+    // One of "pending", "ready_to_claim" or "claimed".
+    status: String,
+    destination_network: u32,
+    claim_tx_hash: Option<String>,
+    claim_block_number: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct WrappedTokenResponse {
+    network_id: u32,
+    origin_network: u32,
+    origin_token_address: String,
+    wrapped_token_address: String,
+    metadata: String,
+    block_number: u64,
+}
+
+impl WrappedTokenResponse {
+    fn new(network_id: u32, token: &WrappedToken) -> Self {
+        Self {
+            network_id,
+            origin_network: token.origin_network,
+            origin_token_address: token.origin_address.to_string(),
+            wrapped_token_address: token.wrapped_address.to_string(),
+            metadata: token.metadata.to_string(),
+            block_number: token.block_number,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SovereignTokenResponse {
+    origin_network: u32,
+    origin_token_address: String,
+    sovereign_token_address: String,
+    is_not_mintable: bool,
+}
+
+#[derive(Serialize)]
+struct SovereignConfigResponse {
+    network_id: u32,
+    tokens: Vec<SovereignTokenResponse>,
+    legacy_token_addresses: Vec<String>,
+    weth_address: Option<String>,
+    weth_not_mintable: bool,
+    bridge_manager: Option<String>,
+}
+
+impl SovereignConfigResponse {
+    fn new(network_id: u32, config: &SovereignConfig) -> Self {
+        Self {
+            network_id,
+            tokens: config
+                .tokens
+                .iter()
+                .map(|t| SovereignTokenResponse {
+                    origin_network: t.origin_network,
+                    origin_token_address: t.origin_address.to_string(),
+                    sovereign_token_address: t.sovereign_address.to_string(),
+                    is_not_mintable: t.is_not_mintable,
+                })
+                .collect(),
+            legacy_token_addresses: config.legacy_tokens.iter().map(|a| a.to_string()).collect(),
+            weth_address: config.weth_address.map(|a| a.to_string()),
+            weth_not_mintable: config.weth_not_mintable,
+            bridge_manager: config.bridge_manager.map(|a| a.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct UnsetClaimResponse {
+    source_network: u32,
+    deposit_cnt: u32,
+    block_number: u64,
+    log_index: u64,
+}
+
+impl From<&UnsetClaim> for UnsetClaimResponse {
+    fn from(unset: &UnsetClaim) -> Self {
+        Self {
+            source_network: unset.source_network,
+            deposit_cnt: unset.leaf_index,
+            block_number: unset.block_number,
+            log_index: unset.log_index,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AggchainResponse {
+    rollup_id: u32,
+    chain_id: u64,
+    rollup_address: String,
+    fork_id: u64,
+    rollup_type_id: u32,
+    verifier_type: u8,
+    block_number: u64,
+}
+
+impl From<&RollupInfo> for AggchainResponse {
+    fn from(rollup: &RollupInfo) -> Self {
+        Self {
+            rollup_id: rollup.rollup_id,
+            chain_id: rollup.chain_id,
+            rollup_address: rollup.rollup_address.to_string(),
+            fork_id: rollup.fork_id,
+            rollup_type_id: rollup.rollup_type_id,
+            verifier_type: rollup.verifier_type,
+            block_number: rollup.block_number,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub tree: Arc<MerkleForest>,
@@ -70,6 +251,8 @@ pub struct AppState {
     pub l1_bridge: PolygonZkEVMBridgeV2Instance<ProviderStack>,
     pub l2_bridges: HashMap<u32, PolygonZkEVMBridgeV2Instance<ProviderStack>>,
     pub l1_infotree: PolygonZkEVMGlobalExitRootV2Instance<ProviderStack>,
+    // Errors of every RPC provider, by provider name.
+    pub rpc_stats: Vec<(String, Arc<RpcStats>)>,
     //pub rollup_manager: PolygonRollupManagerInstance<ProviderStack>, Not needed?
 }
 
@@ -146,6 +329,15 @@ async fn sync_status(State(state): State<AppState>) -> impl IntoResponse {
     axum::Json(response)
 }
 
+async fn rpc_stats(State(state): State<AppState>) -> impl IntoResponse {
+    let stats: BTreeMap<&str, &RpcStats> = state
+        .rpc_stats
+        .iter()
+        .map(|(name, stats)| (name.as_str(), stats.as_ref()))
+        .collect();
+    axum::Json(stats).into_response()
+}
+
 #[derive(Deserialize, Debug)]
 struct ClaimProofParams {
     #[serde(rename = "net_id")] // Updated query parameter name
@@ -197,16 +389,465 @@ async fn claim_proof(
     axum::Json(response)
 }
 
-pub async fn run_server(state: AppState) -> Result<(), Box<dyn Error + Send + Sync>> {
+// Unlike /merkle-proof, the proofs are against the exit roots of an L1 Info Tree leaf,
+// so they can be used to claim on L1.
+async fn l1_info_claim_proof(
+    State(state): State<AppState>,
+    Query(params): Query<ClaimProofParams>,
+) -> Result<axum::Json<L1InfoClaimProofResponse>, (StatusCode, String)> {
+    let deposit_count: u32 = params
+        .deposit_count
+        .try_into()
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid deposit_cnt".to_string()))?;
+
+    let proof = claim_proof::claim_proof(&state.tree, params.network_id, deposit_count)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            format!(
+                "Deposit {} of network {} is not claimable yet",
+                deposit_count, params.network_id
+            ),
+        ))?;
+
+    let leaf = &proof.l1_info_leaf;
+    Ok(axum::Json(L1InfoClaimProofResponse {
+        l1_info_tree_index: proof.l1_info_tree_index,
+        global_index: proof.global_index.to_string(),
+        proof_local_exit_root: proof
+            .proof_local_exit_root
+            .map(|byte| format!("0x{:02x}", byte))
+            .to_vec(),
+        proof_rollup_exit_root: proof
+            .proof_rollup_exit_root
+            .map(|byte| format!("0x{:02x}", byte))
+            .to_vec(),
+        mainnet_exit_root: format!("0x{:02x}", leaf.mer),
+        rollup_exit_root: format!("0x{:02x}", leaf.rer),
+        global_exit_root: format!("0x{:02x}", leaf.ger()),
+        timestamp: leaf.timestamp,
+        prev_block_hash: format!("0x{:02x}", leaf.prev_block_hash),
+    }))
+}
+
+// Arguments of claimAsset/claimMessage, named as in the /claim-proof and /bridge-event
+// responses. The global index can be decimal or 0x prefixed hex.
+#[derive(Deserialize, Debug)]
+struct VerifyProofRequest {
+    global_index: U256,
+    proof_local_exit_root: [B256; DEPTH],
+    proof_rollup_exit_root: [B256; DEPTH],
+    mainnet_exit_root: B256,
+    rollup_exit_root: B256,
+    leaf_type: u8,
+    origin_network: u32,
+    origin_address: Address,
+    destination_network: u32,
+    destination_address: Address,
+    amount: U256,
+    metadata: Bytes,
+}
+
+#[derive(Serialize)]
+struct VerifyProofResponse {
+    valid: bool,
+    error: Option<String>,
+    l1_info_tree_index: Option<u32>,
+    global_exit_root: Option<String>,
+    l1_info_root: Option<String>,
+}
+
+// An invalid proof is not an error of the request, the response says why it's invalid.
+async fn verify_proof(
+    State(state): State<AppState>,
+    axum::Json(request): axum::Json<VerifyProofRequest>,
+) -> Result<axum::Json<VerifyProofResponse>, (StatusCode, String)> {
+    let leaf = LeafBridge::new(BridgeEvent {
+        leafType: request.leaf_type,
+        originNetwork: request.origin_network,
+        originAddress: request.origin_address,
+        destinationNetwork: request.destination_network,
+        destinationAddress: request.destination_address,
+        amount: request.amount,
+        metadata: request.metadata,
+        // Not part of the leaf.
+        depositCount: 0,
+    });
+    let claim = Claim {
+        leaf: leaf.hashed_leaf(),
+        proof_local_exit_root: request.proof_local_exit_root,
+        proof_rollup_exit_root: request.proof_rollup_exit_root,
+        global_index: request.global_index,
+        mainnet_exit_root: request.mainnet_exit_root,
+        rollup_exit_root: request.rollup_exit_root,
+    };
+
+    // The L1 Info Tree leaf is proven against the onchain root, at the block the local tree
+    // is synced to.
+    let internal_error = |e: eyre::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let rpc_error = |e: alloy::contract::Error| (StatusCode::BAD_GATEWAY, e.to_string());
+    let block = state
+        .tree
+        .get_latest_block(TreeType::L1InfoTree)
+        .map_err(internal_error)?
+        .ok_or((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "L1 Info Tree not indexed yet".to_string(),
+        ))?;
+    let l1_info_root = state
+        .l1_infotree
+        .getRoot()
+        .block(BlockId::number(block))
+        .call()
+        .await
+        .map_err(rpc_error)?;
+    let l1_info_leaf_count: u32 = state
+        .l1_infotree
+        .depositCount()
+        .block(BlockId::number(block))
+        .call()
+        .await
+        .map_err(rpc_error)?
+        .try_into()
+        .map_err(|_| {
+            (
+                StatusCode::BAD_GATEWAY,
+                "Invalid onchain L1 Info Tree deposit count".to_string(),
+            )
+        })?;
+
+    let verification =
+        claim_proof::verify_claim(&state.tree, &claim, l1_info_root, l1_info_leaf_count)
+            .map_err(internal_error)?;
+
+    Ok(axum::Json(match verification {
+        ClaimVerification::Valid {
+            l1_info_tree_index,
+            global_exit_root,
+            l1_info_root,
+        } => VerifyProofResponse {
+            valid: true,
+            error: None,
+            l1_info_tree_index: Some(l1_info_tree_index),
+            global_exit_root: Some(format!("0x{:02x}", global_exit_root)),
+            l1_info_root: Some(format!("0x{:02x}", l1_info_root)),
+        },
+        ClaimVerification::Invalid(error) => VerifyProofResponse {
+            valid: false,
+            error: Some(error),
+            l1_info_tree_index: None,
+            global_exit_root: None,
+            l1_info_root: None,
+        },
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+struct L1InfoLeafParams {
+    index: Option<u32>,
+    ger: Option<B256>,
+}
+
+// Looks up an L1 Info Tree leaf either by its index or by the GER it added.
+async fn l1_info_leaf(
+    State(state): State<AppState>,
+    Query(params): Query<L1InfoLeafParams>,
+) -> Result<axum::Json<L1InfoLeafResponse>, (StatusCode, String)> {
+    let leaf = match (params.index, params.ger) {
+        (Some(index), None) => state
+            .tree
+            .get_l1info_leaf(index)
+            .map(|leaf| leaf.map(|leaf| (index, leaf))),
+        (None, Some(ger)) => state.tree.get_l1info_leaf_by_ger(&ger),
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Either index or ger must be set".to_string(),
+            ));
+        }
+    }
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let (index, leaf) = leaf.ok_or((
+        StatusCode::NOT_FOUND,
+        "L1 info tree leaf not found".to_string(),
+    ))?;
+    Ok(axum::Json(L1InfoLeafResponse::new(index, &leaf)))
+}
+
+#[derive(Deserialize, Debug)]
+struct L1InfoLeavesParams {
+    block: u64,
+}
+
+// All L1 Info Tree leaves added in an L1 block.
+async fn l1_info_leaves(
+    State(state): State<AppState>,
+    Query(params): Query<L1InfoLeavesParams>,
+) -> Result<axum::Json<Vec<L1InfoLeafResponse>>, (StatusCode, String)> {
+    let leaves = state
+        .tree
+        .get_l1info_leaves_by_block(params.block)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(axum::Json(
+        leaves
+            .iter()
+            .map(|(index, leaf)| L1InfoLeafResponse::new(*index, leaf))
+            .collect(),
+    ))
+}
+
+async fn bridge_event(
+    State(state): State<AppState>,
+    Query(params): Query<ClaimProofParams>,
+) -> Result<axum::Json<BridgeEventResponse>, (StatusCode, String)> {
+    let deposit_count: u32 = params
+        .deposit_count
+        .try_into()
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid deposit_cnt".to_string()))?;
+
+    let event = state
+        .tree
+        .get_bridge_event(params.network_id, deposit_count)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Bridge event not found".to_string()))?;
+    Ok(axum::Json(BridgeEventResponse::new(
+        params.network_id,
+        &event,
+    )))
+}
+
+async fn claim_status(
+    State(state): State<AppState>,
+    Query(params): Query<ClaimProofParams>,
+) -> Result<axum::Json<ClaimStatusResponse>, (StatusCode, String)> {
+    let deposit_count: u32 = params
+        .deposit_count
+        .try_into()
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid deposit_cnt".to_string()))?;
+
+    let (destination_network, status) =
+        claim_proof::claim_status(&state.tree, params.network_id, deposit_count)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .ok_or((StatusCode::NOT_FOUND, "Bridge event not found".to_string()))?;
+
+    let (status, claim) = match status {
+        ClaimStatus::Pending => ("pending", None),
+        ClaimStatus::ReadyToClaim => ("ready_to_claim", None),
+        ClaimStatus::Claimed(claim) => ("claimed", Some(claim)),
+    };
+    Ok(axum::Json(ClaimStatusResponse {
+        status: status.to_string(),
+        destination_network,
+        claim_tx_hash: claim.as_ref().map(|c| format!("0x{:02x}", c.tx_hash)),
+        claim_block_number: claim.as_ref().map(|c| c.block_number),
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+struct BridgeEventsParams {
+    tx_hash: Option<B256>,
+    destination_address: Option<Address>,
+}
+
+// Looks up bridge events either by the transaction that emitted them or by destination address.
+async fn bridge_events(
+    State(state): State<AppState>,
+    Query(params): Query<BridgeEventsParams>,
+) -> Result<axum::Json<Vec<BridgeEventResponse>>, (StatusCode, String)> {
+    let events = match (params.tx_hash, params.destination_address) {
+        (Some(tx_hash), None) => state.tree.get_bridge_events_by_tx(&tx_hash),
+        (None, Some(address)) => state.tree.get_bridge_events_by_destination(&address),
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Either tx_hash or destination_address must be set".to_string(),
+            ));
+        }
+    }
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(axum::Json(
+        events
+            .iter()
+            .map(|(network_id, event)| BridgeEventResponse::new(*network_id, event))
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize, Debug)]
+struct WrappedTokenParams {
+    #[serde(rename = "net_id")]
+    network_id: u32,
+    origin_network: Option<u32>,
+    origin_token_address: Option<Address>,
+    wrapped_token_address: Option<Address>,
+}
+
+// Looks up a wrapped token of the bridge of `net_id`, either by its origin token or by
+// its own address.
+async fn wrapped_token(
+    State(state): State<AppState>,
+    Query(params): Query<WrappedTokenParams>,
+) -> Result<axum::Json<WrappedTokenResponse>, (StatusCode, String)> {
+    let token = match (
+        params.origin_network,
+        params.origin_token_address,
+        params.wrapped_token_address,
+    ) {
+        (Some(origin_network), Some(origin_address), None) => {
+            state
+                .tree
+                .get_wrapped_token(params.network_id, origin_network, &origin_address)
+        }
+        (None, None, Some(wrapped_address)) => state
+            .tree
+            .get_wrapped_token_by_address(params.network_id, &wrapped_address),
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Either origin_network and origin_token_address or wrapped_token_address must be set"
+                    .to_string(),
+            ));
+        }
+    }
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or((StatusCode::NOT_FOUND, "Wrapped token not found".to_string()))?;
+
+    Ok(axum::Json(WrappedTokenResponse::new(
+        params.network_id,
+        &token,
+    )))
+}
+
+#[derive(Deserialize, Debug)]
+struct NetworkParams {
+    #[serde(rename = "net_id")]
+    network_id: u32,
+}
+
+async fn wrapped_tokens(
+    State(state): State<AppState>,
+    Query(params): Query<NetworkParams>,
+) -> Result<axum::Json<Vec<WrappedTokenResponse>>, (StatusCode, String)> {
+    let tokens = state
+        .tree
+        .get_wrapped_tokens(params.network_id)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(axum::Json(
+        tokens
+            .iter()
+            .map(|token| WrappedTokenResponse::new(params.network_id, token))
+            .collect(),
+    ))
+}
+
+// Sovereign token mappings, WETH address and bridge manager of the sovereign chain
+// `net_id`. Everything is empty for the other chains.
+async fn sovereign_config(
+    State(state): State<AppState>,
+    Query(params): Query<NetworkParams>,
+) -> Result<axum::Json<SovereignConfigResponse>, (StatusCode, String)> {
+    let config = state
+        .tree
+        .get_sovereign_config(params.network_id)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(axum::Json(SovereignConfigResponse::new(
+        params.network_id,
+        &config,
+    )))
+}
+
+async fn unset_claims(
+    State(state): State<AppState>,
+    Query(params): Query<NetworkParams>,
+) -> Result<axum::Json<Vec<UnsetClaimResponse>>, (StatusCode, String)> {
+    let unset_claims = state
+        .tree
+        .get_unset_claims(params.network_id)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(axum::Json(
+        unset_claims.iter().map(UnsetClaimResponse::from).collect(),
+    ))
+}
+
+// Every aggchain attached to the RollupManager.
+async fn aggchains(
+    State(state): State<AppState>,
+) -> Result<axum::Json<Vec<AggchainResponse>>, (StatusCode, String)> {
+    let rollups = state
+        .tree
+        .get_rollups()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(axum::Json(
+        rollups.iter().map(AggchainResponse::from).collect(),
+    ))
+}
+
+#[derive(Deserialize, Debug)]
+struct AggchainParams {
+    rollup_id: Option<u32>,
+    chain_id: Option<u64>,
+    rollup_address: Option<Address>,
+}
+
+// Looks up an aggchain by its rollup ID, chain ID or rollup address.
+async fn aggchain(
+    State(state): State<AppState>,
+    Query(params): Query<AggchainParams>,
+) -> Result<axum::Json<AggchainResponse>, (StatusCode, String)> {
+    let rollup = match (params.rollup_id, params.chain_id, params.rollup_address) {
+        (Some(rollup_id), None, None) => state.tree.get_rollup(rollup_id),
+        (None, Some(chain_id), None) => state.tree.get_rollup_by_chain_id(chain_id),
+        (None, None, Some(rollup_address)) => state.tree.get_rollup_by_address(&rollup_address),
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Exactly one of rollup_id, chain_id or rollup_address must be set".to_string(),
+            ));
+        }
+    }
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or((StatusCode::NOT_FOUND, "Aggchain not found".to_string()))?;
+
+    Ok(axum::Json(AggchainResponse::from(&rollup)))
+}
+
+// Serves the API until `shutdown` is cancelled, then waits for in-flight requests.
+pub async fn run_server(
+    state: AppState,
+    shutdown: CancellationToken,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     let server_task = tokio::spawn(async move {
         let app = Router::new()
             .route("/sync-status", get(sync_status))
+            .route("/rpc-stats", get(rpc_stats))
             .route("/merkle-proof", get(claim_proof))
+            .route("/claim-proof", get(l1_info_claim_proof))
+            .route("/verify-proof", post(verify_proof))
+            .route("/l1-info-tree-leaf", get(l1_info_leaf))
+            .route("/l1-info-tree-leaves", get(l1_info_leaves))
+            .route("/bridge-event", get(bridge_event))
+            .route("/bridge-events", get(bridge_events))
+            .route("/claim-status", get(claim_status))
+            .route("/wrapped-token", get(wrapped_token))
+            .route("/wrapped-tokens", get(wrapped_tokens))
+            .route("/sovereign-config", get(sovereign_config))
+            .route("/unset-claims", get(unset_claims))
+            .route("/aggchains", get(aggchains))
+            .route("/aggchain", get(aggchain))
             .with_state(state);
 
         let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
 
-        axum::serve(listener, app).await
+        axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown.cancelled_owned())
+            .await
     });
 
     server_task.await??;
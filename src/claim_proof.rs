@@ -0,0 +1,328 @@
+use crate::leaf_bridge::RawClaimEvent;
+use crate::leaf_l1infotree::{L1InfoLeafData, LeafL1InfoTree};
+use crate::merkle_tree::{
+    AggchainId, DEPTH, MerkleForest, TreeType, calculate_merkle_root, verify_merkle_proof,
+};
+use alloy::primitives::{B256, U256};
+use eyre::{Result, eyre};
+
+// Everything needed to call claimAsset/claimMessage in the L1 bridge for a deposit.
+// Both proofs are against the exit roots of the L1 Info Tree leaf at `l1_info_tree_index`.
+#[derive(Debug)]
+pub struct ClaimProof {
+    pub l1_info_tree_index: u32,
+    pub l1_info_leaf: L1InfoLeafData,
+    pub proof_local_exit_root: [B256; DEPTH],
+    pub proof_rollup_exit_root: [B256; DEPTH],
+    pub global_index: U256,
+}
+
+// Global index of a deposit, as expected by the bridge contract:
+// - bit 64: set if the deposit comes from mainnet
+// - bits 32..64: rollup index, the aggchain id minus one (zero for mainnet)
+// - bits 0..32: deposit count in the Local Exit Tree
+pub fn global_index(network_id: AggchainId, deposit_count: u32) -> U256 {
+    let local = U256::from(deposit_count);
+    match network_id.checked_sub(1) {
+        None => (U256::from(1) << 64) | local,
+        Some(rollup_index) => (U256::from(rollup_index) << 32) | local,
+    }
+}
+
+// A global index split in its parts. See `global_index`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GlobalIndex {
+    pub mainnet_flag: bool,
+    pub rollup_index: u32,
+    pub local_index: u32,
+}
+
+impl GlobalIndex {
+    pub fn decode(global_index: U256) -> Self {
+        let limbs = global_index.as_limbs();
+        Self {
+            mainnet_flag: limbs[1] & 1 == 1,
+            rollup_index: (limbs[0] >> 32) as u32,
+            local_index: limbs[0] as u32,
+        }
+    }
+
+    // Network where the deposit was made. The rollup index is ignored for mainnet.
+    pub fn source_network(&self) -> AggchainId {
+        if self.mainnet_flag {
+            0
+        } else {
+            self.rollup_index + 1
+        }
+    }
+}
+
+// Arguments of claimAsset/claimMessage that the bridge contract checks before paying out.
+// `leaf` is the hashed deposit, see `LeafBridge::hashed_leaf`.
+#[derive(Debug)]
+pub struct Claim {
+    pub leaf: B256,
+    pub proof_local_exit_root: [B256; DEPTH],
+    pub proof_rollup_exit_root: [B256; DEPTH],
+    pub global_index: U256,
+    pub mainnet_exit_root: B256,
+    pub rollup_exit_root: B256,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClaimVerification {
+    // The global exit root of the claim is the leaf `l1_info_tree_index` of the L1 Info
+    // Tree, proven against the onchain root of the tree.
+    Valid {
+        l1_info_tree_index: u32,
+        global_exit_root: B256,
+        l1_info_root: B256,
+    },
+    // Why the bridge would reject the claim.
+    Invalid(String),
+}
+
+// Verifies a claim the way the bridge contract does: the deposit against the local exit
+// root, which for rollups is then proven against the rollup exit root. The global exit
+// root of both roots must be in the L1 Info Tree, whose leaf is proven against
+// `l1_info_root`, the onchain root of the tree when it had `l1_info_leaf_count` leaves.
+pub fn verify_claim(
+    tree: &MerkleForest,
+    claim: &Claim,
+    l1_info_root: B256,
+    l1_info_leaf_count: u32,
+) -> Result<ClaimVerification> {
+    let index = GlobalIndex::decode(claim.global_index);
+
+    // Same as the contract, unused bits of the global index must be zero.
+    if claim.global_index != global_index(index.source_network(), index.local_index) {
+        return Ok(ClaimVerification::Invalid(format!(
+            "Invalid global index {}",
+            claim.global_index
+        )));
+    }
+
+    if index.mainnet_flag {
+        if !verify_merkle_proof(
+            &claim.leaf,
+            &claim.proof_local_exit_root,
+            index.local_index,
+            &claim.mainnet_exit_root,
+        ) {
+            return Ok(ClaimVerification::Invalid(
+                "Invalid proof against the mainnet exit root".to_string(),
+            ));
+        }
+    } else {
+        let local_exit_root = calculate_merkle_root(
+            &claim.leaf,
+            &claim.proof_local_exit_root,
+            index.local_index as u64,
+        );
+        if !verify_merkle_proof(
+            &local_exit_root,
+            &claim.proof_rollup_exit_root,
+            index.rollup_index,
+            &claim.rollup_exit_root,
+        ) {
+            return Ok(ClaimVerification::Invalid(
+                "Invalid proof against the rollup exit root".to_string(),
+            ));
+        }
+    }
+
+    let ger = LeafL1InfoTree::to_ger(&claim.mainnet_exit_root, &claim.rollup_exit_root);
+    let Some((l1_info_tree_index, leaf)) = tree.get_l1info_leaf_by_ger(&ger)? else {
+        return Ok(ClaimVerification::Invalid(format!(
+            "Global exit root {} is not in the L1 Info Tree",
+            ger
+        )));
+    };
+
+    if l1_info_tree_index >= l1_info_leaf_count {
+        return Ok(ClaimVerification::Invalid(format!(
+            "Global exit root {} is not in the L1 Info Tree with root {}",
+            ger, l1_info_root
+        )));
+    }
+
+    // Only fails if the stored tree differs from the onchain one.
+    let proof =
+        tree.merkle_proof_at(TreeType::L1InfoTree, l1_info_tree_index, l1_info_leaf_count)?;
+    if !verify_merkle_proof(
+        &leaf.info_root_leaf(),
+        &proof,
+        l1_info_tree_index,
+        &l1_info_root,
+    ) {
+        return Err(eyre!(
+            "L1 info leaf {} doesn't match the onchain L1 info root {}",
+            l1_info_tree_index,
+            l1_info_root
+        ));
+    }
+
+    Ok(ClaimVerification::Valid {
+        l1_info_tree_index,
+        global_exit_root: ger,
+        l1_info_root,
+    })
+}
+
+// Status of a deposit, as seen from its destination network.
+pub enum ClaimStatus {
+    // Not yet included in the L1 Info Tree, so it can't be claimed. Also while the exit
+    // roots of the L1 Info Tree leaf including it are not indexed yet.
+    Pending,
+    ReadyToClaim,
+    Claimed(RawClaimEvent),
+}
+
+// Joins the deposit `deposit_count` of `network_id` with its claim in the destination network,
+// returned along with the status. Returns None if the deposit is not indexed. Note that claims
+// are only seen if the destination network is indexed.
+pub fn claim_status(
+    tree: &MerkleForest,
+    network_id: AggchainId,
+    deposit_count: u32,
+) -> Result<Option<(AggchainId, ClaimStatus)>> {
+    let Some(deposit) = tree.get_bridge_event(network_id, deposit_count)? else {
+        return Ok(None);
+    };
+
+    let destination = deposit.bridge_event.destinationNetwork;
+    if let Some(claim) = tree.get_claim(destination, network_id, deposit_count)? {
+        // Sovereign chains can unset a claim, which makes the deposit claimable again.
+        let unset = tree
+            .get_unset_claim(destination, network_id, deposit_count)?
+            .is_some_and(|u| (u.block_number, u.log_index) > (claim.block_number, claim.log_index));
+        if !unset {
+            return Ok(Some((destination, ClaimStatus::Claimed(claim))));
+        }
+    }
+
+    let status = if claim_proof(tree, network_id, deposit_count)?.is_some() {
+        ClaimStatus::ReadyToClaim
+    } else {
+        ClaimStatus::Pending
+    };
+    Ok(Some((destination, status)))
+}
+
+// Builds the proof of the deposit `deposit_count` of `network_id`. Returns None if the
+// deposit is not yet claimable, either because it's not indexed or because no L1 Info
+// Tree leaf includes it yet. Also while the exit roots of that leaf are not indexed, when
+// the L1 Info Tree is indexed ahead of the bridges or the RollupManager.
+pub fn claim_proof(
+    tree: &MerkleForest,
+    network_id: AggchainId,
+    deposit_count: u32,
+) -> Result<Option<ClaimProof>> {
+    let let_tree = TreeType::LocalExitTree(network_id);
+    if deposit_count >= tree.get_leaf_count(&let_tree)? {
+        return Ok(None);
+    }
+
+    let (index, leaf, leaf_count, proof_rollup_exit_root) = if network_id == 0 {
+        let Some(index) = first_leaf_with_mainnet_deposit(tree, deposit_count)? else {
+            return Ok(None);
+        };
+        let leaf = tree
+            .get_l1info_leaf(index)?
+            .ok_or_else(|| eyre!("L1 info leaf {} not found", index))?;
+        let Some(leaf_count) = tree.get_exit_root_leaf_count(0, &leaf.mer)? else {
+            return Ok(None);
+        };
+
+        // Mainnet deposits are not part of the Rollup Exit Tree.
+        (index, leaf, leaf_count, [B256::ZERO; DEPTH])
+    } else {
+        let Some(index) = first_leaf_with_rollup_deposit(tree, network_id, deposit_count)? else {
+            return Ok(None);
+        };
+        let leaf = tree
+            .get_l1info_leaf(index)?
+            .ok_or_else(|| eyre!("L1 info leaf {} not found", index))?;
+
+        // The Rollup Exit Tree may not be indexed up to this leaf yet.
+        let Some(ler) = tree.get_rollup_leaf_at_root(&leaf.rer, network_id)? else {
+            return Ok(None);
+        };
+        let Some(leaf_count) = tree.get_exit_root_leaf_count(network_id, &ler)? else {
+            return Ok(None);
+        };
+        let proof = tree
+            .rollup_merkle_proof_at_root(&leaf.rer, network_id)?
+            .ok_or_else(|| eyre!("Rollup exit root {} not found", leaf.rer))?;
+        (index, leaf, leaf_count, proof)
+    };
+
+    Ok(Some(ClaimProof {
+        l1_info_tree_index: index,
+        l1_info_leaf: leaf,
+        proof_local_exit_root: tree.merkle_proof_at(let_tree, deposit_count, leaf_count)?,
+        proof_rollup_exit_root,
+        global_index: global_index(network_id, deposit_count),
+    }))
+}
+
+// Index of the first L1 Info Tree leaf whose mainnet exit root includes the deposit.
+// Mainnet exit roots only grow, so the leaves can be binary searched.
+fn first_leaf_with_mainnet_deposit(tree: &MerkleForest, deposit_count: u32) -> Result<Option<u32>> {
+    let info_leaf_count = tree.get_leaf_count(&TreeType::L1InfoTree)?;
+
+    let (mut lo, mut hi) = (0, info_leaf_count);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let leaf = tree
+            .get_l1info_leaf(mid)?
+            .ok_or_else(|| eyre!("L1 info leaf {} not found", mid))?;
+
+        // A root that is not indexed is newer than the local tree, which already has
+        // the deposit. So it includes it.
+        let includes = tree
+            .get_exit_root_leaf_count(0, &leaf.mer)?
+            .is_none_or(|count| count > deposit_count);
+        if includes {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+
+    Ok((lo < info_leaf_count).then_some(lo))
+}
+
+// Index of the first L1 Info Tree leaf whose rollup exit root includes the deposit of the
+// rollup `network_id`. Its local exit root only grows, so the leaves can be binary searched.
+fn first_leaf_with_rollup_deposit(
+    tree: &MerkleForest,
+    network_id: AggchainId,
+    deposit_count: u32,
+) -> Result<Option<u32>> {
+    let info_leaf_count = tree.get_leaf_count(&TreeType::L1InfoTree)?;
+
+    let (mut lo, mut hi) = (0, info_leaf_count);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let leaf = tree
+            .get_l1info_leaf(mid)?
+            .ok_or_else(|| eyre!("L1 info leaf {} not found", mid))?;
+
+        // Unknown roots are newer than the local trees. A rollup exit root that is not
+        // indexed can't be proven yet, which is checked once the leaf is found.
+        let includes = match tree.get_rollup_leaf_at_root(&leaf.rer, network_id)? {
+            Some(ler) => tree
+                .get_exit_root_leaf_count(network_id, &ler)?
+                .is_none_or(|count| count > deposit_count),
+            None => true,
+        };
+        if includes {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+
+    Ok((lo < info_leaf_count).then_some(lo))
+}
@@ -1,5 +1,6 @@
+use alloy::rpc::types::BlockNumberOrTag;
 use alloy::transports::http::reqwest::Url;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::str::FromStr;
 
 // TODO:
@@ -40,11 +41,15 @@ impl FromStr for L2Rpc {
 #[derive(Parser)]
 #[command(name = "aggkit-rust")]
 #[command(about = "TODO", long_about = None)]
+#[command(subcommand_negates_reqs = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// RPC URL for the Ethereum L1 network.
     /// https://mainnet.infura.io/v3/xxx
-    #[arg(long)]
-    pub l1_rpc_url: String,
+    #[arg(long, required = true)]
+    pub l1_rpc_url: Option<String>,
 
     /// RPC URLs for the Aggchain L2 networks. chain-id:rpc-url.
     /// --l2-rpc-url=0:http://someurl
@@ -53,7 +58,7 @@ pub struct Cli {
 
     /// Path for the key-value store storing the merkle tree.
     /// Example: db
-    #[arg(long, default_value = "db")]
+    #[arg(long, default_value = "db", global = true)]
     pub key_value_store: String,
 
     /// Contract address of the PolygonZkEVMGlobalExitRootV2.
@@ -71,8 +76,56 @@ pub struct Cli {
     #[arg(long, default_value = "0x5132A183E9F3CB7C848b0AAC5Ae0c4f0491B7aB2")]
     pub rollup_manager_address: String,
 
-    /// Number of blocks to query in a single request.
-    /// Lower values help avoid RPC timeouts but may slow down indexing.
+    /// Maximum number of blocks to query in a single request.
+    /// Lower values help avoid RPC timeouts but may slow down indexing. The range is
+    /// lowered while the RPC rejects it or returns too many results.
     #[arg(long, default_value = "10000")]
     pub block_range: u64,
+
+    /// Maximum number of retries of an RPC request that was rate limited or failed
+    /// transiently. Other errors are not retried.
+    #[arg(long, default_value = "100")]
+    pub rpc_max_retries: u32,
+
+    /// Milliseconds to wait before retrying an RPC request, unless the provider says
+    /// how long.
+    #[arg(long, default_value = "2000")]
+    pub rpc_backoff: u64,
+
+    /// Compute units per second of the RPC provider. Retries of queued requests wait
+    /// longer not to exceed it.
+    #[arg(long, default_value = "100")]
+    pub rpc_compute_units_per_second: u64,
+
+    /// Block up to which the contracts are indexed: finalized, safe or latest.
+    /// Non-finalized modes keep track of block hashes to detect reorgs and roll them back.
+    #[arg(long, default_value = "finalized")]
+    pub sync_mode: BlockNumberOrTag,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Checks the key-value store without indexing: recomputes every node from the
+    /// leaves and checks the leaf counts and latest blocks of every tree.
+    CheckDb {
+        /// Rewrite the inconsistent nodes and metadata.
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Exports the trees of the key-value store to a snapshot file.
+    ExportSnapshot {
+        /// Snapshot file to write.
+        #[arg(long)]
+        file: String,
+        /// Also export the bridge events, needed to query the events before the snapshot.
+        #[arg(long)]
+        events: bool,
+    },
+    /// Creates a new key-value store from a snapshot file. Indexing then resumes from
+    /// the blocks the snapshot was taken at.
+    ImportSnapshot {
+        /// Snapshot file to read.
+        #[arg(long)]
+        file: String,
+    },
 }
@@ -32,7 +32,7 @@ sol!(
 );
 
 // This is an old version of the event. Onchain it has the same address but the ABI
-// had a breaking chang in the AddExistingRollup event.
+// had a breaking chang in the AddExistingRollup and AddNewRollupType events.
 sol!(
     #[allow(missing_docs)]
     #[sol(rpc)]
@@ -45,6 +45,15 @@ sol!(
             uint8 rollupCompatibilityID,
             uint64 lastVerifiedBatchBeforeUpgrade
         );
+        event AddNewRollupType(
+            uint32 indexed rollupTypeID,
+            address consensusImplementation,
+            address verifier,
+            uint64 forkID,
+            uint8 rollupCompatibilityID,
+            bytes32 genesis,
+            string description
+        );
     }
 );
 
@@ -1,144 +1,370 @@
+use crate::merkle_tree::MerkleForest;
+use crate::rpc::{self, RetryConfig, RpcStats, is_range_limit_error};
 use alloy::primitives::{Address, B256};
 use alloy::providers::Provider;
 use alloy::rpc::types::Filter;
 use alloy::rpc::types::{BlockNumberOrTag, Log};
-use alloy::transports::TransportError;
 use alloy::transports::http::reqwest::Url;
-use alloy::transports::layers::RetryPolicy;
-use alloy::{
-    providers::ProviderBuilder, rpc::client::RpcClient, transports::layers::RetryBackoffLayer,
-};
 use async_trait::async_trait;
 use eyre::Result;
 use futures::stream::{self, StreamExt, TryStreamExt};
 use std::collections::BTreeMap;
 use std::error::Error;
+use std::ops::RangeInclusive;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use tokio::task;
 use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
 
-// TODO:
-// - Handle sigterm gracefully.
+/// Number of blocks queried per `eth_getLogs`. It's halved whenever a query hits a limit of
+/// the RPC, and doubled after enough queries in a row succeed, up to the configured range.
+pub struct BlockRange {
+    max: u64,
+    current: AtomicU64,
+    successes: AtomicU64,
+}
+
+impl BlockRange {
+    /// Successful queries in a row after which the range is doubled.
+    pub const GROW_AFTER: u64 = 10;
+
+    pub fn new(max: u64) -> Self {
+        let max = max.max(1);
+        Self {
+            max,
+            current: AtomicU64::new(max),
+            successes: AtomicU64::new(0),
+        }
+    }
 
-#[derive(Debug, Copy, Clone, Default)]
-#[non_exhaustive]
-pub struct AlwaysRetryPolicy;
+    pub fn get(&self) -> u64 {
+        self.current.load(Ordering::Relaxed)
+    }
 
-impl RetryPolicy for AlwaysRetryPolicy {
-    fn should_retry(&self, error: &TransportError) -> bool {
-        // TODO: Be more granular with the retry policy.
-        // we don't want to retry in some cases.
-        true
+    /// Called when a query of `failed` blocks hit a limit. Returns the new range.
+    pub fn shrink(&self, failed: u64) -> u64 {
+        self.successes.store(0, Ordering::Relaxed);
+        let half = (failed / 2).max(1);
+        // Concurrent queries may have shrunk it already.
+        self.current.fetch_min(half, Ordering::Relaxed).min(half)
     }
 
-    fn backoff_hint(&self, error: &TransportError) -> Option<std::time::Duration> {
-        None
+    /// Called when a query succeeded. Returns the new range if it grew.
+    pub fn record_success(&self) -> Option<u64> {
+        if self.successes.fetch_add(1, Ordering::Relaxed) + 1 < Self::GROW_AFTER {
+            return None;
+        }
+        self.successes.store(0, Ordering::Relaxed);
+        let current = self.get();
+        if current >= self.max {
+            return None;
+        }
+        let grown = current.saturating_mul(2).min(self.max);
+        // Unless a concurrent query hit a limit meanwhile.
+        self.current
+            .compare_exchange(current, grown, Ordering::Relaxed, Ordering::Relaxed)
+            .ok()
+            .map(|_| grown)
     }
 }
 
 #[async_trait]
 pub trait EventProcessor: Send + Sync + 'static {
-    async fn process_events(&self, events: &[Log]) -> Result<(), eyre::Error>;
-    fn latest_processed_block(&self) -> Result<Option<u64>, eyre::Error>;
+    /// Processes the events of the chunk of blocks `blocks`, all of them, into `tree`.
+    /// The indexer commits everything written with its cursor, so a chunk is either
+    /// processed as a whole or not at all.
+    async fn process_events(
+        &self,
+        tree: &MerkleForest,
+        blocks: RangeInclusive<u64>,
+        events: &[Log],
+    ) -> Result<(), eyre::Error>;
+    fn latest_processed_block(&self, tree: &MerkleForest) -> Result<Option<u64>, eyre::Error>;
+    /// Reverts everything processed after `block`, which becomes the latest
+    /// processed block. Called when a reorg is detected.
+    fn rollback(&self, tree: &MerkleForest, block: u64) -> Result<(), eyre::Error>;
+}
+
+// Messages sent from the producer to the consumer.
+enum IndexerMessage {
+    // Events of the blocks [start, end].
+    Chunk(u64, u64, Vec<Log>),
+    // A reorg was detected and every block after this one has to be rolled back.
+    // The consumer notifies once done, so the producer can resume from there.
+    Reorg(u64, oneshot::Sender<()>),
 }
 
 pub struct Indexer<P: EventProcessor + Send + Sync + 'static> {
     name: String,
     provider: Arc<dyn Provider + Send + Sync>,
+    rpc_stats: Arc<RpcStats>,
     contract_address: Address,
-    block_range: u64,
+    // Adapted to the limits of the RPC, starting from the configured one.
+    block_range: BlockRange,
     parallel_queries: usize,
     max_queue_size: usize,
     starting_block: u64,
     poll_interval: u64,
     sync_mode: BlockNumberOrTag,
     event_processor: P,
+    // Where events are processed into. Also keeps the cursor and the block hashes needed
+    // to detect reorgs.
+    store: Arc<MerkleForest>,
 }
 
 impl<P: EventProcessor + Send + Sync + 'static> Indexer<P> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         rpc_url: Url,
         name: String,
         contract_address: Address,
         sync_mode: BlockNumberOrTag,
         event_processor: P,
+        store: Arc<MerkleForest>,
         block_range: u64,
+        retry: &RetryConfig,
     ) -> Result<Self, eyre::Report> {
         // TODO: Maybe pass the provider instead of the url to each type?
-        let max_retry: u32 = 100;
-        let backoff: u64 = 2000;
-        let cups: u64 = 100;
         let parallel_queries: usize = 5;
         let max_queue_size: usize = 100;
         let poll_interval: u64 = 3;
 
-        let latest_processed_block = event_processor.latest_processed_block()?;
+        let latest_processed_block = event_processor.latest_processed_block(&store)?;
+        let cursor = store.get_indexer_cursor(&name)?;
 
         println!("[{}] rpc_url: {:?}", name, rpc_url);
 
         println!(
-            "[{}] latest_processed_block: {:?}",
-            name, latest_processed_block
+            "[{}] latest_processed_block: {:?} cursor: {:?}",
+            name, latest_processed_block, cursor
         );
 
-        let starting_block = match latest_processed_block {
+        // Stores written by older versions have no cursor, and the processor may be ahead of
+        // it since it was written after the events of each chunk.
+        let starting_block = match latest_processed_block.max(cursor) {
             Some(block) => block + 1,
             None => 0,
         };
 
-        let retry_policy =
-            RetryBackoffLayer::new_with_policy(max_retry, backoff, cups, AlwaysRetryPolicy);
+        // Hashes of blocks that were fetched but never processed are stale.
+        store.delete_block_hashes(&name, starting_block, u64::MAX)?;
 
-        // Uses middleware to retry on rate limit errors.
-        let provider = Arc::new(
-            ProviderBuilder::new()
-                .connect_client(RpcClient::builder().layer(retry_policy).http(rpc_url)),
-        );
+        // Each indexer has its own provider, so that its errors are counted apart.
+        let rpc_stats = Arc::new(RpcStats::default());
+        let provider = rpc::connect(rpc_url, retry, Arc::clone(&rpc_stats));
 
         Ok(Self {
             name,
             provider,
+            rpc_stats,
             contract_address,
-            block_range,
+            block_range: BlockRange::new(block_range),
             parallel_queries,
             max_queue_size,
             poll_interval,
             starting_block,
             sync_mode,
             event_processor,
+            store,
         })
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn rpc_stats(&self) -> Arc<RpcStats> {
+        Arc::clone(&self.rpc_stats)
+    }
+
+    // Finalized blocks can't be reorged. Any other mode tracks block hashes to
+    // detect reorgs and roll back the affected blocks.
+    fn reorg_aware(&self) -> bool {
+        !matches!(self.sync_mode, BlockNumberOrTag::Finalized)
+    }
+
+    async fn block_hash(
+        &self,
+        provider: &Arc<dyn Provider + Send + Sync>,
+        block: u64,
+    ) -> Result<Option<B256>, Box<dyn Error + Send + Sync>> {
+        Ok(provider
+            .get_block_by_number(BlockNumberOrTag::Number(block))
+            .await?
+            .map(|block| block.header.hash))
+    }
+
+    // Compares the stored block hashes with the canonical chain. If any of them was
+    // reorged, returns the latest stored block that is still canonical, which is the
+    // block to roll back to. Blocks verified in previous rounds are ancestors of the
+    // latest stored one, so only that one and the ones stored after `verified_to` are checked.
+    async fn find_reorg(
+        &self,
+        provider: &Arc<dyn Provider + Send + Sync>,
+        verified_to: &mut u64,
+    ) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+        let stored = self.store.get_block_hashes(&self.name)?;
+        let mut to_check: Vec<&(u64, B256)> = stored
+            .iter()
+            .filter(|(block, _)| *block > *verified_to)
+            .chain(stored.last())
+            .collect();
+        to_check.dedup();
+
+        let mut reorged = None;
+        for (block, hash) in to_check {
+            if self.block_hash(provider, *block).await? != Some(*hash) {
+                reorged = Some(*block);
+                break;
+            }
+        }
+
+        let Some(reorged) = reorged else {
+            if let Some((block, _)) = stored.last() {
+                *verified_to = *block;
+            }
+            return Ok(None);
+        };
+
+        // Walk back until a block that is still canonical, that's the common ancestor.
+        for (block, hash) in stored.iter().rev().filter(|(block, _)| *block < reorged) {
+            if self.block_hash(provider, *block).await? == Some(*hash) {
+                *verified_to = *block;
+                return Ok(Some(*block));
+            }
+        }
+
+        Err(format!(
+            "[{}] Block {} was reorged and no common ancestor was found",
+            self.name, reorged
+        )
+        .into())
+    }
+
+    // Fetches the events of the blocks [start, end], in as many queries as the limits of the
+    // RPC require.
+    async fn get_logs(
+        &self,
+        provider: &Arc<dyn Provider + Send + Sync>,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<Log>, Box<dyn Error + Send + Sync>> {
+        let mut logs = Vec::new();
+        let mut from = start;
+        while from <= end {
+            let to = end.min(from.saturating_add(self.block_range.get() - 1));
+            let filter = Filter::new()
+                .from_block(from)
+                .to_block(to)
+                .address(self.contract_address);
+            match provider.get_logs(&filter).await {
+                Ok(events) => {
+                    logs.extend(events);
+                    from = to + 1;
+                    if let Some(range) = self.block_range.record_success() {
+                        println!("[{}] Block range increased to {}", self.name, range);
+                    }
+                }
+                // A single block can't be split any further.
+                Err(err) if to > from && is_range_limit_error(&err) => {
+                    let range = self.block_range.shrink(to - from + 1);
+                    println!(
+                        "[{}] Blocks [{}-{}] hit a limit of the RPC, block range decreased to {}: {}",
+                        self.name, from, to, range, err
+                    );
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(logs)
+    }
+
+    // Fetches events until `shutdown` is cancelled. Fetches in flight are dropped then,
+    // which is safe: the hashes stored for chunks that are never processed are deleted on
+    // the next start.
     async fn event_producer(
         &self,
-        tx: mpsc::Sender<(u64, u64, Vec<Log>)>,
+        tx: mpsc::Sender<IndexerMessage>,
+        shutdown: CancellationToken,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        tokio::select! {
+            // A send failing because the consumer stopped is not an error on shutdown.
+            biased;
+            _ = shutdown.cancelled() => {
+                println!("[{}] Stopped fetching events", self.name);
+                Ok(())
+            }
+            res = self.produce_events(tx) => res,
+        }
+    }
+
+    async fn produce_events(
+        &self,
+        tx: mpsc::Sender<IndexerMessage>,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
         let mut processed_to = self.starting_block.saturating_sub(1);
+        // Stored block hashes up to this block were already checked against the chain.
+        let mut verified_to = 0;
         let contract_address = self.contract_address;
 
         loop {
             let provider = Arc::clone(&self.provider);
-            let finalized_block = provider
+            let head_block = provider
                 .get_block_by_number(self.sync_mode)
                 .await?
-                .ok_or(eyre::eyre!("Finalized block is None"))
-                .unwrap()
+                .ok_or(eyre::eyre!("{} block is None", self.sync_mode))?
                 .header
                 .number;
-            println!(
-                "[{}] {} block: {:?}",
-                self.name, self.sync_mode, finalized_block
-            );
-            let remaining = finalized_block.saturating_sub(processed_to);
+            println!("[{}] {} block: {:?}", self.name, self.sync_mode, head_block);
+
+            // Blocks above `safe_block` might still be reorged.
+            let mut safe_block = head_block;
+            if self.reorg_aware() {
+                if let Some(ancestor) = self.find_reorg(&provider, &mut verified_to).await? {
+                    println!(
+                        "[{}] Reorg detected, rolling back to block {}",
+                        self.name, ancestor
+                    );
+                    let (done_tx, done_rx) = oneshot::channel();
+                    tx.send(IndexerMessage::Reorg(ancestor, done_tx)).await?;
+                    done_rx.await?;
+
+                    self.store
+                        .delete_block_hashes(&self.name, ancestor + 1, u64::MAX)?;
+                    processed_to = ancestor;
+                    continue;
+                }
+
+                let finalized = provider
+                    .get_block_by_number(BlockNumberOrTag::Finalized)
+                    .await?
+                    .ok_or(eyre::eyre!("Finalized block is None"))?
+                    .header;
+                safe_block = finalized.number;
+
+                // Keep the finalized block as the oldest hash. It can't be reorged so
+                // there is no need to go further back.
+                if finalized.number <= processed_to {
+                    self.store
+                        .put_block_hashes(&self.name, &[(finalized.number, finalized.hash)])?;
+                    self.store
+                        .delete_block_hashes(&self.name, 0, finalized.number)?;
+                }
+            }
+
+            let remaining = head_block.saturating_sub(processed_to);
             if remaining == 0 {
                 sleep(Duration::from_secs(self.poll_interval)).await;
                 continue;
             }
 
-            let chunk_starts: Vec<u64> = ((processed_to + 1)..=finalized_block)
-                .step_by(self.block_range as usize)
+            // Chunks are split further if the range shrinks while they are fetched.
+            let block_range = self.block_range.get();
+            let chunk_starts: Vec<u64> = ((processed_to + 1)..=head_block)
+                .step_by(block_range as usize)
                 .collect();
 
             stream::iter(
@@ -150,36 +376,59 @@ impl<P: EventProcessor + Send + Sync + 'static> Indexer<P> {
                 let tx = tx.clone();
                 let provider = Arc::clone(&provider);
                 async move {
-                    let chunk_end =
-                        std::cmp::min(chunk_start + self.block_range - 1, finalized_block);
+                    let chunk_end = std::cmp::min(chunk_start + block_range - 1, head_block);
 
                     println!(
                         "[{}] Fetching events for blocks [{:?}-{:?}] contract address: {:?}",
                         self.name, chunk_start, chunk_end, contract_address
                     );
-                    let events = provider
-                        .get_logs(
-                            &Filter::new()
-                                .from_block(chunk_start)
-                                .to_block(chunk_end)
-                                .address(contract_address),
+                    // The hash of the last block is fetched before the logs. If a reorg
+                    // happens in between, it's then an old hash, found on the next round.
+                    let end_hash = if chunk_end > safe_block {
+                        Some(
+                            self.block_hash(&provider, chunk_end)
+                                .await?
+                                .ok_or(eyre::eyre!("Block {} is None", chunk_end))?,
                         )
-                        .await?;
+                    } else {
+                        None
+                    };
+                    let events = self.get_logs(&provider, chunk_start, chunk_end).await?;
 
-                    tx.send((chunk_start, chunk_end, events)).await?;
+                    // Keep the hashes of the blocks that might be reorged. The ones with
+                    // events and the last one of the chunk.
+                    if let Some(end_hash) = end_hash {
+                        let mut hashes: BTreeMap<u64, B256> = events
+                            .iter()
+                            .filter_map(|log| Some((log.block_number?, log.block_hash?)))
+                            .filter(|(block, _)| *block > safe_block)
+                            .collect();
+                        hashes.insert(chunk_end, end_hash);
+                        self.store.put_block_hashes(
+                            &self.name,
+                            &hashes.into_iter().collect::<Vec<_>>(),
+                        )?;
+                    }
+
+                    tx.send(IndexerMessage::Chunk(chunk_start, chunk_end, events))
+                        .await?;
                     Ok(())
                 }
             })
             .await?;
 
-            // All blocks up to `finalized_block` have been queued for processing.
-            processed_to = finalized_block;
+            // All blocks up to `head_block` have been queued for processing.
+            processed_to = head_block;
         }
     }
 
+    // Processes chunks in order until the producer stops. Once `shutdown` is cancelled, no
+    // more chunks are received and the ones already queued are processed, as long as they
+    // are contiguous.
     async fn event_consumer(
         &self,
-        mut rx: mpsc::Receiver<(u64, u64, Vec<Log>)>,
+        mut rx: mpsc::Receiver<IndexerMessage>,
+        shutdown: CancellationToken,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
         // `last_processed` tracks the last block that has been fully processed. We
         // initialise it to one block before `starting_block` so that the next
@@ -189,8 +438,38 @@ impl<P: EventProcessor + Send + Sync + 'static> Indexer<P> {
         // Buffer keyed by `chunk_start` so we can easily fetch the contiguous
         // range that must come next (i.e. `last_processed + 1`).
         let mut buffer: BTreeMap<u64, (u64, Vec<Log>)> = BTreeMap::new();
+        let mut closed = false;
         // TODO: confusion between events (whats recevied) and ev (what i will process)
-        while let Some((chunk_start, chunk_end, events)) = rx.recv().await {
+        loop {
+            let message = tokio::select! {
+                _ = shutdown.cancelled(), if !closed => {
+                    rx.close();
+                    closed = true;
+                    continue;
+                }
+                message = rx.recv() => message,
+            };
+            let Some(message) = message else {
+                break;
+            };
+            let (chunk_start, chunk_end, events) = match message {
+                IndexerMessage::Chunk(chunk_start, chunk_end, events) => {
+                    (chunk_start, chunk_end, events)
+                }
+                IndexerMessage::Reorg(ancestor, done) => {
+                    // Every chunk fetched before the reorg was sent before this message,
+                    // so they are all processed by now.
+                    buffer.clear();
+                    let tx = self.store.transaction()?;
+                    self.event_processor.rollback(&tx, ancestor)?;
+                    tx.put_indexer_cursor(&self.name, ancestor)?;
+                    tx.commit()?;
+                    last_processed = ancestor;
+                    let _ = done.send(());
+                    continue;
+                }
+            };
+
             // Store the range in the buffer.  The key is the `chunk_start` so we
             // can determine when the next contiguous segment is ready.
             buffer.insert(chunk_start, (chunk_end, events));
@@ -206,45 +485,73 @@ impl<P: EventProcessor + Send + Sync + 'static> Indexer<P> {
                     self.contract_address,
                     ev.len()
                 );
-                // TODO: Maybe add the start and end block chunks.
-                self.event_processor.process_events(&ev).await?;
+                // Everything written for the chunk is committed at once, with the cursor.
+                // Also for chunks without events, so that restarts resume from here
+                // instead of from the last event.
+                let tx = self.store.transaction()?;
+                self.event_processor
+                    .process_events(&tx, (last_processed + 1)..=end, &ev)
+                    .await?;
+                tx.put_indexer_cursor(&self.name, end)?;
+                tx.commit()?;
                 // Update the cursor so that the next expected start is directly
                 // after the `end` we just processed.
                 last_processed = end;
             }
         }
+
+        if !buffer.is_empty() {
+            println!(
+                "[{}] Dropped {} chunks after block {}, they will be fetched again",
+                self.name,
+                buffer.len(),
+                last_processed
+            );
+        }
         Ok(())
     }
 
-    // Runs the indexer in the background. The function consumes `self` so it can be
-    // moved into an `Arc`, allowing the spawned tasks to hold an owned clone that
-    // lives for the entire `'static` lifetime required by `tokio::spawn`.
-    pub async fn run(self) -> Result<(), Box<dyn Error + Send + Sync>> {
-        // TODO: handle graceful shutdown.
+    // Runs the indexer in the background until `shutdown` is cancelled. The function
+    // consumes `self` so it can be moved into an `Arc`, allowing the spawned tasks to hold
+    // an owned clone that lives for the entire `'static` lifetime required by `tokio::spawn`.
+    pub async fn run(
+        self,
+        shutdown: CancellationToken,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
         // Bounded channel to avoid un-controlled memory growth and to provide
         // back-pressure between producer and consumer.
-        let (tx, rx) = mpsc::channel::<(u64, u64, Vec<Log>)>(self.max_queue_size);
+        let (tx, rx) = mpsc::channel::<IndexerMessage>(self.max_queue_size);
+        // Cancelled on shutdown, or if the producer stops on its own.
+        let shutdown = shutdown.child_token();
 
         // Move `self` into an `Arc` so we can share it between the two async tasks we are about to spawn.
         let this = Arc::new(self);
 
-        // Spawn producer.
+        // Spawn producer. It owns the only sender, so the consumer stops once it's done.
         let producer_handle = {
             let idx = Arc::clone(&this);
-            let tx = tx.clone();
-            task::spawn(async move { idx.event_producer(tx).await })
+            let shutdown = shutdown.clone();
+            task::spawn(async move { idx.event_producer(tx, shutdown).await })
         };
 
         // Spawn consumer.
         let consumer_handle = {
             let idx = Arc::clone(&this);
-            task::spawn(async move { idx.event_consumer(rx).await })
+            let shutdown = shutdown.clone();
+            task::spawn(async move { idx.event_consumer(rx, shutdown).await })
         };
 
-        // Await both tasks.
-        producer_handle.await??;
-        consumer_handle.await??;
+        let producer_result = producer_handle.await;
+        shutdown.cancel();
+        let consumer_result = consumer_handle.await;
+
+        // Whatever was processed is kept, even if the indexer failed.
+        this.store.flush()?;
+        println!("[{}] Stopped, RPC errors: {:?}", this.name, this.rpc_stats);
 
+        // If the consumer failed, the producer only failed to send to it.
+        consumer_result??;
+        producer_result??;
         Ok(())
     }
 }
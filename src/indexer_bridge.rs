@@ -1,59 +1,147 @@
-use crate::contracts::PolygonZkEVMBridgeV2::{BridgeEvent, ClaimEvent, NewWrappedToken};
+use crate::contracts::BridgeL2SovereignChain::{
+    MigrateLegacyToken, RemoveLegacySovereignTokenAddress, SetBridgeManager,
+    SetSovereignTokenAddress, SetSovereignWETHAddress, UnsetClaim as UnsetClaimEvent,
+};
+use crate::contracts::PolygonZkEVMBridge::ClaimEvent as ClaimEventV1;
+use crate::contracts::PolygonZkEVMBridgeV2::{
+    BridgeEvent, ClaimEvent, NewWrappedToken, PolygonZkEVMBridgeV2Instance,
+};
 use crate::indexer::EventProcessor;
-use crate::leaf_bridge::LeafBridge;
+use crate::leaf_bridge::{
+    BridgeEvents, RawBridgeEvent, RawClaimEvent, SovereignEvent, UnsetClaim, WrappedToken,
+};
 use crate::merkle_tree::{MerkleForest, TreeType};
+use crate::rpc::{self, ProviderStack};
+use alloy::eips::BlockId;
 use alloy::rpc::types::Log;
 use alloy::sol_types::SolEvent;
 use async_trait::async_trait;
 use eyre::Result;
-use std::sync::Arc;
+use std::ops::RangeInclusive;
 
 pub struct BridgeEventProcessor {
-    pub tree: Arc<MerkleForest>,
     pub aggchain_id: u32,
+    pub bridge: PolygonZkEVMBridgeV2Instance<ProviderStack>,
+}
+
+impl BridgeEventProcessor {
+    // Compares the Local Exit Root with the one of the bridge at `block`. Historical state
+    // may not be available in the RPC node, then the check is skipped.
+    async fn check_local_exit_root(&self, tree: &MerkleForest, block: u64) -> Result<()> {
+        let local = tree.get_root(&TreeType::LocalExitTree(self.aggchain_id))?;
+        let onchain = match self
+            .bridge
+            .getRoot()
+            .block(BlockId::number(block))
+            .call()
+            .await
+        {
+            Ok(root) => root,
+            // Nodes that prune old state can't answer for past blocks, only then the
+            // check is skipped. Other errors, already retried by the RPC client, fail the
+            // chunk so that it's not committed unchecked.
+            Err(alloy::contract::Error::TransportError(e)) if rpc::is_state_unavailable(&e) => {
+                println!(
+                    "Skipped the check of the onchain Local Exit Root of aggchain {} at block {}, the state is not available: {}",
+                    self.aggchain_id, block, e
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                return Err(eyre::eyre!(
+                    "Could not get the onchain Local Exit Root of aggchain {} at block {}: {}",
+                    self.aggchain_id,
+                    block,
+                    e
+                ));
+            }
+        };
+        if local != Some(onchain) {
+            return Err(eyre::eyre!(
+                "Local Exit Root mismatch of aggchain {} at block {}: local {:?} != onchain {}",
+                self.aggchain_id,
+                block,
+                local,
+                onchain
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl EventProcessor for BridgeEventProcessor {
-    fn latest_processed_block(&self) -> Result<Option<u64>, eyre::Error> {
-        self.tree
-            .get_latest_block(TreeType::LocalExitTree(self.aggchain_id))
+    fn latest_processed_block(&self, tree: &MerkleForest) -> Result<Option<u64>, eyre::Error> {
+        tree.get_latest_block(TreeType::LocalExitTree(self.aggchain_id))
+    }
+
+    fn rollback(&self, tree: &MerkleForest, block: u64) -> Result<(), eyre::Error> {
+        tree.rollback_to_block(TreeType::LocalExitTree(self.aggchain_id), block)
     }
 
-    async fn process_events(&self, events: &[Log]) -> Result<(), eyre::Error> {
+    async fn process_events(
+        &self,
+        tree: &MerkleForest,
+        blocks: RangeInclusive<u64>,
+        events: &[Log],
+    ) -> Result<(), eyre::Error> {
+        // All the logs of the chunk are inserted at once, in a single batch.
+        let mut bridge_events = BridgeEvents::default();
         for event in events {
             match event.topic0() {
                 Some(&BridgeEvent::SIGNATURE_HASH) => {
                     let event = event.log_decode::<BridgeEvent>()?;
-                    let leaf_bridge = LeafBridge::new(event.data().clone());
-
-                    // TODO: Ensure the block number is updated only when there are no more events from that block.
-                    // TODO: Process in batches instead of one by one.
-                    self.tree.append_events(
-                        self.aggchain_id,
-                        &[leaf_bridge],
-                        event
-                            .block_number
-                            .ok_or(eyre::eyre!("Block number is None"))?,
-                    )?;
+                    bridge_events
+                        .deposits
+                        .push(RawBridgeEvent::from_log(&event)?);
                 }
                 Some(&ClaimEvent::SIGNATURE_HASH) => {
                     let event = event.log_decode::<ClaimEvent>()?;
-                    // TODO:
+                    bridge_events.claims.push(RawClaimEvent::from_log(&event)?);
+                }
+                // Emitted by the bridge before the upgrade to V2.
+                Some(&ClaimEventV1::SIGNATURE_HASH) => {
+                    let event = event.log_decode::<ClaimEventV1>()?;
+                    bridge_events
+                        .claims
+                        .push(RawClaimEvent::from_log_v1(&event, self.aggchain_id)?);
                 }
                 Some(&NewWrappedToken::SIGNATURE_HASH) => {
                     let event = event.log_decode::<NewWrappedToken>()?;
-                    // TODO:
+                    bridge_events
+                        .wrapped_tokens
+                        .push(WrappedToken::from_log(&event)?);
+                }
+                // Only emitted by sovereign chains (BridgeL2SovereignChain).
+                Some(&SetSovereignTokenAddress::SIGNATURE_HASH)
+                | Some(&MigrateLegacyToken::SIGNATURE_HASH)
+                | Some(&RemoveLegacySovereignTokenAddress::SIGNATURE_HASH)
+                | Some(&SetSovereignWETHAddress::SIGNATURE_HASH)
+                | Some(&SetBridgeManager::SIGNATURE_HASH) => {
+                    bridge_events
+                        .sovereign_events
+                        .push(SovereignEvent::from_log(event)?);
+                }
+                Some(&UnsetClaimEvent::SIGNATURE_HASH) => {
+                    let event = event.log_decode::<UnsetClaimEvent>()?;
+                    bridge_events
+                        .unset_claims
+                        .push(UnsetClaim::from_log(&event)?);
                 }
 
                 _ => {}
             }
         }
+
+        if !bridge_events.is_empty() {
+            tree.append_bridge_events(self.aggchain_id, &bridge_events)?;
+        }
+        // The whole chunk is processed, so the tree is now as it was at its end. Recent
+        // state is more likely to be available than the one at the last deposit.
+        if !bridge_events.deposits.is_empty() {
+            self.check_local_exit_root(tree, *blocks.end()).await?;
+        }
         // TODO: Sovereign chain events are missing.
-        // -SetSovereignTokenAddress
-        // -MigrateLegacyToken
-        // -RemoveLegacySovereignTokenAddress
-        // -SetSovereignWETHAddress
         // -UpdatedClaimedGlobalIndexHashChain
         // -UpdatedUnsetGlobalIndexHashChain
         // -SetInitialLocalBalanceTreeAmount
@@ -7,23 +7,93 @@ use alloy::providers::Provider;
 use alloy::rpc::types::{BlockNumberOrTag, Log};
 use alloy::sol_types::SolEvent;
 use async_trait::async_trait;
-use eyre::Result;
+use eyre::{Result, eyre};
 use futures::stream::{self, StreamExt, TryStreamExt};
 use std::collections::HashMap;
+use std::ops::RangeInclusive;
 use std::sync::Arc;
 
 pub struct L1InfoTreeEventProcessor {
-    pub tree: Arc<MerkleForest>,
     pub provider: Arc<dyn Provider>,
+    // Indexers of the Main Exit Tree and the Rollup Exit Tree, whose cursors tell up to
+    // which block the exit roots of the leaves can be checked.
+    pub mainnet_indexer: String,
+    pub rollup_manager_indexer: String,
+}
+
+impl L1InfoTreeEventProcessor {
+    // Compares the trees with the roots emitted along `leaf`, once it is appended. A
+    // difference means that they diverged from the contracts, so indexing can't go on.
+    fn check_leaf(&self, tree: &MerkleForest, leaf: &LeafL1InfoTree) -> Result<()> {
+        let block = leaf.log.block_number.ok_or(eyre!("Block number is None"))?;
+
+        // Only emitted since UpdateL1InfoTreeV2.
+        if let Some(onchain_root) = leaf.onchain_info_root() {
+            let local_root = tree.get_root(&TreeType::L1InfoTree)?;
+            if local_root != Some(onchain_root) {
+                return Err(eyre!(
+                    "L1 Info Tree root mismatch at block {}: local {:?} != onchain {}",
+                    block,
+                    local_root,
+                    onchain_root
+                ));
+            }
+        }
+        if let Some(onchain_count) = leaf.leaf_count() {
+            let local_count = tree.get_leaf_count(&TreeType::L1InfoTree)?;
+            if local_count != onchain_count {
+                return Err(eyre!(
+                    "L1 Info Tree leaf count mismatch at block {}: local {} != onchain {}",
+                    block,
+                    local_count,
+                    onchain_count
+                ));
+            }
+        }
+
+        // The exit roots must be ones the Main Exit Tree and the Rollup Exit Tree had, as
+        // long as they are indexed up to this block. The latest block of the trees only
+        // moves with their events, unlike the cursors of their indexers.
+        let mainnet_indexed = tree
+            .get_indexer_cursor(&self.mainnet_indexer)?
+            .is_some_and(|b| b >= block);
+        if mainnet_indexed && tree.get_exit_root_leaf_count(0, &leaf.mer())?.is_none() {
+            return Err(eyre!(
+                "Mainnet exit root {} of the L1 Info Tree leaf at block {} is not a root of the Main Exit Tree",
+                leaf.mer(),
+                block
+            ));
+        }
+        let rollups_indexed = tree
+            .get_indexer_cursor(&self.rollup_manager_indexer)?
+            .is_some_and(|b| b >= block);
+        if rollups_indexed && !tree.has_rollup_exit_root(&leaf.rer())? {
+            return Err(eyre!(
+                "Rollup exit root {} of the L1 Info Tree leaf at block {} is not a root of the Rollup Exit Tree",
+                leaf.rer(),
+                block
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl EventProcessor for L1InfoTreeEventProcessor {
-    fn latest_processed_block(&self) -> Result<Option<u64>, eyre::Error> {
-        self.tree.get_latest_block(TreeType::L1InfoTree)
+    fn latest_processed_block(&self, tree: &MerkleForest) -> Result<Option<u64>, eyre::Error> {
+        tree.get_latest_block(TreeType::L1InfoTree)
+    }
+
+    fn rollback(&self, tree: &MerkleForest, block: u64) -> Result<(), eyre::Error> {
+        tree.rollback_to_block(TreeType::L1InfoTree, block)
     }
 
-    async fn process_events(&self, events: &[Log]) -> Result<(), eyre::Error> {
+    async fn process_events(
+        &self,
+        tree: &MerkleForest,
+        _blocks: RangeInclusive<u64>,
+        events: &[Log],
+    ) -> Result<(), eyre::Error> {
         let mut v1_events: Vec<Log<UpdateL1InfoTree>> = Vec::new();
         let mut v2_events_by_tx: HashMap<B256, Log<UpdateL1InfoTreeV2>> = HashMap::new();
 
@@ -99,12 +169,8 @@ impl EventProcessor for L1InfoTreeEventProcessor {
 
         // Now that we have constructed all the leaves, we can process them.
         for leaf in leaves {
-            self.tree.append_l1info_leaf(
-                &leaf.info_root_leaf(),
-                leaf.log
-                    .block_number
-                    .ok_or(eyre::eyre!("Block number is None"))?,
-            )?;
+            tree.append_l1info_leaf(&leaf.data()?)?;
+            self.check_leaf(tree, &leaf)?;
         }
         Ok(())
     }
@@ -1,59 +1,242 @@
 use crate::contracts::PolygonRollupManager::AddExistingRollup;
+use crate::contracts::PolygonRollupManager::AddNewRollupType;
+use crate::contracts::PolygonRollupManager::CreateNewAggchain;
 use crate::contracts::PolygonRollupManager::CreateNewRollup;
+use crate::contracts::PolygonRollupManager::PolygonRollupManagerInstance;
+use crate::contracts::PolygonRollupManager::UpdateRollup;
 use crate::contracts::PolygonRollupManager::VerifyBatchesTrustedAggregator;
 use crate::contracts::PolygonRollupManager::VerifyPessimisticStateTransition;
 use crate::contracts::PolygonRollupManagerOld::AddExistingRollup as AddExistingRollupOld;
+use crate::contracts::PolygonRollupManagerOld::AddNewRollupType as AddNewRollupTypeOld;
 use crate::indexer::EventProcessor;
 use crate::merkle_tree::MerkleForest;
 use crate::merkle_tree::TreeType;
-use alloy::primitives::B256;
+use crate::rollup::{RollupInfo, RollupType};
+use crate::rpc::{self, ProviderStack};
+use alloy::eips::BlockId;
 use alloy::rpc::types::Log;
 use alloy::sol_types::SolEvent;
 use async_trait::async_trait;
 use eyre::Result;
-use std::sync::Arc;
+use std::ops::RangeInclusive;
 
 pub struct RollupManagerEventProcessor {
-    pub tree: Arc<MerkleForest>,
+    pub rollup_manager: PolygonRollupManagerInstance<ProviderStack>,
+}
+
+impl RollupManagerEventProcessor {
+    // Compares the Rollup Exit Root with the one of the RollupManager at `block`. Historical
+    // state may not be available in the RPC node, then the check is skipped.
+    async fn check_rollup_exit_root(&self, tree: &MerkleForest, block: u64) -> Result<()> {
+        let local = tree
+            .get_root(&TreeType::RollupExitTree)?
+            .ok_or(eyre::eyre!("Rollup Exit Tree is empty"))?;
+        let onchain = match self
+            .rollup_manager
+            .getRollupExitRoot()
+            .block(BlockId::number(block))
+            .call()
+            .await
+        {
+            Ok(root) => root,
+            // Nodes that prune old state can't answer for past blocks, only then the
+            // check is skipped. Other errors, already retried by the RPC client, fail the
+            // chunk so that it's not committed unchecked.
+            Err(alloy::contract::Error::TransportError(e)) if rpc::is_state_unavailable(&e) => {
+                println!(
+                    "Skipped the check of the onchain Rollup Exit Root at block {}, the state is not available: {}",
+                    block, e
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                return Err(eyre::eyre!(
+                    "Could not get the onchain Rollup Exit Root at block {}: {}",
+                    block,
+                    e
+                ));
+            }
+        };
+        if local != onchain {
+            return Err(eyre::eyre!(
+                "Rollup Exit Root mismatch at block {}: local {} != onchain {}",
+                block,
+                local,
+                onchain
+            ));
+        }
+        Ok(())
+    }
+
+    fn rollup_type(&self, tree: &MerkleForest, rollup_type_id: u32) -> Result<RollupType> {
+        tree.get_rollup_type(rollup_type_id)?
+            .ok_or_else(|| eyre::eyre!("Unknown rollup type {}", rollup_type_id))
+    }
 }
 
 #[async_trait]
 impl EventProcessor for RollupManagerEventProcessor {
-    fn latest_processed_block(&self) -> Result<Option<u64>, eyre::Error> {
-        self.tree.get_latest_block(TreeType::RollupExitTree)
+    fn latest_processed_block(&self, tree: &MerkleForest) -> Result<Option<u64>, eyre::Error> {
+        tree.get_latest_block(TreeType::RollupExitTree)
+    }
+
+    fn rollback(&self, tree: &MerkleForest, block: u64) -> Result<(), eyre::Error> {
+        tree.rollback_to_block(TreeType::RollupExitTree, block)
     }
 
     // TODO: Maybe not the most efficient thing. The exit root keep changing constantly
     // so when indexing, you are continuisly overriding the prev leaf.
-    async fn process_events(&self, events: &[Log]) -> Result<(), eyre::Error> {
+    async fn process_events(
+        &self,
+        tree: &MerkleForest,
+        blocks: RangeInclusive<u64>,
+        events: &[Log],
+    ) -> Result<(), eyre::Error> {
+        // Whether a rollup leaf was updated, to cross-check the resulting root.
+        let mut updated = false;
         for event in events {
+            let block_number = event
+                .block_number
+                .ok_or(eyre::eyre!("Block number not found"))?;
+            let log_index = event.log_index.ok_or(eyre::eyre!("Log index not found"))?;
             match event.topic0() {
+                Some(&AddNewRollupType::SIGNATURE_HASH) => {
+                    let event = event.log_decode::<AddNewRollupType>()?;
+                    tree.put_rollup_type(
+                        &RollupType {
+                            rollup_type_id: event.data().rollupTypeID,
+                            fork_id: event.data().forkID,
+                            verifier_type: event.data().rollupVerifierType,
+                        },
+                        block_number,
+                        log_index,
+                    )?;
+                }
+                Some(&AddNewRollupTypeOld::SIGNATURE_HASH) => {
+                    let event = event.log_decode::<AddNewRollupTypeOld>()?;
+                    tree.put_rollup_type(
+                        &RollupType {
+                            rollup_type_id: event.data().rollupTypeID,
+                            fork_id: event.data().forkID,
+                            verifier_type: event.data().rollupCompatibilityID,
+                        },
+                        block_number,
+                        log_index,
+                    )?;
+                }
                 Some(&CreateNewRollup::SIGNATURE_HASH) => {
                     let event = event.log_decode::<CreateNewRollup>()?;
-                    // TODO:
+                    let rollup_type = self.rollup_type(tree, event.data().rollupTypeID)?;
+                    tree.put_rollup(
+                        &RollupInfo {
+                            rollup_id: event.data().rollupID,
+                            chain_id: event.data().chainID,
+                            rollup_address: event.data().rollupAddress,
+                            fork_id: rollup_type.fork_id,
+                            rollup_type_id: rollup_type.rollup_type_id,
+                            verifier_type: rollup_type.verifier_type,
+                            block_number,
+                        },
+                        block_number,
+                        log_index,
+                    )?;
+                }
+                Some(&CreateNewAggchain::SIGNATURE_HASH) => {
+                    let event = event.log_decode::<CreateNewAggchain>()?;
+                    let rollup_type = self.rollup_type(tree, event.data().rollupTypeID)?;
+                    tree.put_rollup(
+                        &RollupInfo {
+                            rollup_id: event.data().rollupID,
+                            chain_id: event.data().chainID,
+                            rollup_address: event.data().rollupAddress,
+                            fork_id: rollup_type.fork_id,
+                            rollup_type_id: rollup_type.rollup_type_id,
+                            verifier_type: event.data().rollupVerifierType,
+                            block_number,
+                        },
+                        block_number,
+                        log_index,
+                    )?;
                 }
                 Some(&AddExistingRollup::SIGNATURE_HASH) => {
                     let event = event.log_decode::<AddExistingRollup>()?;
-                    // TODO:
+                    tree.put_rollup(
+                        &RollupInfo {
+                            rollup_id: event.data().rollupID,
+                            chain_id: event.data().chainID,
+                            rollup_address: event.data().rollupAddress,
+                            fork_id: event.data().forkID,
+                            rollup_type_id: 0,
+                            verifier_type: event.data().rollupVerifierType,
+                            block_number,
+                        },
+                        block_number,
+                        log_index,
+                    )?;
                 }
                 Some(&AddExistingRollupOld::SIGNATURE_HASH) => {
                     let event = event.log_decode::<AddExistingRollupOld>()?;
-                    // TODO:
+                    tree.put_rollup(
+                        &RollupInfo {
+                            rollup_id: event.data().rollupID,
+                            chain_id: event.data().chainID,
+                            rollup_address: event.data().rollupAddress,
+                            fork_id: event.data().forkID,
+                            rollup_type_id: 0,
+                            verifier_type: event.data().rollupCompatibilityID,
+                            block_number,
+                        },
+                        block_number,
+                        log_index,
+                    )?;
+                }
+                Some(&UpdateRollup::SIGNATURE_HASH) => {
+                    let event = event.log_decode::<UpdateRollup>()?;
+                    let rollup_id = event.data().rollupID;
+                    let rollup_type = self.rollup_type(tree, event.data().newRollupTypeID)?;
+                    let rollup = tree
+                        .get_rollup(rollup_id)?
+                        .ok_or_else(|| eyre::eyre!("Unknown rollup {}", rollup_id))?;
+                    tree.put_rollup(
+                        &RollupInfo {
+                            fork_id: rollup_type.fork_id,
+                            rollup_type_id: rollup_type.rollup_type_id,
+                            verifier_type: rollup_type.verifier_type,
+                            ..rollup
+                        },
+                        block_number,
+                        log_index,
+                    )?;
                 }
                 Some(&VerifyBatchesTrustedAggregator::SIGNATURE_HASH) => {
                     let event = event.log_decode::<VerifyBatchesTrustedAggregator>()?;
-                    self.tree.set_rollup_leaf(
+                    tree.set_rollup_leaf(
                         event.data().rollupID,
                         &event.data().exitRoot,
-                        event
-                            .block_number
-                            .ok_or(eyre::eyre!("Block number not found"))?,
+                        block_number,
+                        log_index,
+                    )?;
+                    updated = true;
+                }
+                // Aggchains settled with pessimistic proofs.
+                Some(&VerifyPessimisticStateTransition::SIGNATURE_HASH) => {
+                    let event = event.log_decode::<VerifyPessimisticStateTransition>()?;
+                    tree.set_rollup_leaf(
+                        event.data().rollupID,
+                        &event.data().newLocalExitRoot,
+                        block_number,
+                        log_index,
                     )?;
+                    updated = true;
                 }
-                Some(&VerifyPessimisticStateTransition::SIGNATURE_HASH) => {}
                 _ => {}
             }
         }
+
+        // The whole chunk is processed, so the tree is now as it was at its end.
+        if updated {
+            self.check_rollup_exit_root(tree, *blocks.end()).await?;
+        }
         Ok(())
     }
 }
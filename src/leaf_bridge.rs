@@ -1,3 +1,9 @@
+use crate::claim_proof::global_index;
+use crate::contracts::BridgeL2SovereignChain::{
+    MigrateLegacyToken, RemoveLegacySovereignTokenAddress, SetBridgeManager,
+    SetSovereignTokenAddress, SetSovereignWETHAddress, UnsetClaim as UnsetClaimEvent,
+};
+use crate::contracts::PolygonZkEVMBridge::ClaimEvent as ClaimEventV1;
 use crate::contracts::PolygonZkEVMBridgeV2::{
     BridgeEvent, ClaimEvent, EmergencyStateActivated, EmergencyStateDeactivated, Initialized,
     NewWrappedToken,
@@ -5,6 +11,9 @@ use crate::contracts::PolygonZkEVMBridgeV2::{
 use alloy::primitives::B256;
 use alloy::primitives::Uint;
 use alloy::primitives::keccak256;
+use alloy::primitives::{Address, Bytes, U256};
+use alloy::rpc::types::Log;
+use alloy::sol_types::SolEvent;
 
 #[derive(Clone)]
 pub struct LeafBridge {
@@ -30,6 +39,583 @@ impl LeafBridge {
         buf[49..81].copy_from_slice(&amount_be);
         buf[81..113].copy_from_slice(metadata_hash.as_slice());
 
-        keccak256(&buf)
+        keccak256(buf)
+    }
+}
+
+// A BridgeEvent as it was emitted, with the location of its log. This is what is kept
+// of every deposit, so that it can be looked up without an RPC node.
+#[derive(Clone)]
+pub struct RawBridgeEvent {
+    pub bridge_event: BridgeEvent,
+    pub tx_hash: B256,
+    pub block_number: u64,
+    pub log_index: u64,
+}
+
+impl RawBridgeEvent {
+    const HEADER_LEN: usize = 133;
+
+    pub fn from_log(log: &Log<BridgeEvent>) -> eyre::Result<Self> {
+        Ok(Self {
+            bridge_event: log.data().clone(),
+            tx_hash: log
+                .transaction_hash
+                .ok_or(eyre::eyre!("Transaction hash is None"))?,
+            block_number: log
+                .block_number
+                .ok_or(eyre::eyre!("Block number is None"))?,
+            log_index: log.log_index.ok_or(eyre::eyre!("Log index is None"))?,
+        })
+    }
+
+    pub fn leaf(&self) -> LeafBridge {
+        LeafBridge::new(self.bridge_event.clone())
+    }
+
+    // Encoding: leafType | originNetwork BE | originAddress | destinationNetwork BE |
+    // destinationAddress | amount BE | depositCount BE | tx_hash | block_number BE |
+    // log_index BE | metadata. Only the metadata has a variable length, so it goes last.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let e = &self.bridge_event;
+        let mut buf = Vec::with_capacity(Self::HEADER_LEN + e.metadata.len());
+        buf.push(e.leafType);
+        buf.extend_from_slice(&e.originNetwork.to_be_bytes());
+        buf.extend_from_slice(e.originAddress.as_slice());
+        buf.extend_from_slice(&e.destinationNetwork.to_be_bytes());
+        buf.extend_from_slice(e.destinationAddress.as_slice());
+        buf.extend_from_slice(&e.amount.to_be_bytes::<32>());
+        buf.extend_from_slice(&e.depositCount.to_be_bytes());
+        buf.extend_from_slice(self.tx_hash.as_slice());
+        buf.extend_from_slice(&self.block_number.to_be_bytes());
+        buf.extend_from_slice(&self.log_index.to_be_bytes());
+        buf.extend_from_slice(&e.metadata);
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> eyre::Result<Self> {
+        if bytes.len() < Self::HEADER_LEN {
+            return Err(eyre::eyre!(
+                "Invalid raw bridge event length {} < {}",
+                bytes.len(),
+                Self::HEADER_LEN
+            ));
+        }
+        Ok(Self {
+            bridge_event: BridgeEvent {
+                leafType: bytes[0],
+                originNetwork: u32::from_be_bytes(bytes[1..5].try_into()?),
+                originAddress: Address::from_slice(&bytes[5..25]),
+                destinationNetwork: u32::from_be_bytes(bytes[25..29].try_into()?),
+                destinationAddress: Address::from_slice(&bytes[29..49]),
+                amount: U256::from_be_slice(&bytes[49..81]),
+                depositCount: u32::from_be_bytes(bytes[81..85].try_into()?),
+                metadata: Bytes::copy_from_slice(&bytes[Self::HEADER_LEN..]),
+            },
+            tx_hash: B256::from_slice(&bytes[85..117]),
+            block_number: u64::from_be_bytes(bytes[117..125].try_into()?),
+            log_index: u64::from_be_bytes(bytes[125..133].try_into()?),
+        })
+    }
+}
+
+// A ClaimEvent as it was emitted, with the location of its log.
+#[derive(Clone)]
+pub struct RawClaimEvent {
+    pub global_index: U256,
+    pub origin_network: u32,
+    pub origin_address: Address,
+    pub destination_address: Address,
+    pub amount: U256,
+    pub tx_hash: B256,
+    pub block_number: u64,
+    pub log_index: u64,
+}
+
+impl RawClaimEvent {
+    const ENCODED_LEN: usize = 156;
+
+    pub fn from_log(log: &Log<ClaimEvent>) -> eyre::Result<Self> {
+        let event = log.data();
+        Self::new(
+            log,
+            event.globalIndex,
+            event.originNetwork,
+            event.originAddress,
+            event.destinationAddress,
+            event.amount,
+        )
+    }
+
+    // The first version of the bridge only connected mainnet with one rollup, so the
+    // claim event just had the deposit count. A claim in mainnet (`aggchain_id` 0) is
+    // always for a deposit of rollup 1, and the other way around.
+    pub fn from_log_v1(log: &Log<ClaimEventV1>, aggchain_id: u32) -> eyre::Result<Self> {
+        let event = log.data();
+        let source_network = if aggchain_id == 0 { 1 } else { 0 };
+        Self::new(
+            log,
+            global_index(source_network, event.index),
+            event.originNetwork,
+            event.originAddress,
+            event.destinationAddress,
+            event.amount,
+        )
+    }
+
+    fn new<T>(
+        log: &Log<T>,
+        global_index: U256,
+        origin_network: u32,
+        origin_address: Address,
+        destination_address: Address,
+        amount: U256,
+    ) -> eyre::Result<Self> {
+        Ok(Self {
+            global_index,
+            origin_network,
+            origin_address,
+            destination_address,
+            amount,
+            tx_hash: log
+                .transaction_hash
+                .ok_or(eyre::eyre!("Transaction hash is None"))?,
+            block_number: log
+                .block_number
+                .ok_or(eyre::eyre!("Block number is None"))?,
+            log_index: log.log_index.ok_or(eyre::eyre!("Log index is None"))?,
+        })
+    }
+
+    // Encoding: global_index BE | origin_network BE | origin_address | destination_address |
+    // amount BE | tx_hash | block_number BE | log_index BE
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut buf = [0u8; Self::ENCODED_LEN];
+        buf[0..32].copy_from_slice(&self.global_index.to_be_bytes::<32>());
+        buf[32..36].copy_from_slice(&self.origin_network.to_be_bytes());
+        buf[36..56].copy_from_slice(self.origin_address.as_slice());
+        buf[56..76].copy_from_slice(self.destination_address.as_slice());
+        buf[76..108].copy_from_slice(&self.amount.to_be_bytes::<32>());
+        buf[108..140].copy_from_slice(self.tx_hash.as_slice());
+        buf[140..148].copy_from_slice(&self.block_number.to_be_bytes());
+        buf[148..156].copy_from_slice(&self.log_index.to_be_bytes());
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> eyre::Result<Self> {
+        if bytes.len() != Self::ENCODED_LEN {
+            return Err(eyre::eyre!(
+                "Invalid raw claim event length {} != {}",
+                bytes.len(),
+                Self::ENCODED_LEN
+            ));
+        }
+        Ok(Self {
+            global_index: U256::from_be_slice(&bytes[0..32]),
+            origin_network: u32::from_be_bytes(bytes[32..36].try_into()?),
+            origin_address: Address::from_slice(&bytes[36..56]),
+            destination_address: Address::from_slice(&bytes[56..76]),
+            amount: U256::from_be_slice(&bytes[76..108]),
+            tx_hash: B256::from_slice(&bytes[108..140]),
+            block_number: u64::from_be_bytes(bytes[140..148].try_into()?),
+            log_index: u64::from_be_bytes(bytes[148..156].try_into()?),
+        })
+    }
+}
+
+// A token deployed by a bridge to represent a token of another network, from its
+// NewWrappedToken event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WrappedToken {
+    pub origin_network: u32,
+    pub origin_address: Address,
+    pub wrapped_address: Address,
+    // ABI encoded name, symbol and decimals of the origin token.
+    pub metadata: Bytes,
+    pub block_number: u64,
+    pub log_index: u64,
+}
+
+impl WrappedToken {
+    const HEADER_LEN: usize = 60;
+
+    pub fn from_log(log: &Log<NewWrappedToken>) -> eyre::Result<Self> {
+        let event = log.data();
+        Ok(Self {
+            origin_network: event.originNetwork,
+            origin_address: event.originTokenAddress,
+            wrapped_address: event.wrappedTokenAddress,
+            metadata: event.metadata.clone(),
+            block_number: log
+                .block_number
+                .ok_or(eyre::eyre!("Block number is None"))?,
+            log_index: log.log_index.ok_or(eyre::eyre!("Log index is None"))?,
+        })
+    }
+
+    // Encoding: origin_network BE | origin_address | wrapped_address | block_number BE |
+    // log_index BE | metadata
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::HEADER_LEN + self.metadata.len());
+        buf.extend_from_slice(&self.origin_network.to_be_bytes());
+        buf.extend_from_slice(self.origin_address.as_slice());
+        buf.extend_from_slice(self.wrapped_address.as_slice());
+        buf.extend_from_slice(&self.block_number.to_be_bytes());
+        buf.extend_from_slice(&self.log_index.to_be_bytes());
+        buf.extend_from_slice(&self.metadata);
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> eyre::Result<Self> {
+        if bytes.len() < Self::HEADER_LEN {
+            return Err(eyre::eyre!(
+                "Invalid wrapped token length {} < {}",
+                bytes.len(),
+                Self::HEADER_LEN
+            ));
+        }
+        Ok(Self {
+            origin_network: u32::from_be_bytes(bytes[0..4].try_into()?),
+            origin_address: Address::from_slice(&bytes[4..24]),
+            wrapped_address: Address::from_slice(&bytes[24..44]),
+            block_number: u64::from_be_bytes(bytes[44..52].try_into()?),
+            log_index: u64::from_be_bytes(bytes[52..60].try_into()?),
+            metadata: Bytes::copy_from_slice(&bytes[Self::HEADER_LEN..]),
+        })
+    }
+}
+
+// A claim that was unset in a sovereign chain, so that the deposit can be claimed again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsetClaim {
+    pub source_network: u32,
+    pub leaf_index: u32,
+    pub block_number: u64,
+    pub log_index: u64,
+}
+
+impl UnsetClaim {
+    const ENCODED_LEN: usize = 24;
+
+    pub fn from_log(log: &Log<UnsetClaimEvent>) -> eyre::Result<Self> {
+        let event = log.data();
+        Ok(Self {
+            source_network: event.sourceBridgeNetwork,
+            leaf_index: event.leafIndex,
+            block_number: log
+                .block_number
+                .ok_or(eyre::eyre!("Block number is None"))?,
+            log_index: log.log_index.ok_or(eyre::eyre!("Log index is None"))?,
+        })
+    }
+
+    // Encoding: source_network BE | leaf_index BE | block_number BE | log_index BE
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut buf = [0u8; Self::ENCODED_LEN];
+        buf[0..4].copy_from_slice(&self.source_network.to_be_bytes());
+        buf[4..8].copy_from_slice(&self.leaf_index.to_be_bytes());
+        buf[8..16].copy_from_slice(&self.block_number.to_be_bytes());
+        buf[16..24].copy_from_slice(&self.log_index.to_be_bytes());
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> eyre::Result<Self> {
+        if bytes.len() != Self::ENCODED_LEN {
+            return Err(eyre::eyre!(
+                "Invalid unset claim length {} != {}",
+                bytes.len(),
+                Self::ENCODED_LEN
+            ));
+        }
+        Ok(Self {
+            source_network: u32::from_be_bytes(bytes[0..4].try_into()?),
+            leaf_index: u32::from_be_bytes(bytes[4..8].try_into()?),
+            block_number: u64::from_be_bytes(bytes[8..16].try_into()?),
+            log_index: u64::from_be_bytes(bytes[16..24].try_into()?),
+        })
+    }
+}
+
+// Changes made by the bridge manager of a sovereign chain (BridgeL2SovereignChain) to
+// the tokens of its bridge. MigrateLegacyToken is emitted by users instead, but is kept
+// along them since it involves the same tokens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SovereignChange {
+    SetToken {
+        origin_network: u32,
+        origin_address: Address,
+        sovereign_address: Address,
+        is_not_mintable: bool,
+    },
+    MigrateLegacyToken {
+        sender: Address,
+        legacy_address: Address,
+        updated_address: Address,
+        amount: U256,
+    },
+    RemoveLegacyToken {
+        sovereign_address: Address,
+    },
+    SetWeth {
+        sovereign_address: Address,
+        is_not_mintable: bool,
+    },
+    SetBridgeManager {
+        bridge_manager: Address,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SovereignEvent {
+    pub change: SovereignChange,
+    pub block_number: u64,
+    pub log_index: u64,
+}
+
+impl SovereignEvent {
+    // Decodes any of the logs of SovereignChange.
+    pub fn from_log(log: &Log) -> eyre::Result<Self> {
+        let change = match log.topic0() {
+            Some(&SetSovereignTokenAddress::SIGNATURE_HASH) => {
+                let event = log.log_decode::<SetSovereignTokenAddress>()?;
+                let event = event.data();
+                SovereignChange::SetToken {
+                    origin_network: event.originNetwork,
+                    origin_address: event.originTokenAddress,
+                    sovereign_address: event.sovereignTokenAddress,
+                    is_not_mintable: event.isNotMintable,
+                }
+            }
+            Some(&MigrateLegacyToken::SIGNATURE_HASH) => {
+                let event = log.log_decode::<MigrateLegacyToken>()?;
+                let event = event.data();
+                SovereignChange::MigrateLegacyToken {
+                    sender: event.sender,
+                    legacy_address: event.legacyTokenAddress,
+                    updated_address: event.updatedTokenAddress,
+                    amount: event.amount,
+                }
+            }
+            Some(&RemoveLegacySovereignTokenAddress::SIGNATURE_HASH) => {
+                let event = log.log_decode::<RemoveLegacySovereignTokenAddress>()?;
+                SovereignChange::RemoveLegacyToken {
+                    sovereign_address: event.data().sovereignTokenAddress,
+                }
+            }
+            Some(&SetSovereignWETHAddress::SIGNATURE_HASH) => {
+                let event = log.log_decode::<SetSovereignWETHAddress>()?;
+                let event = event.data();
+                SovereignChange::SetWeth {
+                    sovereign_address: event.sovereignWETHTokenAddress,
+                    is_not_mintable: event.isNotMintable,
+                }
+            }
+            Some(&SetBridgeManager::SIGNATURE_HASH) => {
+                let event = log.log_decode::<SetBridgeManager>()?;
+                SovereignChange::SetBridgeManager {
+                    bridge_manager: event.data().bridgeManager,
+                }
+            }
+            topic => return Err(eyre::eyre!("Not a sovereign chain event: {:?}", topic)),
+        };
+        Ok(Self {
+            change,
+            block_number: log
+                .block_number
+                .ok_or(eyre::eyre!("Block number is None"))?,
+            log_index: log.log_index.ok_or(eyre::eyre!("Log index is None"))?,
+        })
+    }
+
+    // Encoding: block_number BE | log_index BE | change tag | change fields
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(109);
+        buf.extend_from_slice(&self.block_number.to_be_bytes());
+        buf.extend_from_slice(&self.log_index.to_be_bytes());
+        match &self.change {
+            SovereignChange::SetToken {
+                origin_network,
+                origin_address,
+                sovereign_address,
+                is_not_mintable,
+            } => {
+                buf.push(0);
+                buf.extend_from_slice(&origin_network.to_be_bytes());
+                buf.extend_from_slice(origin_address.as_slice());
+                buf.extend_from_slice(sovereign_address.as_slice());
+                buf.push(*is_not_mintable as u8);
+            }
+            SovereignChange::MigrateLegacyToken {
+                sender,
+                legacy_address,
+                updated_address,
+                amount,
+            } => {
+                buf.push(1);
+                buf.extend_from_slice(sender.as_slice());
+                buf.extend_from_slice(legacy_address.as_slice());
+                buf.extend_from_slice(updated_address.as_slice());
+                buf.extend_from_slice(&amount.to_be_bytes::<32>());
+            }
+            SovereignChange::RemoveLegacyToken { sovereign_address } => {
+                buf.push(2);
+                buf.extend_from_slice(sovereign_address.as_slice());
+            }
+            SovereignChange::SetWeth {
+                sovereign_address,
+                is_not_mintable,
+            } => {
+                buf.push(3);
+                buf.extend_from_slice(sovereign_address.as_slice());
+                buf.push(*is_not_mintable as u8);
+            }
+            SovereignChange::SetBridgeManager { bridge_manager } => {
+                buf.push(4);
+                buf.extend_from_slice(bridge_manager.as_slice());
+            }
+        }
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> eyre::Result<Self> {
+        let len = match bytes.get(16) {
+            Some(0) => 62,
+            Some(1) => 109,
+            Some(2) | Some(4) => 37,
+            Some(3) => 38,
+            Some(tag) => return Err(eyre::eyre!("Unknown sovereign change {}", tag)),
+            None => {
+                return Err(eyre::eyre!(
+                    "Invalid sovereign event length {}",
+                    bytes.len()
+                ));
+            }
+        };
+        if bytes.len() != len {
+            return Err(eyre::eyre!(
+                "Invalid sovereign event length {} != {}",
+                bytes.len(),
+                len
+            ));
+        }
+        let b = &bytes[17..];
+        let change = match bytes[16] {
+            0 => SovereignChange::SetToken {
+                origin_network: u32::from_be_bytes(b[0..4].try_into()?),
+                origin_address: Address::from_slice(&b[4..24]),
+                sovereign_address: Address::from_slice(&b[24..44]),
+                is_not_mintable: b[44] != 0,
+            },
+            1 => SovereignChange::MigrateLegacyToken {
+                sender: Address::from_slice(&b[0..20]),
+                legacy_address: Address::from_slice(&b[20..40]),
+                updated_address: Address::from_slice(&b[40..60]),
+                amount: U256::from_be_slice(&b[60..92]),
+            },
+            2 => SovereignChange::RemoveLegacyToken {
+                sovereign_address: Address::from_slice(&b[0..20]),
+            },
+            3 => SovereignChange::SetWeth {
+                sovereign_address: Address::from_slice(&b[0..20]),
+                is_not_mintable: b[20] != 0,
+            },
+            _ => SovereignChange::SetBridgeManager {
+                bridge_manager: Address::from_slice(&b[0..20]),
+            },
+        };
+        Ok(Self {
+            change,
+            block_number: u64::from_be_bytes(bytes[0..8].try_into()?),
+            log_index: u64::from_be_bytes(bytes[8..16].try_into()?),
+        })
+    }
+}
+
+// A token of another network that the sovereign chain maps to a token of its own,
+// instead of a wrapped token deployed by the bridge.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SovereignToken {
+    pub origin_network: u32,
+    pub origin_address: Address,
+    pub sovereign_address: Address,
+    pub is_not_mintable: bool,
+}
+
+// Current configuration of a sovereign chain bridge, as left by its SovereignEvents.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SovereignConfig {
+    pub tokens: Vec<SovereignToken>,
+    // Tokens that were replaced by another sovereign token and not removed yet. They can
+    // still be migrated to the new one.
+    pub legacy_tokens: Vec<Address>,
+    pub weth_address: Option<Address>,
+    pub weth_not_mintable: bool,
+    pub bridge_manager: Option<Address>,
+}
+
+impl SovereignConfig {
+    pub fn apply(&mut self, change: &SovereignChange) {
+        match change {
+            SovereignChange::SetToken {
+                origin_network,
+                origin_address,
+                sovereign_address,
+                is_not_mintable,
+            } => {
+                let token = SovereignToken {
+                    origin_network: *origin_network,
+                    origin_address: *origin_address,
+                    sovereign_address: *sovereign_address,
+                    is_not_mintable: *is_not_mintable,
+                };
+                match self.tokens.iter_mut().find(|t| {
+                    t.origin_network == *origin_network && t.origin_address == *origin_address
+                }) {
+                    Some(current) => {
+                        if current.sovereign_address != *sovereign_address {
+                            self.legacy_tokens.push(current.sovereign_address);
+                        }
+                        *current = token;
+                    }
+                    None => self.tokens.push(token),
+                }
+                self.legacy_tokens.retain(|a| a != sovereign_address);
+            }
+            SovereignChange::RemoveLegacyToken { sovereign_address } => {
+                self.legacy_tokens.retain(|a| a != sovereign_address);
+            }
+            SovereignChange::SetWeth {
+                sovereign_address,
+                is_not_mintable,
+            } => {
+                self.weth_address = Some(*sovereign_address);
+                self.weth_not_mintable = *is_not_mintable;
+            }
+            SovereignChange::SetBridgeManager { bridge_manager } => {
+                self.bridge_manager = Some(*bridge_manager);
+            }
+            SovereignChange::MigrateLegacyToken { .. } => {}
+        }
+    }
+}
+
+// The logs of a bridge that are indexed, usually from one chunk of blocks.
+#[derive(Clone, Default)]
+pub struct BridgeEvents {
+    pub deposits: Vec<RawBridgeEvent>,
+    pub claims: Vec<RawClaimEvent>,
+    pub wrapped_tokens: Vec<WrappedToken>,
+    // Only emitted by sovereign chains.
+    pub sovereign_events: Vec<SovereignEvent>,
+    pub unset_claims: Vec<UnsetClaim>,
+}
+
+impl BridgeEvents {
+    pub fn is_empty(&self) -> bool {
+        self.deposits.is_empty()
+            && self.claims.is_empty()
+            && self.wrapped_tokens.is_empty()
+            && self.sovereign_events.is_empty()
+            && self.unset_claims.is_empty()
     }
 }
@@ -61,11 +61,21 @@ impl LeafL1InfoTree {
     }
 
     pub fn info_root_leaf(&self) -> FixedBytes<32> {
-        let mut buf = [0u8; 72];
-        buf[0..32].copy_from_slice(self.ger.as_slice());
-        buf[32..64].copy_from_slice(self.prev_l1_block_hash().as_slice());
-        buf[64..72].copy_from_slice(&self.timestamp().to_be_bytes());
-        keccak256(&buf)
+        info_root_leaf(&self.ger, &self.prev_l1_block_hash(), self.timestamp())
+    }
+
+    // Data of the leaf that is persisted next to the tree, needed to build claim proofs.
+    pub fn data(&self) -> eyre::Result<L1InfoLeafData> {
+        Ok(L1InfoLeafData {
+            mer: self.mer(),
+            rer: self.rer(),
+            prev_block_hash: self.prev_l1_block_hash(),
+            timestamp: self.timestamp(),
+            block: self
+                .log
+                .block_number
+                .ok_or(eyre::eyre!("Block number is None"))?,
+        })
     }
 
     pub fn to_ger(mer: &FixedBytes<32>, rer: &FixedBytes<32>) -> B256 {
@@ -90,3 +100,61 @@ impl LeafL1InfoTree {
     // TODO: Add getter for the index? Only preset in V2 event
     // but maybe a nice to have to ensure we dont try to insert events out of order.
 }
+
+fn info_root_leaf(ger: &B256, prev_block_hash: &B256, timestamp: u64) -> FixedBytes<32> {
+    let mut buf = [0u8; 72];
+    buf[0..32].copy_from_slice(ger.as_slice());
+    buf[32..64].copy_from_slice(prev_block_hash.as_slice());
+    buf[64..72].copy_from_slice(&timestamp.to_be_bytes());
+    keccak256(buf)
+}
+
+// Everything needed to rebuild an L1 Info Tree leaf, plus the L1 block it was added in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct L1InfoLeafData {
+    pub mer: B256,
+    pub rer: B256,
+    pub prev_block_hash: B256,
+    pub timestamp: u64,
+    pub block: u64,
+}
+
+impl L1InfoLeafData {
+    const ENCODED_LEN: usize = 112;
+
+    pub fn ger(&self) -> B256 {
+        LeafL1InfoTree::to_ger(&self.mer, &self.rer)
+    }
+
+    pub fn info_root_leaf(&self) -> FixedBytes<32> {
+        info_root_leaf(&self.ger(), &self.prev_block_hash, self.timestamp)
+    }
+
+    // Fixed size encoding: mer | rer | prev_block_hash | timestamp BE | block BE
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut buf = [0u8; Self::ENCODED_LEN];
+        buf[0..32].copy_from_slice(self.mer.as_slice());
+        buf[32..64].copy_from_slice(self.rer.as_slice());
+        buf[64..96].copy_from_slice(self.prev_block_hash.as_slice());
+        buf[96..104].copy_from_slice(&self.timestamp.to_be_bytes());
+        buf[104..112].copy_from_slice(&self.block.to_be_bytes());
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> eyre::Result<Self> {
+        if bytes.len() != Self::ENCODED_LEN {
+            return Err(eyre::eyre!(
+                "Invalid L1 info leaf length {} != {}",
+                bytes.len(),
+                Self::ENCODED_LEN
+            ));
+        }
+        Ok(Self {
+            mer: B256::from_slice(&bytes[0..32]),
+            rer: B256::from_slice(&bytes[32..64]),
+            prev_block_hash: B256::from_slice(&bytes[64..96]),
+            timestamp: u64::from_be_bytes(bytes[96..104].try_into()?),
+            block: u64::from_be_bytes(bytes[104..112].try_into()?),
+        })
+    }
+}
@@ -1,4 +1,5 @@
 pub mod api;
+pub mod claim_proof;
 pub mod cli;
 pub mod contracts;
 pub mod indexer;
@@ -8,3 +9,6 @@ pub mod indexer_rollupmanager;
 pub mod leaf_bridge;
 pub mod leaf_l1infotree;
 pub mod merkle_tree;
+pub mod rollup;
+pub mod rpc;
+pub mod storage;
@@ -1,30 +1,114 @@
-use aggkit_rust::api::{AppState, ProviderStack, run_server};
-use aggkit_rust::cli::Cli;
+use aggkit_rust::api::{AppState, run_server};
+use aggkit_rust::cli::{Cli, Command};
 use aggkit_rust::contracts::PolygonZkEVMBridgeV2::{self, PolygonZkEVMBridgeV2Instance};
 use aggkit_rust::contracts::{PolygonRollupManager, PolygonZkEVMGlobalExitRootV2};
 use aggkit_rust::indexer::Indexer;
 use aggkit_rust::indexer_bridge::BridgeEventProcessor;
 use aggkit_rust::indexer_l1infotree::L1InfoTreeEventProcessor;
 use aggkit_rust::indexer_rollupmanager::RollupManagerEventProcessor;
-use aggkit_rust::merkle_tree::MerkleForest;
+use aggkit_rust::merkle_tree::{Inconsistency, MerkleForest};
+use aggkit_rust::rpc::{self, ProviderStack, RetryConfig, RpcStats};
 use alloy::primitives::Address;
-use alloy::providers::ProviderBuilder;
-use alloy::rpc::client::RpcClient;
-use alloy::rpc::types::BlockNumberOrTag;
+use alloy::providers::Provider;
 use alloy::transports::http::reqwest::Url;
-use alloy::transports::layers::RetryBackoffLayer;
 use clap::Parser;
 use eyre::Result;
-use futures::future;
 use std::collections::HashMap;
 use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
 use std::sync::Arc;
-use tokio::{signal, task};
+use tokio::signal;
+use tokio::signal::unix::SignalKind;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+// Checks every tree of the key-value store while the indexer is stopped, e.g. after a
+// crash, since writes are not synced. See `MerkleForest::check_tree`.
+fn check_db(key_value_store: &str, repair: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !std::path::Path::new(key_value_store).exists() {
+        return Err(format!("Key-value store {} not found", key_value_store).into());
+    }
+    let forest = MerkleForest::open(key_value_store)?;
+    let mut total = 0;
+    let mut missing_leaves = 0;
+    for tree_type in forest.get_trees()? {
+        let found = forest.check_tree(tree_type, repair)?;
+        println!("{:?}: {} inconsistencies", tree_type, found.len());
+        for inconsistency in &found {
+            println!("  {:?}", inconsistency);
+            if matches!(inconsistency, Inconsistency::MissingLeaf { .. }) {
+                missing_leaves += 1;
+            }
+        }
+        total += found.len();
+    }
+
+    if missing_leaves > 0 {
+        return Err(format!(
+            "{} trees have missing leaves, they must be indexed again",
+            missing_leaves
+        )
+        .into());
+    }
+    if total > 0 && !repair {
+        return Err(format!(
+            "Found {} inconsistencies, run with --repair to fix them",
+            total
+        )
+        .into());
+    }
+    println!(
+        "Checked {:?}: {} inconsistencies repaired",
+        key_value_store, total
+    );
+    Ok(())
+}
+
+// Resolves on Ctrl-C or SIGTERM, which is how Kubernetes stops pods.
+async fn shutdown_signal() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut sigterm = signal::unix::signal(SignalKind::terminate())?;
+    tokio::select! {
+        res = signal::ctrl_c() => res?,
+        _ = sigterm.recv() => {}
+    }
+    Ok(())
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let cli = Cli::parse();
-    let l1_rpc_url: Url = cli.l1_rpc_url.parse()?;
+    match cli.command {
+        Some(Command::CheckDb { repair }) => return check_db(&cli.key_value_store, repair),
+        Some(Command::ExportSnapshot { file, events }) => {
+            if !std::path::Path::new(&cli.key_value_store).exists() {
+                return Err(format!("Key-value store {} not found", cli.key_value_store).into());
+            }
+            let forest = MerkleForest::open(&cli.key_value_store)?;
+            let writer = BufWriter::new(File::create(&file)?);
+            let entries = forest.export_snapshot(writer, events)?;
+            println!("Exported {} entries to {:?}", entries, file);
+            return Ok(());
+        }
+        Some(Command::ImportSnapshot { file }) => {
+            let reader = BufReader::new(File::open(&file)?);
+            let (_, import) = MerkleForest::import_snapshot(&cli.key_value_store, reader)?;
+            for (cf, entries) in import.entries {
+                println!("Imported {} entries of {}", entries, cf);
+            }
+            println!(
+                "Imported {:?} into {:?} (events: {})",
+                file, cli.key_value_store, import.events
+            );
+            return Ok(());
+        }
+        None => {}
+    }
+    let l1_rpc_url: Url = cli
+        .l1_rpc_url
+        .as_deref()
+        .ok_or("--l1-rpc-url is required")?
+        .parse()?;
     let l2_rpc_urls: Vec<Url> = cli
         .l2_rpcs
         .iter()
@@ -35,29 +119,37 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let rollup_manager_address: Address = cli.rollup_manager_address.parse()?;
     let key_value_store: String = cli.key_value_store;
 
-    let max_retry: u32 = 100;
-    let backoff: u64 = 2000;
-    let cups: u64 = 100;
+    let retry = RetryConfig {
+        max_retries: cli.rpc_max_retries,
+        backoff: cli.rpc_backoff,
+        compute_units_per_second: cli.rpc_compute_units_per_second,
+    };
+
+    // Requests are retried for a while, which would hide a wrong URL or API key.
+    for rpc_url in std::iter::once(&l1_rpc_url).chain(&l2_rpc_urls) {
+        rpc::check_connection(rpc_url).await?;
+    }
+
+    // Errors of the providers used by the API and the event processors, next to the
+    // ones of each indexer.
+    let mut rpc_stats: Vec<(String, Arc<RpcStats>)> = Vec::new();
+    let mut connect = |name: String, rpc_url: &Url| {
+        let stats = Arc::new(RpcStats::default());
+        rpc_stats.push((name, Arc::clone(&stats)));
+        rpc::connect(rpc_url.clone(), &retry, stats)
+    };
 
     // TODO: Organize this better.
-    let l1_provider = Arc::new(
-        ProviderBuilder::new().connect_client(
-            RpcClient::builder()
-                .layer(RetryBackoffLayer::new(max_retry, backoff, cups))
-                .http(l1_rpc_url.clone()),
-        ),
-    );
+    let l1_provider = connect("l1".to_string(), &l1_rpc_url);
 
     // Build a provider per configured L2 RPC
-    let l2_providers: Vec<ProviderStack> = l2_rpc_urls
+    let l2_providers: Vec<ProviderStack> = cli
+        .l2_rpcs
         .iter()
-        .map(|l2_rpc_url| {
-            Arc::new(
-                ProviderBuilder::new().connect_client(
-                    RpcClient::builder()
-                        .layer(RetryBackoffLayer::new(max_retry, backoff, cups))
-                        .http(l2_rpc_url.clone()),
-                ),
+        .map(|l2_rpc| {
+            connect(
+                format!("l2-aggchain-{}", l2_rpc.aggchain_id),
+                &l2_rpc.rpc_url,
             )
         })
         .collect();
@@ -65,6 +157,7 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     println!("l1 rpc url: {:?}", l1_rpc_url.as_str());
     println!("l2 rpc urls: {:?}", l2_rpc_urls);
     println!("key value store: {:?}", key_value_store);
+    println!("sync mode: {}", cli.sync_mode);
     println!("Ger contract address: {:?}", ger_address);
     println!("Bridge contract address: {:?}", bridge_address);
     println!(
@@ -72,35 +165,57 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         rollup_manager_address
     );
 
+    // Every L2 RPC must be of the aggchain it is configured for, otherwise its bridge
+    // events would end up in the Local Exit Tree of another aggchain.
+    let rollup_manager = PolygonRollupManager::new(rollup_manager_address, l1_provider.clone());
+    for (l2_rpc, l2_provider) in cli.l2_rpcs.iter().zip(&l2_providers) {
+        let chain_id = l2_provider.get_chain_id().await?;
+        let rollup_id = rollup_manager.chainIDToRollupID(chain_id).call().await?;
+        if rollup_id != l2_rpc.aggchain_id {
+            return Err(format!(
+                "--l2-rpc-url {}: chain id {} is aggchain {} on-chain, not {}",
+                l2_rpc.rpc_url, chain_id, rollup_id, l2_rpc.aggchain_id
+            )
+            .into());
+        }
+    }
+
     let trees = Arc::new(MerkleForest::open(key_value_store)?);
 
+    let l1_bridge = PolygonZkEVMBridgeV2::new(bridge_address, l1_provider.clone());
+
     let l1_bridge_indexer = Indexer::new(
         l1_rpc_url.clone(),
         "l1-bridge-indexer".to_string(),
         bridge_address,
-        BlockNumberOrTag::Finalized,
+        cli.sync_mode,
         BridgeEventProcessor {
-            tree: Arc::clone(&trees),
             aggchain_id: 0,
+            bridge: l1_bridge.clone(),
         },
+        Arc::clone(&trees),
         cli.block_range,
+        &retry,
     )?;
 
     // Create one indexer per configured L2 RPC, using aggchain_id in the name
     let l2_bridge_indexers: Vec<Indexer<BridgeEventProcessor>> = cli
         .l2_rpcs
         .iter()
-        .map(|l2_rpc| {
+        .zip(&l2_providers)
+        .map(|(l2_rpc, l2_provider)| {
             Indexer::new(
                 l2_rpc.rpc_url.clone(),
                 format!("l2-bridge-indexer-aggchain-{}", l2_rpc.aggchain_id),
                 bridge_address,
-                BlockNumberOrTag::Finalized,
+                cli.sync_mode,
                 BridgeEventProcessor {
-                    tree: Arc::clone(&trees),
                     aggchain_id: l2_rpc.aggchain_id,
+                    bridge: PolygonZkEVMBridgeV2::new(bridge_address, l2_provider.clone()),
                 },
+                Arc::clone(&trees),
                 cli.block_range,
+                &retry,
             )
         })
         .collect::<Result<_, _>>()?;
@@ -109,98 +224,93 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         l1_rpc_url.clone(),
         "rollup-manager-indexer".to_string(),
         rollup_manager_address,
-        BlockNumberOrTag::Finalized,
+        cli.sync_mode,
         RollupManagerEventProcessor {
-            tree: Arc::clone(&trees),
+            rollup_manager: rollup_manager.clone(),
         },
+        Arc::clone(&trees),
         cli.block_range,
+        &retry,
     )?;
 
     let l1infotree_indexer = Indexer::new(
         l1_rpc_url.clone(),
         "l1infotree-indexer".to_string(),
         ger_address,
-        BlockNumberOrTag::Finalized,
+        cli.sync_mode,
         L1InfoTreeEventProcessor {
-            tree: Arc::clone(&trees),
             provider: l1_provider.clone(),
+            mainnet_indexer: l1_bridge_indexer.name().to_string(),
+            rollup_manager_indexer: rollup_manager_indexer.name().to_string(),
         },
+        Arc::clone(&trees),
         cli.block_range,
+        &retry,
     )?;
 
-    let handle_l1_bridge_indexer = task::spawn(l1_bridge_indexer.run());
-
-    // Spawn a task per L2 bridge indexer
-    let l2_bridge_tasks: Vec<_> = l2_bridge_indexers
-        .into_iter()
-        .map(|indexer| task::spawn(indexer.run()))
-        .collect();
+    // Every task stops once cancelled. Indexers process what they already fetched and
+    // sync the key-value store, and the server answers in-flight requests.
+    let shutdown = CancellationToken::new();
+    let mut tasks = JoinSet::new();
 
-    // Future that resolves once all L2 bridge indexer tasks finish
-    let handle_l2_bridge_indexers = future::join_all(l2_bridge_tasks);
-
-    let handle_l1infotree_indexer = task::spawn(l1infotree_indexer.run());
-    let handle_rollup_manager_indexer = task::spawn(rollup_manager_indexer.run());
+    rpc_stats.push((
+        l1_bridge_indexer.name().to_string(),
+        l1_bridge_indexer.rpc_stats(),
+    ));
+    tasks.spawn(l1_bridge_indexer.run(shutdown.clone()));
+    for indexer in l2_bridge_indexers {
+        rpc_stats.push((indexer.name().to_string(), indexer.rpc_stats()));
+        tasks.spawn(indexer.run(shutdown.clone()));
+    }
+    rpc_stats.push((
+        l1infotree_indexer.name().to_string(),
+        l1infotree_indexer.rpc_stats(),
+    ));
+    tasks.spawn(l1infotree_indexer.run(shutdown.clone()));
+    rpc_stats.push((
+        rollup_manager_indexer.name().to_string(),
+        rollup_manager_indexer.rpc_stats(),
+    ));
+    tasks.spawn(rollup_manager_indexer.run(shutdown.clone()));
 
-    let l1_bridge = PolygonZkEVMBridgeV2::new(bridge_address, l1_provider.clone());
     let l1_infotree = PolygonZkEVMGlobalExitRootV2::new(ger_address, l1_provider.clone());
-    let rollup_manager = PolygonRollupManager::new(rollup_manager_address, l1_provider.clone());
 
     let l2_bridges: HashMap<u32, PolygonZkEVMBridgeV2Instance<ProviderStack>> = cli
         .l2_rpcs
         .iter()
-        .map(|l2_rpc| {
-            let provider = Arc::new(
-                ProviderBuilder::new().connect_client(
-                    RpcClient::builder()
-                        .layer(RetryBackoffLayer::new(max_retry, backoff, cups))
-                        .http(l2_rpc.rpc_url.clone()),
-                ),
-            );
+        .zip(&l2_providers)
+        .map(|(l2_rpc, l2_provider)| {
             (
                 l2_rpc.aggchain_id,
-                PolygonZkEVMBridgeV2::new(bridge_address, provider),
+                PolygonZkEVMBridgeV2::new(bridge_address, l2_provider.clone()),
             )
         })
         .collect();
 
     let state = AppState {
         tree: Arc::clone(&trees),
-        l1_bridge: l1_bridge,
-        l2_bridges: l2_bridges,
-        l1_infotree: l1_infotree,
-        //rollup_manager: rollup_manager,
+        l1_bridge,
+        l2_bridges,
+        l1_infotree,
+        rpc_stats,
     };
-    let handle_api = task::spawn(run_server(state));
+    tasks.spawn(run_server(state, shutdown.clone()));
 
     // TODO: Do proper error handling
-
+    // Tasks only finish on their own on errors, which stops the rest.
     tokio::select! {
-        res = handle_l1_bridge_indexer => {
-            println!("Index task l1 completed: {:?}", res);
-        }
-        res = handle_l2_bridge_indexers => {
-            println!("Index tasks l2 completed: {:?}", res);
-        }
-
-        res = handle_l1infotree_indexer => {
-            println!("Index task l1infotree completed: {:?}", res);
+        Some(res) = tasks.join_next() => {
+            println!("Task completed: {:?}", res);
         }
-
-        res = handle_rollup_manager_indexer => {
-            println!("Index task rollup manager completed: {:?}", res);
-        }
-
-        res = handle_api => {
-            println!("Server task completed: {:?}", res);
-        }
-
-        res = signal::ctrl_c() => {
+        res = shutdown_signal() => {
             println!("Received shutdown signal: {:?}", res);
         }
     }
 
-    // TODO: Handle sigterm gracefully.
+    shutdown.cancel();
+    while let Some(res) = tasks.join_next().await {
+        println!("Task completed: {:?}", res);
+    }
 
     Ok(())
 }
@@ -1,17 +1,32 @@
-use crate::leaf_bridge::LeafBridge;
-use alloy::primitives::{FixedBytes, keccak256};
+use crate::claim_proof::GlobalIndex;
+use crate::leaf_bridge::{
+    BridgeEvents, LeafBridge, RawBridgeEvent, RawClaimEvent, SovereignConfig, SovereignEvent,
+    UnsetClaim, WrappedToken,
+};
+use crate::leaf_l1infotree::L1InfoLeafData;
+use crate::rollup::{RollupInfo, RollupType};
+use crate::storage::{
+    Direction, IteratorMode, MemoryStorage, OverlayStorage, RocksDbStorage, Storage, WriteBatch,
+};
+use alloy::primitives::{Address, B256, FixedBytes, Keccak256, keccak256};
 use eyre::{Result, eyre};
-use rocksdb::{
-    ColumnFamily, ColumnFamilyDescriptor, DB, DBCompressionType, Options, WriteBatch, WriteOptions,
+use rocksdb::{ColumnFamilyDescriptor, DB, DBCompressionType, Options};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::TryInto,
+    io::{Read, Write},
+    ops::Deref,
+    path::Path,
+    process::exit,
+    sync::{Arc, Mutex},
 };
-use std::{convert::TryInto, path::Path, process::exit};
 
 // This file contains an implementation of all Merkle trees existing in the Agglayer. These are:
 // - Local Exit Trees: Merkle trees for each Aggchain, storing the bridge exits as leafs.
 // - Rollup Exit Tree: A Merkle tree made of all the Local Exit Roots of all Aggchains (except of the Main Exit Tree).
 // - L1 Info Tree: A Merkle tree storing all the Global Exit Roots + some extra information at different points in time.
 // It is implemented using a RocksDB key-value store. This allows for very fast retrieval of the Merkle tree roots
-// and proofs of any leaf in any of the mentioned trees.
+// and proofs of any leaf in any of the mentioned trees. The trees can also be kept in memory, see `storage.rs`.
 
 // Each tree is identified by its TreeType. There are only one RollupExitTree and one L1InfoTree trees
 // and an arbitrary number of LocalExitTree identified by their AggchainId.
@@ -24,9 +39,10 @@ pub type BlockNum = u64;
 
 // Depth of the merkle tree and maximum number of leaves that
 // can be stored. Calculated as 2^DEPTH - 1.
-const DEPTH: usize = 32;
+pub const DEPTH: usize = 32;
 const MAX_LEAVES: u32 = ((1u64 << DEPTH) - 1) as u32;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum TreeType {
     /// Append-only Local Exit Tree of a specific AggChain.
     /// When 0 it is the Main Exit Tree. This type requires a TreeId.
@@ -48,6 +64,11 @@ impl TreeType {
         }
     }
 
+    /// Whether leaves can only be appended. Otherwise any leaf can be set.
+    pub fn is_append_only(&self) -> bool {
+        !matches!(self, Self::RollupExitTree)
+    }
+
     pub fn aggchain_id(&self) -> AggchainId {
         match *self {
             Self::LocalExitTree(id) => id,
@@ -56,16 +77,177 @@ impl TreeType {
     }
 }
 
+// Every BridgeEvent as emitted, by aggchain and deposit count. Two more column families
+// index them by transaction hash and by destination address.
 const CF_RAW_BRIDGE_LEAF: &str = "raw_bridge_leaf";
+const CF_BRIDGE_BY_TX: &str = "bridge_by_tx";
+const CF_BRIDGE_BY_DESTINATION: &str = "bridge_by_destination";
+
+// Every ClaimEvent, by the aggchain it was claimed in and the deposit it claims.
+const CF_CLAIMS: &str = "claims";
+
+// Wrapped tokens deployed by each bridge, by origin token. Another column family maps
+// the wrapped token addresses back to their origin.
+const CF_WRAPPED_TOKENS: &str = "wrapped_tokens";
+const CF_WRAPPED_TOKENS_BY_ADDRESS: &str = "wrapped_tokens_by_address";
+
+// Claims unset by a sovereign chain, keyed like the claims.
+const CF_UNSET_CLAIMS: &str = "unset_claims";
+
+// Token and bridge manager changes of each sovereign chain, by block and log index. The
+// current configuration is built from all of them, see `get_sovereign_config`.
+const CF_SOVEREIGN_EVENTS: &str = "sovereign_events";
+
+// Bridge logs other than deposits, ordered by aggchain, block and log index, so that
+// they can be rolled back. Each entry points to where the log is stored.
+const CF_BRIDGE_LOGS_BY_BLOCK: &str = "bridge_logs_by_block";
 const CF_METADATA: &str = "metadata";
+const CF_BLOCK_HASHES: &str = "block_hashes";
+
+// Last block each indexer processed, by indexer name. Unlike the latest block of the
+// trees, it also advances on blocks without events.
+const CF_INDEXER_CURSORS: &str = "indexer_cursors";
+
+// Data of each L1 Info Tree leaf (MER, RER, timestamp...), by leaf index.
+const CF_L1_INFO_LEAVES: &str = "l1_info_leaves";
+
+// Index of the L1 Info Tree leaf of each Global Exit Root. GERs are only added once.
+const CF_L1_INFO_GERS: &str = "l1_info_gers";
+
+// Every root a Local Exit Tree had, mapped to its leaf count at that time.
+const CF_EXIT_ROOTS: &str = "exit_roots";
+
+// Every leaf the Rollup Exit Tree had, by rollup ID, block and log index (same layout as
+// `bridge_log_block_key`), followed by the Rollup Exit Root it resulted in. Past Rollup
+// Exit Trees are rebuilt from them, see `get_rollup_leaves_at`.
+const CF_ROLLUP_LEAVES: &str = "rollup_leaves";
+
+// Every Rollup Exit Root, mapped to the block and log index of the first leaf that made it.
+// Roots repeat when an aggchain is verified again with the same Local Exit Root.
+const CF_ROLLUP_EXIT_ROOTS: &str = "rollup_exit_roots";
+
+// Aggchains attached to the RollupManager, by rollup ID. Two more column families map
+// chain IDs and rollup addresses to rollup IDs.
+const CF_ROLLUPS: &str = "rollups";
+const CF_ROLLUPS_BY_CHAIN_ID: &str = "rollups_by_chain_id";
+const CF_ROLLUPS_BY_ADDRESS: &str = "rollups_by_address";
+
+// Rollup types of the RollupManager, by rollup type ID.
+const CF_ROLLUP_TYPES: &str = "rollup_types";
+
+// Writes to the rollup and rollup type registries, by block and log index, so that they
+// can be rolled back. Each entry is the kind and ID of the written entry, followed by the
+// value it replaced if there was one.
+const CF_REGISTRY_LOGS: &str = "registry_logs";
+
+#[repr(u8)]
+enum BridgeLogKind {
+    Claim = 0,
+    WrappedToken = 1,
+    UnsetClaim = 2,
+}
+
+#[repr(u8)]
+enum RegistryLogKind {
+    Rollup = 0,
+    RollupType = 1,
+}
 
 // TODO: Unsure if I will need this.
 const CF_TREE_LEVELS: &str = "tree_levels";
 
+// Snapshots are a stream of the entries of some column families: the magic and the
+// version, whether the bridge events are included, then for each column family its
+// name and its entries as key length | key | value length | value, followed by
+// SNAPSHOT_END_OF_CF. A zero name length ends the column families, and a keccak256 of
+// everything before follows. Lengths are big endian.
+const SNAPSHOT_MAGIC: &[u8; 8] = b"AGGKITRS";
+const SNAPSHOT_VERSION: u32 = 1;
+const SNAPSHOT_END_OF_CF: u32 = u32::MAX;
+const SNAPSHOT_BATCH_SIZE: usize = 100_000;
+
+/// What `MerkleForest::import_snapshot` imported.
+#[derive(Debug)]
+pub struct SnapshotImport {
+    /// Whether the snapshot contains the bridge events.
+    pub events: bool,
+    /// Number of entries of each column family, in snapshot order.
+    pub entries: Vec<(&'static str, u64)>,
+}
+
+// Column families always in a snapshot. Only the leaves of CF_TREE_LEVELS are exported.
+const SNAPSHOT_TREE_CFS: [&str; 14] = [
+    CF_TREE_LEVELS,
+    CF_METADATA,
+    CF_BLOCK_HASHES,
+    CF_INDEXER_CURSORS,
+    CF_L1_INFO_LEAVES,
+    CF_L1_INFO_GERS,
+    CF_EXIT_ROOTS,
+    CF_ROLLUP_LEAVES,
+    CF_ROLLUP_EXIT_ROOTS,
+    CF_ROLLUPS,
+    CF_ROLLUPS_BY_CHAIN_ID,
+    CF_ROLLUPS_BY_ADDRESS,
+    CF_ROLLUP_TYPES,
+    CF_REGISTRY_LOGS,
+];
+
+// Column families of the bridge events, only in a snapshot when asked for.
+const SNAPSHOT_EVENT_CFS: [&str; 9] = [
+    CF_RAW_BRIDGE_LEAF,
+    CF_BRIDGE_BY_TX,
+    CF_BRIDGE_BY_DESTINATION,
+    CF_CLAIMS,
+    CF_WRAPPED_TOKENS,
+    CF_WRAPPED_TOKENS_BY_ADDRESS,
+    CF_UNSET_CLAIMS,
+    CF_SOVEREIGN_EVENTS,
+    CF_BRIDGE_LOGS_BY_BLOCK,
+];
+
+// Hash everything written to or read from a snapshot, for its checksum.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Keccak256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct HashingReader<R> {
+    inner: R,
+    hasher: Keccak256,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
 #[repr(u8)]
 enum ColumnType {
     HashedNode = 0,
     Metadata = 1,
+    LeafBlock = 2,
 }
 
 #[repr(u8)]
@@ -94,9 +276,180 @@ fn meta_key(tree: &TreeType, tag: MetaTag) -> [u8; 7] {
     k[6] = tag as u8;
     k
 }
+
+// Block number in which the leaf at `index` was inserted. Only used by append-only trees.
+fn leaf_block_key(tree: &TreeType, index: u32) -> [u8; 10] {
+    let mut k = [0u8; 10];
+    k[..4].copy_from_slice(&tree.aggchain_id().to_be_bytes());
+    k[4] = ColumnType::LeafBlock as u8;
+    k[5] = tree.tree_type();
+    k[6..10].copy_from_slice(&index.to_be_bytes());
+    k
+}
+
+// Raw bridge events are keyed by aggchain and deposit count, in insertion order.
+fn raw_bridge_key(aggchain_id: AggchainId, deposit_count: u32) -> [u8; 8] {
+    let mut k = [0u8; 8];
+    k[..4].copy_from_slice(&aggchain_id.to_be_bytes());
+    k[4..].copy_from_slice(&deposit_count.to_be_bytes());
+    k
+}
+
+// Keys of the secondary indexes: the indexed value followed by the primary key, so that
+// all the events with the same value can be found with a prefix iteration.
+fn bridge_index_key(prefix: &[u8], aggchain_id: AggchainId, deposit_count: u32) -> Vec<u8> {
+    let mut k = prefix.to_vec();
+    k.extend_from_slice(&raw_bridge_key(aggchain_id, deposit_count));
+    k
+}
+
+fn claim_key(aggchain_id: AggchainId, source_network: AggchainId, deposit_count: u32) -> [u8; 12] {
+    let mut k = [0u8; 12];
+    k[..4].copy_from_slice(&aggchain_id.to_be_bytes());
+    k[4..8].copy_from_slice(&source_network.to_be_bytes());
+    k[8..].copy_from_slice(&deposit_count.to_be_bytes());
+    k
+}
+
+fn wrapped_token_key(
+    aggchain_id: AggchainId,
+    origin_network: AggchainId,
+    origin_address: &Address,
+) -> [u8; 28] {
+    let mut k = [0u8; 28];
+    k[..4].copy_from_slice(&aggchain_id.to_be_bytes());
+    k[4..8].copy_from_slice(&origin_network.to_be_bytes());
+    k[8..].copy_from_slice(origin_address.as_slice());
+    k
+}
+
+fn wrapped_address_key(aggchain_id: AggchainId, wrapped_address: &Address) -> [u8; 24] {
+    let mut k = [0u8; 24];
+    k[..4].copy_from_slice(&aggchain_id.to_be_bytes());
+    k[4..].copy_from_slice(wrapped_address.as_slice());
+    k
+}
+
+fn bridge_log_block_key(aggchain_id: AggchainId, block: BlockNum, log_index: u64) -> [u8; 20] {
+    let mut k = [0u8; 20];
+    k[..4].copy_from_slice(&aggchain_id.to_be_bytes());
+    k[4..12].copy_from_slice(&block.to_be_bytes());
+    k[12..].copy_from_slice(&log_index.to_be_bytes());
+    k
+}
+
+fn registry_log_key(block: BlockNum, log_index: u64) -> [u8; 16] {
+    let mut k = [0u8; 16];
+    k[..8].copy_from_slice(&block.to_be_bytes());
+    k[8..].copy_from_slice(&log_index.to_be_bytes());
+    k
+}
+
+// Past Local Exit Roots are keyed by aggchain and root.
+fn exit_root_key(aggchain_id: AggchainId, root: &FixedBytes<32>) -> [u8; 36] {
+    let mut k = [0u8; 36];
+    k[..4].copy_from_slice(&aggchain_id.to_be_bytes());
+    k[4..].copy_from_slice(root.as_slice());
+    k
+}
+
+// Block hashes are stored per indexer. The name is followed by a separator so that
+// the hashes of an indexer are sorted by block number and don't mix with other indexers.
+fn block_hash_prefix(indexer: &str) -> Vec<u8> {
+    let mut k = Vec::with_capacity(indexer.len() + 1);
+    k.extend_from_slice(indexer.as_bytes());
+    k.push(0);
+    k
+}
+
+fn block_hash_key(indexer: &str, block: BlockNum) -> Vec<u8> {
+    let mut k = block_hash_prefix(indexer);
+    k.extend_from_slice(&block.to_be_bytes());
+    k
+}
+/// An inconsistency found by `MerkleForest::check_tree`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// A node differs from the one computed from its children. None if the node
+    /// is missing, or if it shouldn't exist because it only covers empty leaves.
+    Node {
+        tree_type: TreeType,
+        level: u8,
+        index: u32,
+        stored: Option<FixedBytes<32>>,
+        expected: Option<FixedBytes<32>>,
+    },
+    /// The leaf count doesn't match the last leaf.
+    LeafCount {
+        tree_type: TreeType,
+        stored: u32,
+        expected: u32,
+    },
+    /// The latest processed block is before the block of the last leaf.
+    LatestBlock {
+        tree_type: TreeType,
+        stored: Option<BlockNum>,
+        expected: BlockNum,
+    },
+    /// A leaf of an append-only tree is missing. The tree must be indexed again from it.
+    MissingLeaf { tree_type: TreeType, index: u32 },
+}
+
 pub struct MerkleForest {
-    db: DB,
+    db: Arc<dyn Storage>,
     zero: [FixedBytes<32>; DEPTH + 1],
+    // Frontier of each append-only tree with its leaf count, so that appending doesn't
+    // read any node. See `cached_frontier`.
+    frontiers: Mutex<HashMap<TreeType, (u32, [FixedBytes<32>; DEPTH])>>,
+}
+
+/// Writes to the trees kept in memory until `commit`, which writes them all in a single
+/// batch. Reads through the transaction see them, other reads don't. Dropping it discards
+/// them. Dereferences to the trees as seen by the transaction.
+pub struct Transaction<'a> {
+    base: &'a MerkleForest,
+    forest: MerkleForest,
+    storage: Arc<OverlayStorage>,
+    // Frontiers of `base` when the transaction started, to find the ones it changed.
+    frontiers: HashMap<TreeType, (u32, [FixedBytes<32>; DEPTH])>,
+}
+
+impl Deref for Transaction<'_> {
+    type Target = MerkleForest;
+
+    fn deref(&self) -> &MerkleForest {
+        &self.forest
+    }
+}
+
+impl Transaction<'_> {
+    pub fn commit(self) -> Result<()> {
+        self.storage.commit()?;
+
+        // The frontiers the transaction changed are the ones of the base trees now.
+        let changed = self
+            .forest
+            .frontiers
+            .lock()
+            .map_err(|_| eyre!("Frontier cache lock poisoned"))?;
+        let mut frontiers = self
+            .base
+            .frontiers
+            .lock()
+            .map_err(|_| eyre!("Frontier cache lock poisoned"))?;
+        for tree_type in changed.keys().chain(self.frontiers.keys()) {
+            match changed.get(tree_type) {
+                frontier if frontier == self.frontiers.get(tree_type) => {}
+                Some(frontier) => {
+                    frontiers.insert(*tree_type, *frontier);
+                }
+                None => {
+                    frontiers.remove(tree_type);
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl MerkleForest {
@@ -108,11 +461,51 @@ impl MerkleForest {
         // TODO: Tune this. https://github.com/facebook/rocksdb/wiki/RocksDB-Tuning-Guide
         let mut tree_opts = Options::default();
         let mut raw_opts = Options::default();
+        let mut by_tx_opts = Options::default();
+        let mut by_destination_opts = Options::default();
+        let mut claims_opts = Options::default();
+        let mut wrapped_opts = Options::default();
+        let mut wrapped_by_address_opts = Options::default();
+        let mut unset_claims_opts = Options::default();
+        let mut sovereign_opts = Options::default();
+        let mut logs_by_block_opts = Options::default();
         let mut meta_opts = Options::default();
+        let mut hashes_opts = Options::default();
+        let mut cursors_opts = Options::default();
+        let mut info_opts = Options::default();
+        let mut gers_opts = Options::default();
+        let mut exit_roots_opts = Options::default();
+        let mut rollup_leaves_opts = Options::default();
+        let mut rollup_exit_roots_opts = Options::default();
+        let mut rollups_opts = Options::default();
+        let mut rollups_by_chain_id_opts = Options::default();
+        let mut rollups_by_address_opts = Options::default();
+        let mut rollup_types_opts = Options::default();
+        let mut registry_logs_opts = Options::default();
 
         tree_opts.set_compression_type(DBCompressionType::Zstd);
         raw_opts.set_compression_type(DBCompressionType::Zstd);
+        by_tx_opts.set_compression_type(DBCompressionType::Zstd);
+        by_destination_opts.set_compression_type(DBCompressionType::Zstd);
+        claims_opts.set_compression_type(DBCompressionType::Zstd);
+        wrapped_opts.set_compression_type(DBCompressionType::Zstd);
+        wrapped_by_address_opts.set_compression_type(DBCompressionType::Zstd);
+        unset_claims_opts.set_compression_type(DBCompressionType::Zstd);
+        sovereign_opts.set_compression_type(DBCompressionType::Zstd);
+        logs_by_block_opts.set_compression_type(DBCompressionType::Zstd);
         meta_opts.set_compression_type(DBCompressionType::Zstd);
+        hashes_opts.set_compression_type(DBCompressionType::Zstd);
+        cursors_opts.set_compression_type(DBCompressionType::Zstd);
+        info_opts.set_compression_type(DBCompressionType::Zstd);
+        gers_opts.set_compression_type(DBCompressionType::Zstd);
+        exit_roots_opts.set_compression_type(DBCompressionType::Zstd);
+        rollup_leaves_opts.set_compression_type(DBCompressionType::Zstd);
+        rollup_exit_roots_opts.set_compression_type(DBCompressionType::Zstd);
+        rollups_opts.set_compression_type(DBCompressionType::Zstd);
+        rollups_by_chain_id_opts.set_compression_type(DBCompressionType::Zstd);
+        rollups_by_address_opts.set_compression_type(DBCompressionType::Zstd);
+        rollup_types_opts.set_compression_type(DBCompressionType::Zstd);
+        registry_logs_opts.set_compression_type(DBCompressionType::Zstd);
 
         let db = DB::open_cf_descriptors(
             &opts,
@@ -120,10 +513,43 @@ impl MerkleForest {
             vec![
                 ColumnFamilyDescriptor::new(CF_TREE_LEVELS, tree_opts),
                 ColumnFamilyDescriptor::new(CF_RAW_BRIDGE_LEAF, raw_opts),
+                ColumnFamilyDescriptor::new(CF_BRIDGE_BY_TX, by_tx_opts),
+                ColumnFamilyDescriptor::new(CF_BRIDGE_BY_DESTINATION, by_destination_opts),
+                ColumnFamilyDescriptor::new(CF_CLAIMS, claims_opts),
+                ColumnFamilyDescriptor::new(CF_WRAPPED_TOKENS, wrapped_opts),
+                ColumnFamilyDescriptor::new(CF_WRAPPED_TOKENS_BY_ADDRESS, wrapped_by_address_opts),
+                ColumnFamilyDescriptor::new(CF_UNSET_CLAIMS, unset_claims_opts),
+                ColumnFamilyDescriptor::new(CF_SOVEREIGN_EVENTS, sovereign_opts),
+                ColumnFamilyDescriptor::new(CF_BRIDGE_LOGS_BY_BLOCK, logs_by_block_opts),
                 ColumnFamilyDescriptor::new(CF_METADATA, meta_opts),
+                ColumnFamilyDescriptor::new(CF_BLOCK_HASHES, hashes_opts),
+                ColumnFamilyDescriptor::new(CF_INDEXER_CURSORS, cursors_opts),
+                ColumnFamilyDescriptor::new(CF_L1_INFO_LEAVES, info_opts),
+                ColumnFamilyDescriptor::new(CF_L1_INFO_GERS, gers_opts),
+                ColumnFamilyDescriptor::new(CF_EXIT_ROOTS, exit_roots_opts),
+                ColumnFamilyDescriptor::new(CF_ROLLUP_LEAVES, rollup_leaves_opts),
+                ColumnFamilyDescriptor::new(CF_ROLLUP_EXIT_ROOTS, rollup_exit_roots_opts),
+                ColumnFamilyDescriptor::new(CF_ROLLUPS, rollups_opts),
+                ColumnFamilyDescriptor::new(CF_ROLLUPS_BY_CHAIN_ID, rollups_by_chain_id_opts),
+                ColumnFamilyDescriptor::new(CF_ROLLUPS_BY_ADDRESS, rollups_by_address_opts),
+                ColumnFamilyDescriptor::new(CF_ROLLUP_TYPES, rollup_types_opts),
+                ColumnFamilyDescriptor::new(CF_REGISTRY_LOGS, registry_logs_opts),
             ],
         )?;
 
+        Ok(Self::with_storage(Box::new(RocksDbStorage::new(db))))
+    }
+
+    /// Trees kept in memory only, lost when dropped.
+    pub fn in_memory() -> Self {
+        Self::with_storage(Box::new(MemoryStorage::default()))
+    }
+
+    pub fn with_storage(db: Box<dyn Storage>) -> Self {
+        Self::from_storage(Arc::from(db))
+    }
+
+    fn from_storage(db: Arc<dyn Storage>) -> Self {
         // Default leaf is the zero hash.
         let mut zero = [FixedBytes::<32>::from([0u8; 32]); DEPTH + 1];
 
@@ -133,26 +559,45 @@ impl MerkleForest {
             zero[i] = hash(&zero[i - 1], &zero[i - 1]);
         }
 
-        Ok(Self { db, zero })
+        Self {
+            db,
+            zero,
+            frontiers: Mutex::new(HashMap::new()),
+        }
     }
 
-    fn cf_trees(&self) -> Result<&ColumnFamily> {
-        self.db
-            .cf_handle(CF_TREE_LEVELS)
-            .ok_or_else(|| eyre!("CF 'trees' not found"))
+    /// Starts a transaction on the trees, see `Transaction`.
+    pub fn transaction(&self) -> Result<Transaction<'_>> {
+        let storage = Arc::new(OverlayStorage::new(Arc::clone(&self.db)));
+        let forest = Self::from_storage(Arc::clone(&storage) as Arc<dyn Storage>);
+        let frontiers = self
+            .frontiers
+            .lock()
+            .map_err(|_| eyre!("Frontier cache lock poisoned"))?
+            .clone();
+        *forest
+            .frontiers
+            .lock()
+            .map_err(|_| eyre!("Frontier cache lock poisoned"))? = frontiers.clone();
+        Ok(Transaction {
+            base: self,
+            forest,
+            storage,
+            frontiers,
+        })
     }
 
-    fn cf_meta(&self) -> Result<&ColumnFamily> {
-        self.db
-            .cf_handle(CF_METADATA)
-            .ok_or_else(|| eyre!("CF 'meta' not found"))
+    /// Syncs every write to disk. Writes are not synced otherwise, so this is done on
+    /// shutdown.
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush()
     }
 
     // TODO: This should be u64?
     pub fn get_leaf_count(&self, tree_type: &TreeType) -> Result<u32> {
         let index = self
             .db
-            .get_cf(self.cf_meta()?, &meta_key(&tree_type, MetaTag::LeafCount))?
+            .get_cf(CF_METADATA, &meta_key(tree_type, MetaTag::LeafCount))?
             .map(|v| u32::from_be_bytes(v[..4].try_into().unwrap()))
             .unwrap_or(0);
         Ok(index)
@@ -167,7 +612,7 @@ impl MerkleForest {
         // Get the hash of this tree at this level and index.
         let opt = self
             .db
-            .get_cf(self.cf_trees()?, &node_key(&tree_type, level, index))?;
+            .get_cf(CF_TREE_LEVELS, &node_key(&tree_type, level, index))?;
 
         // Parse bytes to hash
         Ok(opt.map(|bytes| {
@@ -184,7 +629,7 @@ impl MerkleForest {
         Ok(self
             .db
             .get_cf(
-                self.cf_meta()?,
+                CF_METADATA,
                 &meta_key(&tree_type, MetaTag::LatestProcessedBlock),
             )?
             .map(|v| u64::from_be_bytes(v[..8].try_into().unwrap())))
@@ -200,7 +645,7 @@ impl MerkleForest {
         // The root is always at index 0 at the last level
         if let Some(v) = self
             .db
-            .get_cf(self.cf_trees()?, &node_key(&tree_type, DEPTH as u8, 0))?
+            .get_cf(CF_TREE_LEVELS, &node_key(tree_type, DEPTH as u8, 0))?
         {
             Ok(Some(FixedBytes::<32>::from(
                 <[u8; 32]>::try_from(&v[..]).unwrap(),
@@ -217,7 +662,7 @@ impl MerkleForest {
         num_leaves: u32,
     ) -> Result<()> {
         batch.put_cf(
-            self.cf_meta()?,
+            CF_METADATA,
             &meta_key(&tree_type, MetaTag::LeafCount),
             &num_leaves.to_be_bytes(),
         );
@@ -232,12 +677,11 @@ impl MerkleForest {
         index: u32,
         node: &FixedBytes<32>,
     ) -> Result<()> {
-        // TODO: use batches
-        self.db.put_cf(
-            self.cf_trees()?,
+        batch.put_cf(
+            CF_TREE_LEVELS,
             node_key(&tree_type, level as u8, index),
             node.as_slice(),
-        )?;
+        );
         Ok(())
     }
 
@@ -248,273 +692,1942 @@ impl MerkleForest {
         block_number: BlockNum,
     ) -> Result<()> {
         batch.put_cf(
-            self.cf_meta()?,
+            CF_METADATA,
             &meta_key(&tree_type, MetaTag::LatestProcessedBlock),
             &block_number.to_be_bytes(),
         );
         Ok(())
     }
 
-    // Append events to the Local Exit Tree of a specific Aggchain.
-    // TODO: Allow for batch insertions.
-    // TODO: Perform some extra checks.
-    pub fn append_events(
+    fn put_leaf_block(
         &self,
-        aggchain_id: AggchainId,
-        leaves: &[LeafBridge],
-        block: BlockNum,
-    ) -> eyre::Result<()> {
-        if !leaves
-            .windows(2)
-            .all(|w| w[0].bridge_event.depositCount + 1 == w[1].bridge_event.depositCount)
-        {
-            return Err(eyre!("depositCount is not increasing monotonically"));
-        }
+        batch: &mut WriteBatch,
+        tree_type: TreeType,
+        index: u32,
+        block_number: BlockNum,
+    ) -> Result<()> {
+        batch.put_cf(
+            CF_METADATA,
+            leaf_block_key(&tree_type, index),
+            block_number.to_be_bytes(),
+        );
+        Ok(())
+    }
 
-        // Ensure we don't store more than possible. Both are u32, should never happen.
-        let mut index = self.get_leaf_count(&TreeType::LocalExitTree(aggchain_id))?;
-        if index >= MAX_LEAVES {
-            return Err(eyre!(
-                "Deposit count exceeds maximum limit {} >= {}",
+    /// Get the block number in which a leaf of an append-only tree was inserted.
+    pub fn get_leaf_block(&self, tree_type: &TreeType, index: u32) -> Result<Option<BlockNum>> {
+        Ok(self
+            .db
+            .get_cf(CF_METADATA, &leaf_block_key(tree_type, index))?
+            .map(|v| u64::from_be_bytes(v[..8].try_into().unwrap())))
+    }
+
+    // Like `get_leaf_block`, for rollbacks. Key-value stores created before the blocks of
+    // the leaves were recorded can't be rolled back.
+    fn leaf_block_for_rollback(&self, tree_type: &TreeType, index: u32) -> Result<BlockNum> {
+        self.get_leaf_block(tree_type, index)?.ok_or_else(|| {
+            eyre!(
+                "Block of leaf {} of {:?} not found, the key-value store is too old to roll back. Resync required",
                 index,
-                MAX_LEAVES
-            ));
+                tree_type
+            )
+        })
+    }
+
+    // Number of leaves inserted up to `block` (inclusive). Leaves of append-only
+    // trees are inserted in block order, so we can binary search the first leaf
+    // inserted after `block`.
+    fn leaf_count_at_block(&self, tree_type: &TreeType, block: BlockNum) -> Result<u32> {
+        let mut lo = 0;
+        let mut hi = self.get_leaf_count(tree_type)?;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let leaf_block = self.leaf_block_for_rollback(tree_type, mid)?;
+            if leaf_block <= block {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
         }
+        Ok(lo)
+    }
 
-        // The local index shall match the first leaf's deposit count.
-        if index
-            != (leaves
-                .first()
-                .ok_or(eyre!("No leaves provided"))?
-                .bridge_event
-                .depositCount)
-        {
+    // Removes every leaf from `leaf_count` onwards in an append-only tree. Nodes only
+    // covering removed leaves are deleted, and the right-most path is rehashed so that
+    // the tree is identical to the one we had when it contained `leaf_count` leaves.
+    fn truncate_leaves(
+        &self,
+        batch: &mut WriteBatch,
+        tree_type: TreeType,
+        leaf_count: u32,
+    ) -> Result<()> {
+        if tree_type == TreeType::RollupExitTree {
+            return Err(eyre!("Only append-only trees can be truncated"));
+        }
+
+        let current_count = self.get_leaf_count(&tree_type)?;
+        if leaf_count > current_count {
             return Err(eyre!(
-                "Deposit count mismatch {} != {}",
-                index,
-                leaves.first().unwrap().bridge_event.depositCount
+                "Can't truncate to {} leaves, the tree only has {}",
+                leaf_count,
+                current_count
             ));
         }
+        if leaf_count == current_count {
+            return Ok(());
+        }
+        self.forget_frontier(tree_type)?;
+
+        // Roots the Local Exit Tree had with the removed leaves are no longer valid. Get
+        // them before any node is modified.
+        if let TreeType::LocalExitTree(aggchain_id) = tree_type {
+            for count in leaf_count + 1..=current_count {
+                if let Some(root) = self.get_root_at(tree_type, count)? {
+                    batch.delete_cf(CF_EXIT_ROOTS, exit_root_key(aggchain_id, &root));
+                }
+            }
 
-        if leaves.len() > 1 {
-            return Err(eyre!("Only one leaf can be inserted at a time by now"));
+            // Same for the removed bridge events, and their indexes.
+            for deposit_count in leaf_count..current_count {
+                if let Some(event) = self.get_bridge_event(aggchain_id, deposit_count)? {
+                    self.delete_bridge_indexes(batch, aggchain_id, &event)?;
+                }
+            }
+            batch.delete_range_cf(
+                CF_RAW_BRIDGE_LEAF,
+                raw_bridge_key(aggchain_id, leaf_count),
+                raw_bridge_key(aggchain_id, current_count),
+            );
+        }
+        if tree_type == TreeType::L1InfoTree {
+            for index in leaf_count..current_count {
+                let leaf = self
+                    .get_l1info_leaf(index)?
+                    .ok_or_else(|| eyre!("L1 info leaf {} not found", index))?;
+                batch.delete_cf(CF_L1_INFO_GERS, leaf.ger());
+            }
+            batch.delete_range_cf(
+                CF_L1_INFO_LEAVES,
+                leaf_count.to_be_bytes(),
+                current_count.to_be_bytes(),
+            );
         }
 
-        // TODO: Write in batches is blocked by this: https://github.com/rust-rocksdb/rust-rocksdb/pull/921
-        // By now you can't read your own writes within a batch.
-        let mut batch = WriteBatch::default();
+        // At each level, delete the nodes whose leaves are all removed. These are the
+        // ones starting at or after `leaf_count`, up to the last node in use.
+        for level in 0..=DEPTH {
+            let from = (leaf_count as u64).div_ceil(1 << level) as u32;
+            let to = (((current_count - 1) as u64 >> level) + 1) as u32;
+            if from < to {
+                batch.delete_range_cf(
+                    CF_TREE_LEVELS,
+                    node_key(&tree_type, level as u8, from),
+                    node_key(&tree_type, level as u8, to),
+                );
+            }
+        }
+        batch.delete_range_cf(
+            CF_METADATA,
+            leaf_block_key(&tree_type, leaf_count),
+            leaf_block_key(&tree_type, current_count),
+        );
 
-        // TODO: Only one leaf is supported by now
-        for leaf in leaves {
-            let mut node = leaf.hashed_leaf();
-            self.put_level(
-                &mut batch,
-                TreeType::LocalExitTree(aggchain_id),
-                0,
-                index,
-                &node,
-            )?;
+        // Rehash the path of the new last leaf. Everything at its right is now empty,
+        // so right siblings are always zero hashes.
+        if leaf_count > 0 {
+            let mut index = leaf_count - 1;
+            let mut node = self
+                .get_hash(tree_type, 0, index)?
+                .ok_or_else(|| eyre!("Leaf {} not found", index))?;
 
-            // Hash all the way up to the root.
             for level in 0..DEPTH {
-                let left;
-                let right;
-
-                if index % 2 == 0 {
-                    // left node
-                    left = node;
-                    right = self
-                        .get_hash(TreeType::LocalExitTree(aggchain_id), level as u8, index + 1)?
-                        .unwrap_or(self.zero[level]);
+                let (left, right) = if index.is_multiple_of(2) {
+                    (node, self.zero[level])
                 } else {
-                    // right node
-                    left = self
-                        .get_hash(TreeType::LocalExitTree(aggchain_id), level as u8, index - 1)?
-                        .unwrap_or(self.zero[level]);
-                    right = node;
+                    (
+                        self.get_hash(tree_type, level as u8, index - 1)?
+                            .unwrap_or(self.zero[level]),
+                        node,
+                    )
                 };
 
                 node = hash(&left, &right);
                 index /= 2;
-                self.put_level(
-                    &mut batch,
-                    TreeType::LocalExitTree(aggchain_id),
-                    level + 1,
-                    index,
-                    &node,
-                )?;
+                batch.put_cf(
+                    CF_TREE_LEVELS,
+                    node_key(&tree_type, level as u8 + 1, index),
+                    node.as_slice(),
+                );
             }
-
-            index += 1;
         }
 
-        // Persist meta: number of leaves + latest block
-        self.put_deposit_count(
-            &mut batch,
-            TreeType::LocalExitTree(aggchain_id),
-            leaves
-                .last()
-                .ok_or(eyre!("No leaves provided"))?
-                .bridge_event
-                .depositCount
-                + 1,
-        )?;
-        self.put_block_number(&mut batch, TreeType::LocalExitTree(aggchain_id), block)?;
-
-        let mut write_opts = WriteOptions::default();
-        write_opts.set_sync(false);
-        self.db.write_opt(batch, &write_opts)?;
+        self.put_deposit_count(batch, tree_type, leaf_count)?;
         Ok(())
     }
 
-    // Stores a Rollup's Local Exit Root at a specific index in the Rollup Exit Tree.
-    pub fn set_rollup_leaf(
-        &self,
-        index: u32,
-        leaf: &FixedBytes<32>,
-        block: BlockNum,
-    ) -> Result<()> {
-        // Aggchain index is shifted by 1. Meaning Aggchain 1 is placed at index 0, etc.
-        let index = index
-            .checked_sub(1)
-            .ok_or_else(|| eyre!("index cannot be 0"))?;
-
-        if index >= MAX_LEAVES {
-            return Err(eyre!(
-                "Index {} exceeds the maximum number of leaves ({})",
-                index,
-                MAX_LEAVES
-            ));
+    /// Truncates an append-only tree (Local Exit Tree or L1 Info Tree) to its first
+    /// `leaf_count` leaves. Every intermediate node and the latest processed block are
+    /// restored to the values they had right after inserting leaf `leaf_count - 1`.
+    pub fn truncate_to(&self, tree_type: TreeType, leaf_count: u32) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        self.truncate_leaves(&mut batch, tree_type, leaf_count)?;
+
+        // Each leaf insertion stores the block of the leaf as the latest processed one.
+        // With no leaves left, nothing was processed.
+        match leaf_count.checked_sub(1) {
+            Some(last) => {
+                let block = self.leaf_block_for_rollback(&tree_type, last)?;
+                self.put_block_number(&mut batch, tree_type, block)?;
+            }
+            None => batch.delete_cf(
+                CF_METADATA,
+                meta_key(&tree_type, MetaTag::LatestProcessedBlock),
+            ),
         }
 
-        // Retrieve the current leaf count from the metadata
-        let current_count = self.get_leaf_count(&TreeType::RollupExitTree)?;
+        self.db.write(batch)?;
+        Ok(())
+    }
 
-        // Update the leaf count only if the new index is higher
-        let new_count = (index + 1).max(current_count);
+    /// Rolls an append-only tree back to the state it had right after processing
+    /// `block`. Every leaf inserted in a later block is removed. Used to recover from reorgs.
+    pub fn rollback_to_block(&self, tree_type: TreeType, block: BlockNum) -> Result<()> {
+        if tree_type == TreeType::RollupExitTree {
+            return self.rollback_rollup_exit_tree(block);
+        }
+        let leaf_count = self.leaf_count_at_block(&tree_type, block)?;
 
-        // TODO: Use batches
         let mut batch = WriteBatch::default();
+        self.truncate_leaves(&mut batch, tree_type, leaf_count)?;
+        if let TreeType::LocalExitTree(aggchain_id) = tree_type {
+            self.delete_bridge_logs_after(&mut batch, aggchain_id, block)?;
+        }
+        self.put_block_number(&mut batch, tree_type, block)?;
 
-        // Level-0 write (the leaf itself)
-        let mut node = *leaf;
-        self.put_level(&mut batch, TreeType::RollupExitTree, 0, index, &node)?;
+        self.db.write(batch)?;
+        Ok(())
+    }
 
-        let mut idx = index;
-        for level in 0..DEPTH {
-            let (left, right) = if idx % 2 == 0 {
-                (
-                    node,
-                    self.get_hash(TreeType::RollupExitTree, level as u8, idx + 1)?
-                        .unwrap_or(self.zero[level]),
-                )
-            } else {
-                (
-                    self.get_hash(TreeType::RollupExitTree, level as u8, idx - 1)?
-                        .unwrap_or(self.zero[level]),
-                    node,
-                )
-            };
+    // The Rollup Exit Tree is not append-only, so it's rebuilt from the leaves it had at
+    // `block`. The registry writes logged after `block` are undone too.
+    fn rollback_rollup_exit_tree(&self, block: BlockNum) -> Result<()> {
+        let tree_type = TreeType::RollupExitTree;
+        let leaves = self.get_rollup_leaves_at(block, u64::MAX)?;
+        let current_count = self.get_leaf_count(&tree_type)?;
 
-            node = hash(&left, &right);
-            idx /= 2;
-            self.put_level(&mut batch, TreeType::RollupExitTree, level + 1, idx, &node)?;
+        let mut batch = WriteBatch::default();
+        for rollup_id in 1..=current_count {
+            let from = bridge_log_block_key(rollup_id, block + 1, 0);
+            let to = bridge_log_block_key(rollup_id + 1, 0, 0);
+            let iter = self.db.iterator_cf(
+                CF_ROLLUP_LEAVES,
+                IteratorMode::From(&from, Direction::Forward),
+            );
+            for item in iter {
+                let (k, v) = item?;
+                if k[..4] != rollup_id.to_be_bytes() {
+                    break;
+                }
+                // Roots the tree already had at `block` are kept.
+                let root = &v[32..64];
+                if let Some(position) = self.db.get_cf(CF_ROLLUP_EXIT_ROOTS, root)?
+                    && u64::from_be_bytes(position[..8].try_into()?) > block
+                {
+                    batch.delete_cf(CF_ROLLUP_EXIT_ROOTS, root);
+                }
+            }
+            batch.delete_range_cf(CF_ROLLUP_LEAVES, from, to);
         }
 
-        // Update the leaf count in the metadata
-        self.put_deposit_count(&mut batch, TreeType::RollupExitTree, new_count)?;
-        self.put_block_number(&mut batch, TreeType::RollupExitTree, block)?;
+        // Rewrite all the nodes.
+        for level in 0..=DEPTH {
+            batch.delete_range_cf(
+                CF_TREE_LEVELS,
+                node_key(&tree_type, level as u8, 0),
+                node_key(&tree_type, level as u8 + 1, 0),
+            );
+        }
+        for (level, nodes) in self.sparse_levels(&leaves).iter().enumerate() {
+            for (index, node) in nodes {
+                self.put_level(&mut batch, tree_type, level, *index, node)?;
+            }
+        }
+        let leaf_count = leaves.keys().next_back().map_or(0, |index| index + 1);
+        self.put_deposit_count(&mut batch, tree_type, leaf_count)?;
 
-        // TODO: Ensure when updating block number there are no future transactions
-        // with the same one. Otherwise it won't be really in sync until that block.
+        self.rollback_registry(&mut batch, block)?;
+        self.put_block_number(&mut batch, tree_type, block)?;
 
-        let mut wo = WriteOptions::default();
-        wo.set_sync(false);
-        self.db.write_opt(batch, &wo)?;
+        self.db.write(batch)?;
         Ok(())
     }
 
-    // TODO: Dirty function, lots of copy pasted logic from append_events. Generalize.
-    pub fn append_l1info_leaf(&self, leaf: &FixedBytes<32>, block: BlockNum) -> eyre::Result<()> {
-        let mut index = self.get_leaf_count(&TreeType::L1InfoTree)?;
-        let mut batch = WriteBatch::default();
-        let mut node = leaf.clone();
-
-        self.put_level(&mut batch, TreeType::L1InfoTree, 0, index, &node)?;
-
-        for level in 0..DEPTH {
-            let left;
-            let right;
-
-            if index % 2 == 0 {
-                // left node
-                left = node;
-                right = self
-                    .get_hash(TreeType::L1InfoTree, level as u8, index + 1)?
-                    .unwrap_or(self.zero[level]);
-            } else {
-                // right node
-                left = self
-                    .get_hash(TreeType::L1InfoTree, level as u8, index - 1)?
-                    .unwrap_or(self.zero[level]);
-                right = node;
-            };
-
-            node = hash(&left, &right);
-            index /= 2;
-            self.put_level(&mut batch, TreeType::L1InfoTree, level + 1, index, &node)?;
+    // Restores the rollups and rollup types written after `block`. Only the first write of
+    // each entry counts, it replaced the value the entry had at `block`.
+    fn rollback_registry(&self, batch: &mut WriteBatch, block: BlockNum) -> Result<()> {
+        let from = registry_log_key(block + 1, 0);
+        let to = registry_log_key(BlockNum::MAX, u64::MAX);
+        let iter = self.db.iterator_cf(
+            CF_REGISTRY_LOGS,
+            IteratorMode::From(&from, Direction::Forward),
+        );
+        let mut restored = HashSet::new();
+        for item in iter {
+            let (_, v) = item?;
+            if !restored.insert(v[..5].to_vec()) {
+                continue;
+            }
+            let (id, previous) = (&v[1..5], &v[5..]);
+            match v[0] {
+                x if x == RegistryLogKind::RollupType as u8 => {
+                    if previous.is_empty() {
+                        batch.delete_cf(CF_ROLLUP_TYPES, id);
+                    } else {
+                        batch.put_cf(CF_ROLLUP_TYPES, id, previous);
+                    }
+                }
+                x if x == RegistryLogKind::Rollup as u8 => {
+                    if let Some(current) = self.get_rollup(u32::from_be_bytes(id.try_into()?))? {
+                        batch.delete_cf(CF_ROLLUPS, id);
+                        batch.delete_cf(CF_ROLLUPS_BY_CHAIN_ID, current.chain_id.to_be_bytes());
+                        batch.delete_cf(CF_ROLLUPS_BY_ADDRESS, current.rollup_address);
+                    }
+                    if !previous.is_empty() {
+                        let rollup = RollupInfo::from_bytes(previous)?;
+                        batch.put_cf(CF_ROLLUPS, id, previous);
+                        batch.put_cf(CF_ROLLUPS_BY_CHAIN_ID, rollup.chain_id.to_be_bytes(), id);
+                        batch.put_cf(CF_ROLLUPS_BY_ADDRESS, rollup.rollup_address, id);
+                    }
+                }
+                kind => return Err(eyre!("Unknown registry log kind {}", kind)),
+            }
         }
+        batch.delete_range_cf(CF_REGISTRY_LOGS, from, to);
+        Ok(())
+    }
 
-        // Persist meta: number of leaves + latest block
-        self.put_deposit_count(
-            &mut batch,
-            TreeType::L1InfoTree,
-            self.get_leaf_count(&TreeType::L1InfoTree)? + 1,
-        )?;
-
-        // TODO: Ensure when updating block number there are no future transactions
-        // with the same one. Otherwise it won't be really in sync until that block.
-        self.put_block_number(&mut batch, TreeType::L1InfoTree, block)?;
-
-        let mut write_opts = WriteOptions::default();
-        write_opts.set_sync(false);
-        self.db.write_opt(batch, &write_opts)?;
+    /// Stores the hashes of some blocks seen by `indexer`. Used to detect reorgs.
+    pub fn put_block_hashes(&self, indexer: &str, hashes: &[(BlockNum, B256)]) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for (block, hash) in hashes {
+            batch.put_cf(
+                CF_BLOCK_HASHES,
+                block_hash_key(indexer, *block),
+                hash.as_slice(),
+            );
+        }
+        self.db.write(batch)?;
         Ok(())
     }
 
-    pub fn merkle_proof(&self, tree_type: TreeType, index: u64) -> Result<[FixedBytes<32>; DEPTH]> {
-        let mut index = index;
-        match tree_type {
-            TreeType::RollupExitTree => {
-                // TODO: Maybe dirty. In the Rollup Exit Tree, aggchain 1 is placed at index 0, etc.
-                index = index - 1;
+    /// Returns all the block hashes stored by `indexer`, sorted by block number.
+    pub fn get_block_hashes(&self, indexer: &str) -> Result<Vec<(BlockNum, B256)>> {
+        let prefix = block_hash_prefix(indexer);
+        let mut hashes = Vec::new();
+        for item in self.db.iterator_cf(
+            CF_BLOCK_HASHES,
+            IteratorMode::From(&prefix, Direction::Forward),
+        ) {
+            let (key, value) = item?;
+            if !key.starts_with(&prefix) {
+                break;
             }
-            _ => {}
-        }
-        let mut proof = [FixedBytes::<32>::default(); DEPTH];
-        let mut idx = index;
-        for level in 0..DEPTH {
-            let sib = idx ^ 1;
-            proof[level] = match self.db.get_cf(
-                self.cf_trees()?,
-                &node_key(&tree_type, level as u8, sib as u32),
-            )? {
-                Some(v) => FixedBytes::<32>::from(<[u8; 32]>::try_from(&v[..]).unwrap()),
-                None => self.zero[level],
-            };
-            idx >>= 1;
+            let block = u64::from_be_bytes(key[prefix.len()..].try_into()?);
+            hashes.push((block, B256::try_from(&value[..])?));
         }
-        Ok(proof)
+        Ok(hashes)
     }
-}
 
-#[inline(always)]
-fn hash(l: &FixedBytes<32>, r: &FixedBytes<32>) -> FixedBytes<32> {
-    let mut buf = [0u8; 64];
-    buf[..32].copy_from_slice(l.as_slice());
-    buf[32..].copy_from_slice(r.as_slice());
-    keccak256(buf)
-}
+    /// Deletes the hashes stored by `indexer` for the blocks in `[from, to)`.
+    pub fn delete_block_hashes(&self, indexer: &str, from: BlockNum, to: BlockNum) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(
+            CF_BLOCK_HASHES,
+            block_hash_key(indexer, from),
+            block_hash_key(indexer, to),
+        );
+        self.db.write(batch)
+    }
+
+    /// Last block processed by `indexer`, None if it never processed any.
+    pub fn get_indexer_cursor(&self, indexer: &str) -> Result<Option<BlockNum>> {
+        self.db
+            .get_cf(CF_INDEXER_CURSORS, indexer.as_bytes())?
+            .map(|v| Ok(u64::from_be_bytes(v.as_slice().try_into()?)))
+            .transpose()
+    }
+
+    pub fn put_indexer_cursor(&self, indexer: &str, block: BlockNum) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.put_cf(CF_INDEXER_CURSORS, indexer.as_bytes(), block.to_be_bytes());
+        self.db.write(batch)
+    }
+
+    // Append events to the Local Exit Tree of a specific Aggchain. Each leaf comes with the
+    // block it was emitted in. All leaves are inserted in a single batch, and the latest
+    // processed block is set to the one of the last leaf.
+    // TODO: Perform some extra checks.
+    pub fn append_events(
+        &self,
+        aggchain_id: AggchainId,
+        leaves: &[(LeafBridge, BlockNum)],
+    ) -> eyre::Result<()> {
+        let mut batch = WriteBatch::default();
+        let frontier = self.put_events(&mut batch, aggchain_id, leaves)?;
+
+        self.db.write(batch)?;
+        self.cache_frontier(TreeType::LocalExitTree(aggchain_id), frontier)
+    }
+
+    // Same as `append_events`, but the deposits are also stored as emitted, so that they
+    // can be looked up later on. See `get_bridge_event`. The rest of the logs of the bridge
+    // of `aggchain_id` are stored in the same batch.
+    pub fn append_bridge_events(
+        &self,
+        aggchain_id: AggchainId,
+        events: &BridgeEvents,
+    ) -> eyre::Result<()> {
+        let leaves: Vec<(LeafBridge, BlockNum)> = events
+            .deposits
+            .iter()
+            .map(|e| (e.leaf(), e.block_number))
+            .collect();
+
+        let mut batch = WriteBatch::default();
+        let frontier = if leaves.is_empty() {
+            None
+        } else {
+            Some(self.put_events(&mut batch, aggchain_id, &leaves)?)
+        };
+        for claim in &events.claims {
+            let global_index = GlobalIndex::decode(claim.global_index);
+            let key = claim_key(
+                aggchain_id,
+                global_index.source_network(),
+                global_index.local_index,
+            );
+            batch.put_cf(CF_CLAIMS, key, claim.to_bytes());
+            self.put_bridge_log_block(
+                &mut batch,
+                aggchain_id,
+                claim.block_number,
+                claim.log_index,
+                BridgeLogKind::Claim,
+                &key,
+            )?;
+        }
+        for token in &events.wrapped_tokens {
+            let key = wrapped_token_key(aggchain_id, token.origin_network, &token.origin_address);
+            batch.put_cf(CF_WRAPPED_TOKENS, key, token.to_bytes());
+            batch.put_cf(
+                CF_WRAPPED_TOKENS_BY_ADDRESS,
+                wrapped_address_key(aggchain_id, &token.wrapped_address),
+                key,
+            );
+            self.put_bridge_log_block(
+                &mut batch,
+                aggchain_id,
+                token.block_number,
+                token.log_index,
+                BridgeLogKind::WrappedToken,
+                &key,
+            )?;
+        }
+        for unset in &events.unset_claims {
+            let key = claim_key(aggchain_id, unset.source_network, unset.leaf_index);
+            batch.put_cf(CF_UNSET_CLAIMS, key, unset.to_bytes());
+            self.put_bridge_log_block(
+                &mut batch,
+                aggchain_id,
+                unset.block_number,
+                unset.log_index,
+                BridgeLogKind::UnsetClaim,
+                &key,
+            )?;
+        }
+        for event in &events.sovereign_events {
+            batch.put_cf(
+                CF_SOVEREIGN_EVENTS,
+                bridge_log_block_key(aggchain_id, event.block_number, event.log_index),
+                event.to_bytes(),
+            );
+        }
+        for event in &events.deposits {
+            let deposit_count = event.bridge_event.depositCount;
+            batch.put_cf(
+                CF_RAW_BRIDGE_LEAF,
+                raw_bridge_key(aggchain_id, deposit_count),
+                event.to_bytes(),
+            );
+            batch.put_cf(
+                CF_BRIDGE_BY_TX,
+                bridge_index_key(event.tx_hash.as_slice(), aggchain_id, deposit_count),
+                [],
+            );
+            batch.put_cf(
+                CF_BRIDGE_BY_DESTINATION,
+                bridge_index_key(
+                    event.bridge_event.destinationAddress.as_slice(),
+                    aggchain_id,
+                    deposit_count,
+                ),
+                [],
+            );
+        }
+
+        self.db.write(batch)?;
+        match frontier {
+            Some(frontier) => self.cache_frontier(TreeType::LocalExitTree(aggchain_id), frontier),
+            None => Ok(()),
+        }
+    }
+
+    fn put_bridge_log_block(
+        &self,
+        batch: &mut WriteBatch,
+        aggchain_id: AggchainId,
+        block: BlockNum,
+        log_index: u64,
+        kind: BridgeLogKind,
+        key: &[u8],
+    ) -> Result<()> {
+        let mut value = vec![kind as u8];
+        value.extend_from_slice(key);
+        batch.put_cf(
+            CF_BRIDGE_LOGS_BY_BLOCK,
+            bridge_log_block_key(aggchain_id, block, log_index),
+            value,
+        );
+        Ok(())
+    }
+
+    // Deletes the bridge logs (other than deposits) of `aggchain_id` emitted after `block`.
+    fn delete_bridge_logs_after(
+        &self,
+        batch: &mut WriteBatch,
+        aggchain_id: AggchainId,
+        block: BlockNum,
+    ) -> Result<()> {
+        let from = bridge_log_block_key(aggchain_id, block + 1, 0);
+        let to = bridge_log_block_key(aggchain_id, BlockNum::MAX, u64::MAX);
+        let iter = self.db.iterator_cf(
+            CF_BRIDGE_LOGS_BY_BLOCK,
+            IteratorMode::From(&from, Direction::Forward),
+        );
+        for item in iter {
+            let (k, v) = item?;
+            if k[..4] != aggchain_id.to_be_bytes() {
+                break;
+            }
+            let key = &v[1..];
+            match v[0] {
+                x if x == BridgeLogKind::Claim as u8 => batch.delete_cf(CF_CLAIMS, key),
+                x if x == BridgeLogKind::WrappedToken as u8 => {
+                    if let Some(token) = self.db.get_cf(CF_WRAPPED_TOKENS, key)? {
+                        let token = WrappedToken::from_bytes(&token)?;
+                        batch.delete_cf(
+                            CF_WRAPPED_TOKENS_BY_ADDRESS,
+                            wrapped_address_key(aggchain_id, &token.wrapped_address),
+                        );
+                    }
+                    batch.delete_cf(CF_WRAPPED_TOKENS, key);
+                }
+                x if x == BridgeLogKind::UnsetClaim as u8 => batch.delete_cf(CF_UNSET_CLAIMS, key),
+                kind => return Err(eyre!("Unknown bridge log kind {}", kind)),
+            }
+        }
+        batch.delete_range_cf(CF_BRIDGE_LOGS_BY_BLOCK, from, to);
+        batch.delete_range_cf(CF_SOVEREIGN_EVENTS, from, to);
+        Ok(())
+    }
+
+    /// Wrapped token deployed by the bridge of `aggchain_id` for the token
+    /// `origin_address` of `origin_network`.
+    pub fn get_wrapped_token(
+        &self,
+        aggchain_id: AggchainId,
+        origin_network: AggchainId,
+        origin_address: &Address,
+    ) -> Result<Option<WrappedToken>> {
+        self.db
+            .get_cf(
+                CF_WRAPPED_TOKENS,
+                &wrapped_token_key(aggchain_id, origin_network, origin_address),
+            )?
+            .map(|v| WrappedToken::from_bytes(&v))
+            .transpose()
+    }
+
+    /// Wrapped token of the bridge of `aggchain_id` deployed at `wrapped_address`.
+    pub fn get_wrapped_token_by_address(
+        &self,
+        aggchain_id: AggchainId,
+        wrapped_address: &Address,
+    ) -> Result<Option<WrappedToken>> {
+        let Some(key) = self.db.get_cf(
+            CF_WRAPPED_TOKENS_BY_ADDRESS,
+            &wrapped_address_key(aggchain_id, wrapped_address),
+        )?
+        else {
+            return Ok(None);
+        };
+        self.db
+            .get_cf(CF_WRAPPED_TOKENS, &key)?
+            .map(|v| WrappedToken::from_bytes(&v))
+            .transpose()
+    }
+
+    /// Every wrapped token deployed by the bridge of `aggchain_id`.
+    pub fn get_wrapped_tokens(&self, aggchain_id: AggchainId) -> Result<Vec<WrappedToken>> {
+        let prefix = aggchain_id.to_be_bytes();
+        let mut tokens = Vec::new();
+        let iter = self.db.iterator_cf(
+            CF_WRAPPED_TOKENS,
+            IteratorMode::From(&prefix, Direction::Forward),
+        );
+        for item in iter {
+            let (k, v) = item?;
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            tokens.push(WrappedToken::from_bytes(&v)?);
+        }
+        Ok(tokens)
+    }
+
+    /// Claim made in `aggchain_id` of the deposit `deposit_count` of `source_network`.
+    pub fn get_claim(
+        &self,
+        aggchain_id: AggchainId,
+        source_network: AggchainId,
+        deposit_count: u32,
+    ) -> Result<Option<RawClaimEvent>> {
+        self.db
+            .get_cf(
+                CF_CLAIMS,
+                &claim_key(aggchain_id, source_network, deposit_count),
+            )?
+            .map(|v| RawClaimEvent::from_bytes(&v))
+            .transpose()
+    }
+
+    /// Latest unset of the claim of the deposit `deposit_count` of `source_network`,
+    /// made in the sovereign chain `aggchain_id`.
+    pub fn get_unset_claim(
+        &self,
+        aggchain_id: AggchainId,
+        source_network: AggchainId,
+        deposit_count: u32,
+    ) -> Result<Option<UnsetClaim>> {
+        self.db
+            .get_cf(
+                CF_UNSET_CLAIMS,
+                &claim_key(aggchain_id, source_network, deposit_count),
+            )?
+            .map(|v| UnsetClaim::from_bytes(&v))
+            .transpose()
+    }
+
+    /// Every claim unset in the sovereign chain `aggchain_id`.
+    pub fn get_unset_claims(&self, aggchain_id: AggchainId) -> Result<Vec<UnsetClaim>> {
+        let prefix = aggchain_id.to_be_bytes();
+        let mut unset_claims = Vec::new();
+        let iter = self.db.iterator_cf(
+            CF_UNSET_CLAIMS,
+            IteratorMode::From(&prefix, Direction::Forward),
+        );
+        for item in iter {
+            let (k, v) = item?;
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            unset_claims.push(UnsetClaim::from_bytes(&v)?);
+        }
+        Ok(unset_claims)
+    }
+
+    /// Sovereign chain events of `aggchain_id`, in the order they were emitted.
+    pub fn get_sovereign_events(&self, aggchain_id: AggchainId) -> Result<Vec<SovereignEvent>> {
+        let prefix = aggchain_id.to_be_bytes();
+        let mut events = Vec::new();
+        let iter = self.db.iterator_cf(
+            CF_SOVEREIGN_EVENTS,
+            IteratorMode::From(&prefix, Direction::Forward),
+        );
+        for item in iter {
+            let (k, v) = item?;
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            events.push(SovereignEvent::from_bytes(&v)?);
+        }
+        Ok(events)
+    }
+
+    /// Current configuration of the sovereign chain `aggchain_id`. Empty if it's not a
+    /// sovereign chain.
+    pub fn get_sovereign_config(&self, aggchain_id: AggchainId) -> Result<SovereignConfig> {
+        let mut config = SovereignConfig::default();
+        for event in self.get_sovereign_events(aggchain_id)? {
+            config.apply(&event.change);
+        }
+        Ok(config)
+    }
+
+    fn delete_bridge_indexes(
+        &self,
+        batch: &mut WriteBatch,
+        aggchain_id: AggchainId,
+        event: &RawBridgeEvent,
+    ) -> Result<()> {
+        let deposit_count = event.bridge_event.depositCount;
+        batch.delete_cf(
+            CF_BRIDGE_BY_TX,
+            bridge_index_key(event.tx_hash.as_slice(), aggchain_id, deposit_count),
+        );
+        batch.delete_cf(
+            CF_BRIDGE_BY_DESTINATION,
+            bridge_index_key(
+                event.bridge_event.destinationAddress.as_slice(),
+                aggchain_id,
+                deposit_count,
+            ),
+        );
+        Ok(())
+    }
+
+    /// BridgeEvent of `aggchain_id` with the given deposit count.
+    pub fn get_bridge_event(
+        &self,
+        aggchain_id: AggchainId,
+        deposit_count: u32,
+    ) -> Result<Option<RawBridgeEvent>> {
+        self.db
+            .get_cf(
+                CF_RAW_BRIDGE_LEAF,
+                &raw_bridge_key(aggchain_id, deposit_count),
+            )?
+            .map(|v| RawBridgeEvent::from_bytes(&v))
+            .transpose()
+    }
+
+    // Resolves every event in a secondary index whose key starts with `prefix`.
+    fn get_bridge_events_by_prefix(
+        &self,
+        cf: &'static str,
+        prefix: &[u8],
+    ) -> Result<Vec<(AggchainId, RawBridgeEvent)>> {
+        let mut events = Vec::new();
+        let iter = self
+            .db
+            .iterator_cf(cf, IteratorMode::From(prefix, Direction::Forward));
+        for item in iter {
+            let (k, _) = item?;
+            if !k.starts_with(prefix) {
+                break;
+            }
+            let aggchain_id = u32::from_be_bytes(k[prefix.len()..prefix.len() + 4].try_into()?);
+            let deposit_count = u32::from_be_bytes(k[prefix.len() + 4..].try_into()?);
+            let event = self
+                .get_bridge_event(aggchain_id, deposit_count)?
+                .ok_or_else(|| {
+                    eyre!(
+                        "Bridge event {} of aggchain {} not found",
+                        deposit_count,
+                        aggchain_id
+                    )
+                })?;
+            events.push((aggchain_id, event));
+        }
+        Ok(events)
+    }
+
+    /// BridgeEvents emitted in the transaction `tx_hash`, with the aggchain they belong to.
+    pub fn get_bridge_events_by_tx(
+        &self,
+        tx_hash: &B256,
+    ) -> Result<Vec<(AggchainId, RawBridgeEvent)>> {
+        self.get_bridge_events_by_prefix(CF_BRIDGE_BY_TX, tx_hash.as_slice())
+    }
+
+    /// BridgeEvents sent to `destination`, in any aggchain.
+    pub fn get_bridge_events_by_destination(
+        &self,
+        destination: &Address,
+    ) -> Result<Vec<(AggchainId, RawBridgeEvent)>> {
+        self.get_bridge_events_by_prefix(CF_BRIDGE_BY_DESTINATION, destination.as_slice())
+    }
+
+    // Validates the leaves and writes them to the Local Exit Tree of `aggchain_id`. Returns
+    // the new frontier and leaf count, to be cached once the batch is written.
+    fn put_events(
+        &self,
+        batch: &mut WriteBatch,
+        aggchain_id: AggchainId,
+        leaves: &[(LeafBridge, BlockNum)],
+    ) -> eyre::Result<(u32, [FixedBytes<32>; DEPTH])> {
+        let tree_type = TreeType::LocalExitTree(aggchain_id);
+
+        if !leaves
+            .windows(2)
+            .all(|w| w[0].0.bridge_event.depositCount + 1 == w[1].0.bridge_event.depositCount)
+        {
+            return Err(eyre!("depositCount is not increasing monotonically"));
+        }
+
+        // The local index shall match the first leaf's deposit count.
+        let (first, _) = leaves.first().ok_or(eyre!("No leaves provided"))?;
+        let index = self.get_leaf_count(&tree_type)?;
+        if index != first.bridge_event.depositCount {
+            return Err(eyre!(
+                "Deposit count mismatch {} != {}",
+                index,
+                first.bridge_event.depositCount
+            ));
+        }
+
+        let hashed: Vec<(FixedBytes<32>, BlockNum)> = leaves
+            .iter()
+            .map(|(l, block)| (l.hashed_leaf(), *block))
+            .collect();
+        self.put_appended_leaves(batch, tree_type, &hashed)
+    }
+
+    // Insertion core of the append-only trees: appends the hashed leaves, with the block
+    // each one was inserted in, and every node above them. Checks common to all trees, and
+    // the exit roots of the Local Exit Trees, are done here. Indexes built from the data of
+    // the leaves (bridge events, L1 Info Tree leaves) are up to the callers that have it.
+    // Returns the new leaf count and frontier, to be cached once the batch is written.
+    fn put_appended_leaves(
+        &self,
+        batch: &mut WriteBatch,
+        tree_type: TreeType,
+        leaves: &[(FixedBytes<32>, BlockNum)],
+    ) -> Result<(u32, [FixedBytes<32>; DEPTH])> {
+        if !tree_type.is_append_only() {
+            return Err(eyre!("Leaves can't be appended to {:?}", tree_type));
+        }
+        let (_, first_block) = leaves.first().ok_or(eyre!("No leaves provided"))?;
+        let (_, last_block) = leaves.last().ok_or(eyre!("No leaves provided"))?;
+
+        if !leaves.windows(2).all(|w| w[0].1 <= w[1].1) {
+            return Err(eyre!("Leaves are not sorted by block"));
+        }
+
+        // Don't go back in time.
+        if let Some(latest) = self.get_latest_block(tree_type)?
+            && *first_block < latest
+        {
+            return Err(eyre!(
+                "Leaf block {} is older than the latest processed one {}",
+                first_block,
+                latest
+            ));
+        }
+
+        // Ensure we don't store more than possible.
+        let index = self.get_leaf_count(&tree_type)?;
+        let leaf_count = index as u64 + leaves.len() as u64;
+        if leaf_count > MAX_LEAVES as u64 {
+            return Err(eyre!(
+                "Leaf count exceeds maximum limit {} + {} > {}",
+                index,
+                leaves.len(),
+                MAX_LEAVES
+            ));
+        }
+        let leaf_count = leaf_count as u32;
+
+        for (i, (_, block)) in leaves.iter().enumerate() {
+            self.put_leaf_block(batch, tree_type, index + i as u32, *block)?;
+        }
+
+        let hashed: Vec<FixedBytes<32>> = leaves.iter().map(|(leaf, _)| *leaf).collect();
+        let mut frontier = self.cached_frontier(tree_type, index)?;
+        self.put_leaves(batch, tree_type, index, &hashed, &frontier)?;
+
+        for (i, leaf) in hashed.iter().enumerate() {
+            let count = index + i as u32 + 1;
+            self.push_frontier(&mut frontier, count, leaf);
+
+            // Index the root after each leaf, so that any exit root seen onchain can be
+            // mapped to the leaves it contains.
+            if let TreeType::LocalExitTree(aggchain_id) = tree_type {
+                batch.put_cf(
+                    CF_EXIT_ROOTS,
+                    exit_root_key(aggchain_id, &self.frontier_root(&frontier, count)),
+                    count.to_be_bytes(),
+                );
+            }
+        }
+
+        // Persist meta: number of leaves + latest block
+        self.put_deposit_count(batch, tree_type, leaf_count)?;
+        self.put_block_number(batch, tree_type, *last_block)?;
+        Ok((leaf_count, frontier))
+    }
+
+    /// Appends a hashed leaf, inserted in `block`, to a Local Exit Tree. Returns its index.
+    /// The L1 Info Tree needs the data of its leaves, see `append_l1info_leaf`.
+    pub fn append_leaf_hash(
+        &self,
+        tree_type: TreeType,
+        leaf: &FixedBytes<32>,
+        block: BlockNum,
+    ) -> Result<u32> {
+        if tree_type == TreeType::L1InfoTree {
+            return Err(eyre!(
+                "Leaves of {:?} can only be appended with their data",
+                tree_type
+            ));
+        }
+        let mut batch = WriteBatch::default();
+        let (leaf_count, frontier) =
+            self.put_appended_leaves(&mut batch, tree_type, &[(*leaf, block)])?;
+        self.db.write(batch)?;
+        self.cache_frontier(tree_type, (leaf_count, frontier))?;
+        Ok(leaf_count - 1)
+    }
+
+    // Insertion core of the trees that aren't append-only: sets the leaf at `index`, then
+    // rehashes its path with the siblings stored in the DB. Returns the new root.
+    fn put_leaf_at(
+        &self,
+        batch: &mut WriteBatch,
+        tree_type: TreeType,
+        index: u32,
+        leaf: &FixedBytes<32>,
+        block: BlockNum,
+    ) -> Result<FixedBytes<32>> {
+        if tree_type.is_append_only() {
+            return Err(eyre!("Leaves of {:?} can only be appended", tree_type));
+        }
+        if index >= MAX_LEAVES {
+            return Err(eyre!(
+                "Index {} exceeds the maximum number of leaves ({})",
+                index,
+                MAX_LEAVES
+            ));
+        }
+
+        let mut node = *leaf;
+        self.put_level(batch, tree_type, 0, index, &node)?;
+        let mut idx = index;
+        for level in 0..DEPTH {
+            let sibling = self
+                .get_hash(tree_type, level as u8, idx ^ 1)?
+                .unwrap_or(self.zero[level]);
+            node = if idx.is_multiple_of(2) {
+                hash(&node, &sibling)
+            } else {
+                hash(&sibling, &node)
+            };
+            idx /= 2;
+            self.put_level(batch, tree_type, level + 1, idx, &node)?;
+        }
+
+        // The leaf count is the index of the last leaf plus one.
+        let current_count = self.get_leaf_count(&tree_type)?;
+        self.put_deposit_count(batch, tree_type, (index + 1).max(current_count))?;
+        self.put_block_number(batch, tree_type, block)?;
+        Ok(node)
+    }
+
+    // Left siblings of the path of the next leaf to be inserted in an append-only tree.
+    // At `level`, it is only meaningful if that bit of `leaf_count` is set.
+    fn frontier(&self, tree_type: TreeType, leaf_count: u32) -> Result<[FixedBytes<32>; DEPTH]> {
+        let mut frontier = [FixedBytes::<32>::default(); DEPTH];
+        for (level, node) in frontier.iter_mut().enumerate() {
+            if (leaf_count >> level) & 1 == 1 {
+                *node = self
+                    .get_hash(tree_type, level as u8, (leaf_count >> level) - 1)?
+                    .unwrap_or(self.zero[level]);
+            }
+        }
+        Ok(frontier)
+    }
+
+    // Same as `frontier`, but without reading the tree if the frontier of the last append
+    // is still valid, which is the case unless the tree was truncated or repaired.
+    fn cached_frontier(
+        &self,
+        tree_type: TreeType,
+        leaf_count: u32,
+    ) -> Result<[FixedBytes<32>; DEPTH]> {
+        let frontiers = self
+            .frontiers
+            .lock()
+            .map_err(|_| eyre!("Frontier cache lock poisoned"))?;
+        match frontiers.get(&tree_type) {
+            Some((count, frontier)) if *count == leaf_count => Ok(*frontier),
+            _ => {
+                drop(frontiers);
+                self.frontier(tree_type, leaf_count)
+            }
+        }
+    }
+
+    fn cache_frontier(
+        &self,
+        tree_type: TreeType,
+        (leaf_count, frontier): (u32, [FixedBytes<32>; DEPTH]),
+    ) -> Result<()> {
+        self.frontiers
+            .lock()
+            .map_err(|_| eyre!("Frontier cache lock poisoned"))?
+            .insert(tree_type, (leaf_count, frontier));
+        Ok(())
+    }
+
+    fn forget_frontier(&self, tree_type: TreeType) -> Result<()> {
+        self.frontiers
+            .lock()
+            .map_err(|_| eyre!("Frontier cache lock poisoned"))?
+            .remove(&tree_type);
+        Ok(())
+    }
+
+    // Updates the frontier after inserting a leaf, `leaf_count` already including it.
+    fn push_frontier(
+        &self,
+        frontier: &mut [FixedBytes<32>; DEPTH],
+        leaf_count: u32,
+        leaf: &FixedBytes<32>,
+    ) {
+        let mut node = *leaf;
+        for (level, sibling) in frontier.iter_mut().enumerate() {
+            if (leaf_count >> level) & 1 == 1 {
+                *sibling = node;
+                return;
+            }
+            node = hash(sibling, &node);
+        }
+    }
+
+    fn frontier_root(&self, frontier: &[FixedBytes<32>; DEPTH], leaf_count: u32) -> FixedBytes<32> {
+        let mut node = self.zero[0];
+        for (level, sibling) in frontier.iter().enumerate() {
+            node = if (leaf_count >> level) & 1 == 1 {
+                hash(sibling, &node)
+            } else {
+                hash(&node, &self.zero[level])
+            };
+        }
+        node
+    }
+
+    // Writes consecutive leaves starting at `first_index` of an append-only tree, and every
+    // node above them. Batches can't read their own writes, so the new nodes are computed
+    // level by level in memory. The left sibling of the first node at each level is in the
+    // `frontier` of the tree with `first_index` leaves, and the right sibling of the last
+    // one is empty, so nothing is read from the DB.
+    fn put_leaves(
+        &self,
+        batch: &mut WriteBatch,
+        tree_type: TreeType,
+        first_index: u32,
+        leaves: &[FixedBytes<32>],
+        frontier: &[FixedBytes<32>; DEPTH],
+    ) -> Result<()> {
+        let mut start = first_index;
+        let mut nodes = leaves.to_vec();
+        for (i, node) in nodes.iter().enumerate() {
+            self.put_level(batch, tree_type, 0, start + i as u32, node)?;
+        }
+
+        for (level, left) in frontier.iter().enumerate() {
+            let end = start + nodes.len() as u32 - 1;
+
+            // Pad both sides so the nodes of this level come in (left, right) pairs.
+            if !start.is_multiple_of(2) {
+                nodes.insert(0, *left);
+                start -= 1;
+            }
+            if end.is_multiple_of(2) {
+                nodes.push(self.zero[level]);
+            }
+
+            nodes = nodes
+                .chunks_exact(2)
+                .map(|pair| hash(&pair[0], &pair[1]))
+                .collect();
+            start /= 2;
+
+            for (i, node) in nodes.iter().enumerate() {
+                self.put_level(batch, tree_type, level + 1, start + i as u32, node)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Stores the Local Exit Root `leaf` of the aggchain `index` in the Rollup Exit Tree, from
+    // the log `log_index` of `block`. The previous leaf is kept, see `get_rollup_leaves_at`.
+    pub fn set_rollup_leaf(
+        &self,
+        index: u32,
+        leaf: &FixedBytes<32>,
+        block: BlockNum,
+        log_index: u64,
+    ) -> Result<()> {
+        let rollup_id = index;
+        // Aggchain index is shifted by 1. Meaning Aggchain 1 is placed at index 0, etc.
+        let index = index
+            .checked_sub(1)
+            .ok_or_else(|| eyre!("index cannot be 0"))?;
+
+        // TODO: Ensure when updating block number there are no future transactions
+        // with the same one. Otherwise it won't be really in sync until that block.
+        let mut batch = WriteBatch::default();
+        let root = self.put_leaf_at(&mut batch, TreeType::RollupExitTree, index, leaf, block)?;
+
+        let mut value = leaf.to_vec();
+        value.extend_from_slice(root.as_slice());
+        batch.put_cf(
+            CF_ROLLUP_LEAVES,
+            bridge_log_block_key(rollup_id, block, log_index),
+            value,
+        );
+        if self
+            .db
+            .get_cf(CF_ROLLUP_EXIT_ROOTS, root.as_slice())?
+            .is_none()
+        {
+            let mut position = block.to_be_bytes().to_vec();
+            position.extend_from_slice(&log_index.to_be_bytes());
+            batch.put_cf(CF_ROLLUP_EXIT_ROOTS, root, position);
+        }
+
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    pub fn append_l1info_leaf(&self, leaf: &L1InfoLeafData) -> eyre::Result<()> {
+        let tree_type = TreeType::L1InfoTree;
+        let index = self.get_leaf_count(&tree_type)?;
+        let mut batch = WriteBatch::default();
+
+        // Keep the leaf data, the tree only has its hash.
+        batch.put_cf(CF_L1_INFO_LEAVES, index.to_be_bytes(), leaf.to_bytes());
+        batch.put_cf(CF_L1_INFO_GERS, leaf.ger(), index.to_be_bytes());
+
+        // TODO: Ensure when updating block number there are no future transactions
+        // with the same one. Otherwise it won't be really in sync until that block.
+        let frontier = self.put_appended_leaves(
+            &mut batch,
+            tree_type,
+            &[(leaf.info_root_leaf(), leaf.block)],
+        )?;
+
+        self.db.write(batch)?;
+        self.cache_frontier(tree_type, frontier)
+    }
+
+    /// Data of the L1 Info Tree leaf at `index`.
+    pub fn get_l1info_leaf(&self, index: u32) -> Result<Option<L1InfoLeafData>> {
+        self.db
+            .get_cf(CF_L1_INFO_LEAVES, &index.to_be_bytes())?
+            .map(|v| L1InfoLeafData::from_bytes(&v))
+            .transpose()
+    }
+
+    /// L1 Info Tree leaf that added the Global Exit Root `ger`, with its index.
+    pub fn get_l1info_leaf_by_ger(
+        &self,
+        ger: &FixedBytes<32>,
+    ) -> Result<Option<(u32, L1InfoLeafData)>> {
+        let Some(v) = self.db.get_cf(CF_L1_INFO_GERS, ger.as_slice())? else {
+            return Ok(None);
+        };
+        let index = u32::from_be_bytes(v[..4].try_into()?);
+        let leaf = self
+            .get_l1info_leaf(index)?
+            .ok_or_else(|| eyre!("L1 info leaf {} not found", index))?;
+        Ok(Some((index, leaf)))
+    }
+
+    /// L1 Info Tree leaves added in the L1 block `block`, with their indexes.
+    pub fn get_l1info_leaves_by_block(
+        &self,
+        block: BlockNum,
+    ) -> Result<Vec<(u32, L1InfoLeafData)>> {
+        let from = match block.checked_sub(1) {
+            Some(prev) => self.leaf_count_at_block(&TreeType::L1InfoTree, prev)?,
+            None => 0,
+        };
+        let to = self.leaf_count_at_block(&TreeType::L1InfoTree, block)?;
+
+        (from..to)
+            .map(|index| {
+                let leaf = self
+                    .get_l1info_leaf(index)?
+                    .ok_or_else(|| eyre!("L1 info leaf {} not found", index))?;
+                Ok((index, leaf))
+            })
+            .collect()
+    }
+
+    /// Latest L1 Info Tree leaf whose rollup exit root is `rer`, with its index.
+    pub fn get_latest_l1info_leaf_by_rer(
+        &self,
+        rer: &FixedBytes<32>,
+    ) -> Result<Option<(u32, L1InfoLeafData)>> {
+        let iter = self.db.iterator_cf(CF_L1_INFO_LEAVES, IteratorMode::End);
+        for item in iter {
+            let (k, v) = item?;
+            let leaf = L1InfoLeafData::from_bytes(&v)?;
+            if leaf.rer == *rer {
+                let index = u32::from_be_bytes(k[..4].try_into()?);
+                return Ok(Some((index, leaf)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Number of leaves the Local Exit Tree of `aggchain_id` had when its root was `root`.
+    /// Returns None if the tree never had that root, or it is not indexed yet.
+    pub fn get_exit_root_leaf_count(
+        &self,
+        aggchain_id: AggchainId,
+        root: &FixedBytes<32>,
+    ) -> Result<Option<u32>> {
+        // An exit root that was never set, or the root of an empty tree.
+        if *root == FixedBytes::<32>::ZERO || *root == self.zero[DEPTH] {
+            return Ok(Some(0));
+        }
+        Ok(self
+            .db
+            .get_cf(CF_EXIT_ROOTS, &exit_root_key(aggchain_id, root))?
+            .map(|v| u32::from_be_bytes(v[..4].try_into().unwrap())))
+    }
+
+    // Adds a rollup type to the registry, or replaces it if it was already there. The
+    // write is logged with the `block` and `log_index` of its event to be rolled back.
+    pub fn put_rollup_type(
+        &self,
+        rollup_type: &RollupType,
+        block: BlockNum,
+        log_index: u64,
+    ) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        let id = rollup_type.rollup_type_id.to_be_bytes();
+        let previous = self.db.get_cf(CF_ROLLUP_TYPES, &id)?;
+        Self::put_registry_log(
+            &mut batch,
+            block,
+            log_index,
+            RegistryLogKind::RollupType,
+            &id,
+            previous.as_deref(),
+        );
+        batch.put_cf(CF_ROLLUP_TYPES, id, rollup_type.to_bytes());
+        self.db.write(batch)
+    }
+
+    pub fn get_rollup_type(&self, rollup_type_id: u32) -> Result<Option<RollupType>> {
+        self.db
+            .get_cf(CF_ROLLUP_TYPES, &rollup_type_id.to_be_bytes())?
+            .map(|v| RollupType::from_bytes(&v))
+            .transpose()
+    }
+
+    // Adds the rollup to the registry, or replaces it if it was already there. Logged like
+    // `put_rollup_type`.
+    pub fn put_rollup(&self, rollup: &RollupInfo, block: BlockNum, log_index: u64) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        let id = rollup.rollup_id.to_be_bytes();
+        let previous = self.db.get_cf(CF_ROLLUPS, &id)?;
+        Self::put_registry_log(
+            &mut batch,
+            block,
+            log_index,
+            RegistryLogKind::Rollup,
+            &id,
+            previous.as_deref(),
+        );
+        if let Some(previous) = previous {
+            let previous = RollupInfo::from_bytes(&previous)?;
+            batch.delete_cf(CF_ROLLUPS_BY_CHAIN_ID, previous.chain_id.to_be_bytes());
+            batch.delete_cf(CF_ROLLUPS_BY_ADDRESS, previous.rollup_address);
+        }
+        batch.put_cf(CF_ROLLUPS, id, rollup.to_bytes());
+        batch.put_cf(CF_ROLLUPS_BY_CHAIN_ID, rollup.chain_id.to_be_bytes(), id);
+        batch.put_cf(CF_ROLLUPS_BY_ADDRESS, rollup.rollup_address, id);
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    fn put_registry_log(
+        batch: &mut WriteBatch,
+        block: BlockNum,
+        log_index: u64,
+        kind: RegistryLogKind,
+        id: &[u8; 4],
+        previous: Option<&[u8]>,
+    ) {
+        let mut value = vec![kind as u8];
+        value.extend_from_slice(id);
+        value.extend_from_slice(previous.unwrap_or_default());
+        batch.put_cf(CF_REGISTRY_LOGS, registry_log_key(block, log_index), value);
+    }
+
+    pub fn get_rollup(&self, rollup_id: u32) -> Result<Option<RollupInfo>> {
+        self.db
+            .get_cf(CF_ROLLUPS, &rollup_id.to_be_bytes())?
+            .map(|v| RollupInfo::from_bytes(&v))
+            .transpose()
+    }
+
+    pub fn get_rollup_by_chain_id(&self, chain_id: u64) -> Result<Option<RollupInfo>> {
+        match self
+            .db
+            .get_cf(CF_ROLLUPS_BY_CHAIN_ID, &chain_id.to_be_bytes())?
+        {
+            Some(id) => self.get_rollup(u32::from_be_bytes(id.as_slice().try_into()?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_rollup_by_address(&self, rollup_address: &Address) -> Result<Option<RollupInfo>> {
+        match self
+            .db
+            .get_cf(CF_ROLLUPS_BY_ADDRESS, rollup_address.as_slice())?
+        {
+            Some(id) => self.get_rollup(u32::from_be_bytes(id.as_slice().try_into()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every rollup in the registry, by rollup ID.
+    pub fn get_rollups(&self) -> Result<Vec<RollupInfo>> {
+        let mut rollups = Vec::new();
+        for item in self.db.iterator_cf(CF_ROLLUPS, IteratorMode::Start) {
+            let (_, v) = item?;
+            rollups.push(RollupInfo::from_bytes(&v)?);
+        }
+        Ok(rollups)
+    }
+
+    // Leaves the Rollup Exit Tree had right after the log `log_index` of `block`, by
+    // index in the tree (aggchain id minus one).
+    fn get_rollup_leaves_at(
+        &self,
+        block: BlockNum,
+        log_index: u64,
+    ) -> Result<BTreeMap<u32, FixedBytes<32>>> {
+        let mut leaves = BTreeMap::new();
+        for rollup_id in 1..=self.get_leaf_count(&TreeType::RollupExitTree)? {
+            let key = bridge_log_block_key(rollup_id, block, log_index);
+            let mut iter = self.db.iterator_cf(
+                CF_ROLLUP_LEAVES,
+                IteratorMode::From(&key, Direction::Reverse),
+            );
+            if let Some(item) = iter.next() {
+                let (k, v) = item?;
+                if k[..4] == rollup_id.to_be_bytes() {
+                    leaves.insert(rollup_id - 1, FixedBytes::<32>::from_slice(&v[..32]));
+                }
+            }
+        }
+        Ok(leaves)
+    }
+
+    // Leaves of the Rollup Exit Tree when its root was `rer`. None if the root is unknown.
+    fn get_rollup_leaves_by_root(
+        &self,
+        rer: &FixedBytes<32>,
+    ) -> Result<Option<BTreeMap<u32, FixedBytes<32>>>> {
+        // The Rollup Exit Root before any rollup was verified.
+        if *rer == FixedBytes::<32>::ZERO || *rer == self.zero[DEPTH] {
+            return Ok(Some(BTreeMap::new()));
+        }
+        let Some(position) = self.db.get_cf(CF_ROLLUP_EXIT_ROOTS, rer.as_slice())? else {
+            return Ok(None);
+        };
+        let block = u64::from_be_bytes(position[..8].try_into()?);
+        let log_index = u64::from_be_bytes(position[8..16].try_into()?);
+        self.get_rollup_leaves_at(block, log_index).map(Some)
+    }
+
+    // Every non-empty node of a tree with `leaves`, by level and index.
+    fn sparse_levels(
+        &self,
+        leaves: &BTreeMap<u32, FixedBytes<32>>,
+    ) -> Vec<BTreeMap<u32, FixedBytes<32>>> {
+        let mut levels = vec![leaves.clone()];
+        for level in 0..DEPTH {
+            let nodes = &levels[level];
+            let mut parents = BTreeMap::new();
+            for index in nodes.keys() {
+                parents.entry(index / 2).or_insert_with(|| {
+                    let left = nodes.get(&(index & !1)).unwrap_or(&self.zero[level]);
+                    let right = nodes.get(&(index | 1)).unwrap_or(&self.zero[level]);
+                    hash(left, right)
+                });
+            }
+            levels.push(parents);
+        }
+        levels
+    }
+
+    /// Whether the Rollup Exit Tree ever had `rer` as root.
+    pub fn has_rollup_exit_root(&self, rer: &FixedBytes<32>) -> Result<bool> {
+        if *rer == FixedBytes::<32>::ZERO || *rer == self.zero[DEPTH] {
+            return Ok(true);
+        }
+        Ok(self
+            .db
+            .get_cf(CF_ROLLUP_EXIT_ROOTS, rer.as_slice())?
+            .is_some())
+    }
+
+    /// Rollup Exit Root at the end of `block`.
+    pub fn get_rollup_exit_root_at_block(&self, block: BlockNum) -> Result<FixedBytes<32>> {
+        let leaves = self.get_rollup_leaves_at(block, u64::MAX)?;
+        Ok(*self.sparse_levels(&leaves)[DEPTH]
+            .get(&0)
+            .unwrap_or(&self.zero[DEPTH]))
+    }
+
+    /// Local exit root of `aggchain_id` in the Rollup Exit Tree of root `rer`. None if
+    /// the root is unknown.
+    pub fn get_rollup_leaf_at_root(
+        &self,
+        rer: &FixedBytes<32>,
+        aggchain_id: AggchainId,
+    ) -> Result<Option<FixedBytes<32>>> {
+        let index = aggchain_id
+            .checked_sub(1)
+            .ok_or_else(|| eyre!("aggchain 0 is not in the Rollup Exit Tree"))?;
+        Ok(self
+            .get_rollup_leaves_by_root(rer)?
+            .map(|leaves| leaves.get(&index).copied().unwrap_or_default()))
+    }
+
+    /// Proof of the leaf of `aggchain_id` in the Rollup Exit Tree of root `rer`. None if
+    /// the root is unknown.
+    pub fn rollup_merkle_proof_at_root(
+        &self,
+        rer: &FixedBytes<32>,
+        aggchain_id: AggchainId,
+    ) -> Result<Option<[FixedBytes<32>; DEPTH]>> {
+        let mut index = aggchain_id
+            .checked_sub(1)
+            .ok_or_else(|| eyre!("aggchain 0 is not in the Rollup Exit Tree"))?;
+        let Some(leaves) = self.get_rollup_leaves_by_root(rer)? else {
+            return Ok(None);
+        };
+        let levels = self.sparse_levels(&leaves);
+        let mut proof = [FixedBytes::<32>::default(); DEPTH];
+        for level in 0..DEPTH {
+            proof[level] = *levels[level].get(&(index ^ 1)).unwrap_or(&self.zero[level]);
+            index >>= 1;
+        }
+        Ok(Some(proof))
+    }
+
+    /// Same as `rollup_merkle_proof_at_root`, with the Rollup Exit Root of the L1 Info
+    /// Tree leaf `l1_info_index`.
+    pub fn rollup_merkle_proof_at_l1info_index(
+        &self,
+        l1_info_index: u32,
+        aggchain_id: AggchainId,
+    ) -> Result<Option<[FixedBytes<32>; DEPTH]>> {
+        match self.get_l1info_leaf(l1_info_index)? {
+            Some(leaf) => self.rollup_merkle_proof_at_root(&leaf.rer, aggchain_id),
+            None => Ok(None),
+        }
+    }
+
+    /// Local Exit Root of `aggchain_id` currently stored in the Rollup Exit Tree.
+    pub fn get_rollup_leaf(&self, aggchain_id: AggchainId) -> Result<Option<FixedBytes<32>>> {
+        let index = aggchain_id
+            .checked_sub(1)
+            .ok_or_else(|| eyre!("aggchain 0 is not in the Rollup Exit Tree"))?;
+        self.get_hash(TreeType::RollupExitTree, 0, index)
+    }
+
+    pub fn merkle_proof(&self, tree_type: TreeType, index: u64) -> Result<[FixedBytes<32>; DEPTH]> {
+        let mut index = index;
+        match tree_type {
+            TreeType::RollupExitTree => {
+                // TODO: Maybe dirty. In the Rollup Exit Tree, aggchain 1 is placed at index 0, etc.
+                index = index - 1;
+            }
+            _ => {}
+        }
+        let mut proof = [FixedBytes::<32>::default(); DEPTH];
+        let mut idx = index;
+        for level in 0..DEPTH {
+            let sib = idx ^ 1;
+            proof[level] = match self.db.get_cf(
+                CF_TREE_LEVELS,
+                &node_key(&tree_type, level as u8, sib as u32),
+            )? {
+                Some(v) => FixedBytes::<32>::from(<[u8; 32]>::try_from(&v[..]).unwrap()),
+                None => self.zero[level],
+            };
+            idx >>= 1;
+        }
+        Ok(proof)
+    }
+
+    // Returns the node at `level` and `index` as it was when the tree had `leaf_count`
+    // leaves. Since the tree is append-only, nodes whose leaves are all below `leaf_count`
+    // never changed afterwards and nodes with no leaves below it were empty. Only the
+    // nodes in the path of leaf `leaf_count - 1` have to be recomputed.
+    fn get_hash_at(
+        &self,
+        tree_type: TreeType,
+        level: usize,
+        index: u32,
+        leaf_count: u32,
+    ) -> Result<FixedBytes<32>> {
+        let first_leaf = (index as u64) << level;
+        let last_leaf = ((index as u64 + 1) << level) - 1;
+
+        if last_leaf < leaf_count as u64 {
+            Ok(self
+                .get_hash(tree_type, level as u8, index)?
+                .unwrap_or(self.zero[level]))
+        } else if first_leaf >= leaf_count as u64 {
+            Ok(self.zero[level])
+        } else {
+            let left = self.get_hash_at(tree_type, level - 1, 2 * index, leaf_count)?;
+            let right = self.get_hash_at(tree_type, level - 1, 2 * index + 1, leaf_count)?;
+            Ok(hash(&left, &right))
+        }
+    }
+
+    fn check_leaf_count_at(&self, tree_type: TreeType, leaf_count: u32) -> Result<()> {
+        if tree_type == TreeType::RollupExitTree {
+            return Err(eyre!(
+                "Historical queries are only supported in append-only trees"
+            ));
+        }
+        let current_count = self.get_leaf_count(&tree_type)?;
+        if leaf_count > current_count {
+            return Err(eyre!(
+                "Leaf count {} is ahead of the tree, which only has {}",
+                leaf_count,
+                current_count
+            ));
+        }
+        Ok(())
+    }
+
+    /// Root of an append-only tree (Local Exit Tree or L1 Info Tree) when it had
+    /// `leaf_count` leaves. As in `get_root`, an empty tree has no root.
+    pub fn get_root_at(
+        &self,
+        tree_type: TreeType,
+        leaf_count: u32,
+    ) -> Result<Option<FixedBytes<32>>> {
+        self.check_leaf_count_at(tree_type, leaf_count)?;
+        if leaf_count == 0 {
+            return Ok(None);
+        }
+        Ok(Some(self.get_hash_at(tree_type, DEPTH, 0, leaf_count)?))
+    }
+
+    /// Merkle proof of the leaf at `index` against the root the append-only tree had
+    /// when it contained `leaf_count` leaves. See `get_root_at`.
+    pub fn merkle_proof_at(
+        &self,
+        tree_type: TreeType,
+        index: u32,
+        leaf_count: u32,
+    ) -> Result<[FixedBytes<32>; DEPTH]> {
+        self.check_leaf_count_at(tree_type, leaf_count)?;
+        if index >= leaf_count {
+            return Err(eyre!(
+                "Leaf {} is not in the tree with {} leaves",
+                index,
+                leaf_count
+            ));
+        }
+
+        let mut proof = [FixedBytes::<32>::default(); DEPTH];
+        let mut idx = index;
+        for (level, sibling) in proof.iter_mut().enumerate() {
+            *sibling = self.get_hash_at(tree_type, level, idx ^ 1, leaf_count)?;
+            idx >>= 1;
+        }
+        Ok(proof)
+    }
+
+    // Every tree with nodes or metadata in the database. Keys of both start with the
+    // aggchain ID, then the column type and tree type, so we can skip from tree to tree.
+    pub fn get_trees(&self) -> Result<Vec<TreeType>> {
+        let mut trees = Vec::new();
+        for (cf, column) in [
+            (CF_TREE_LEVELS, ColumnType::HashedNode as u8),
+            (CF_METADATA, ColumnType::Metadata as u8),
+        ] {
+            let mut from = vec![];
+            loop {
+                let mut iter = self
+                    .db
+                    .iterator_cf(cf, IteratorMode::From(&from, Direction::Forward));
+                let Some(item) = iter.next() else {
+                    break;
+                };
+                let (k, _) = item?;
+                if k.len() < 6 {
+                    return Err(eyre!("Invalid tree key {:?}", k));
+                }
+                // Leaf blocks are stored with the metadata, but every tree with leaf
+                // blocks also has a leaf count.
+                if k[4] == column {
+                    let aggchain_id = u32::from_be_bytes(k[..4].try_into()?);
+                    let tree = match k[5] {
+                        1 => TreeType::LocalExitTree(aggchain_id),
+                        2 => TreeType::RollupExitTree,
+                        3 => TreeType::L1InfoTree,
+                        t => return Err(eyre!("Unknown tree type {} in key {:?}", t, k)),
+                    };
+                    if !trees.contains(&tree) {
+                        trees.push(tree);
+                    }
+                }
+                // Next prefix after aggchain | column | tree type.
+                from = k[..6].to_vec();
+                for i in (0..6).rev() {
+                    if from[i] == u8::MAX {
+                        from[i] = 0;
+                    } else {
+                        from[i] += 1;
+                        break;
+                    }
+                }
+                if from.iter().all(|b| *b == 0) {
+                    break;
+                }
+            }
+        }
+        Ok(trees)
+    }
+
+    // Stored nodes of a level, in index order.
+    fn get_level(&self, tree_type: TreeType, level: u8) -> Result<Vec<(u32, FixedBytes<32>)>> {
+        let prefix = node_key(&tree_type, level, 0);
+        let mut nodes = Vec::new();
+        for item in self.db.iterator_cf(
+            CF_TREE_LEVELS,
+            IteratorMode::From(&prefix, Direction::Forward),
+        ) {
+            let (k, v) = item?;
+            if k.len() != prefix.len() || k[..7] != prefix[..7] {
+                break;
+            }
+            let index = u32::from_be_bytes(k[7..11].try_into()?);
+            let node = <[u8; 32]>::try_from(&v[..])
+                .map_err(|_| eyre!("Node {} of level {} is {} bytes", index, level, v.len()))?;
+            nodes.push((index, FixedBytes::from(node)));
+        }
+        Ok(nodes)
+    }
+
+    // Latest block in which the Rollup Exit Tree was modified, from its history.
+    fn get_rollup_leaves_latest_block(&self) -> Result<Option<BlockNum>> {
+        let mut latest = None;
+        for item in self.db.iterator_cf(CF_ROLLUP_LEAVES, IteratorMode::Start) {
+            let (k, _) = item?;
+            let block = u64::from_be_bytes(k[4..12].try_into()?);
+            latest = latest.max(Some(block));
+        }
+        Ok(latest)
+    }
+
+    // Non-empty nodes of the level above `children`, which are the non-empty nodes of
+    // `level` in index order.
+    fn parent_level(
+        &self,
+        children: &[(u32, FixedBytes<32>)],
+        level: usize,
+    ) -> Vec<(u32, FixedBytes<32>)> {
+        let mut parents: Vec<(u32, FixedBytes<32>)> = Vec::new();
+        for (i, (index, node)) in children.iter().enumerate() {
+            if parents
+                .last()
+                .is_some_and(|(parent, _)| *parent == index / 2)
+            {
+                continue;
+            }
+            let parent = if index & 1 == 0 {
+                // The right sibling is the next child, if any.
+                let sibling = children
+                    .get(i + 1)
+                    .filter(|(next, _)| *next == index + 1)
+                    .map_or(&self.zero[level], |(_, n)| n);
+                hash(node, sibling)
+            } else {
+                hash(&self.zero[level], node)
+            };
+            parents.push((index / 2, parent));
+        }
+        parents
+    }
+
+    /// Recomputes every node of a tree from its leaves (level 0) and the zero hashes,
+    /// and checks its leaf count and latest block. With `repair`, the nodes and
+    /// metadata are rewritten to match the leaves. Missing leaves can't be repaired.
+    pub fn check_tree(&self, tree_type: TreeType, repair: bool) -> Result<Vec<Inconsistency>> {
+        let mut found = Vec::new();
+        let mut batch = WriteBatch::default();
+
+        let leaves = self.get_level(tree_type, 0)?;
+        let expected_count = leaves.last().map_or(0, |(index, _)| index + 1);
+        let first_gap = leaves
+            .iter()
+            .enumerate()
+            .find(|(i, (index, _))| *i as u32 != *index)
+            .map(|(i, _)| i as u32);
+        if let Some(index) = first_gap
+            && tree_type != TreeType::RollupExitTree
+        {
+            found.push(Inconsistency::MissingLeaf { tree_type, index });
+        }
+
+        let stored_count = self.get_leaf_count(&tree_type)?;
+        if stored_count != expected_count {
+            found.push(Inconsistency::LeafCount {
+                tree_type,
+                stored: stored_count,
+                expected: expected_count,
+            });
+            self.put_deposit_count(&mut batch, tree_type, expected_count)?;
+        }
+
+        // Each level is compared with the one computed from the level below, so that
+        // once repaired, the whole tree matches the leaves.
+        let mut children = leaves;
+        for level in 1..=DEPTH {
+            let expected = self.parent_level(&children, level - 1);
+
+            let stored: BTreeMap<u32, FixedBytes<32>> = self
+                .get_level(tree_type, level as u8)?
+                .into_iter()
+                .collect();
+            for (index, node) in &expected {
+                let stored_node = stored.get(index).copied();
+                if stored_node != Some(*node) {
+                    found.push(Inconsistency::Node {
+                        tree_type,
+                        level: level as u8,
+                        index: *index,
+                        stored: stored_node,
+                        expected: Some(*node),
+                    });
+                    self.put_level(&mut batch, tree_type, level, *index, node)?;
+                }
+            }
+            for (index, node) in &stored {
+                if expected.binary_search_by_key(index, |(i, _)| *i).is_err() {
+                    found.push(Inconsistency::Node {
+                        tree_type,
+                        level: level as u8,
+                        index: *index,
+                        stored: Some(*node),
+                        expected: None,
+                    });
+                    batch.delete_cf(CF_TREE_LEVELS, node_key(&tree_type, level as u8, *index));
+                }
+            }
+            children = expected;
+        }
+
+        // The latest block can't be before the block of the last leaf.
+        let leaves_block = match tree_type {
+            TreeType::RollupExitTree => self.get_rollup_leaves_latest_block()?,
+            _ if expected_count > 0 => self.get_leaf_block(&tree_type, expected_count - 1)?,
+            _ => None,
+        };
+        let stored_block = self.get_latest_block(tree_type)?;
+        if let Some(block) = leaves_block
+            && stored_block.is_none_or(|stored| stored < block)
+        {
+            found.push(Inconsistency::LatestBlock {
+                tree_type,
+                stored: stored_block,
+                expected: block,
+            });
+            self.put_block_number(&mut batch, tree_type, block)?;
+        }
+
+        if repair && !found.is_empty() {
+            self.db.write(batch)?;
+            self.forget_frontier(tree_type)?;
+        }
+        Ok(found)
+    }
+
+    /// Writes a snapshot of the trees to `writer`: their leaves, metadata and the data
+    /// needed to serve proofs, plus the bridge events if `events`. Inner nodes are not
+    /// exported, they are recomputed on import. Returns the number of exported entries.
+    pub fn export_snapshot<W: Write>(&self, writer: W, events: bool) -> Result<u64> {
+        let mut writer = HashingWriter {
+            inner: writer,
+            hasher: Keccak256::new(),
+        };
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_be_bytes())?;
+        writer.write_all(&[events as u8])?;
+
+        // Read from a snapshot so that all the column families are consistent.
+        let snapshot = self.db.snapshot()?;
+        let mut entries = 0;
+        let cfs = SNAPSHOT_TREE_CFS
+            .iter()
+            .chain(SNAPSHOT_EVENT_CFS.iter().filter(|_| events));
+        for name in cfs {
+            writer.write_all(&[name.len() as u8])?;
+            writer.write_all(name.as_bytes())?;
+            for item in snapshot.iterator_cf(name, IteratorMode::Start) {
+                let (k, v) = item?;
+                // Only the leaves of the trees, see `rebuild_nodes`.
+                if *name == CF_TREE_LEVELS && k[4] == ColumnType::HashedNode as u8 && k[6] != 0 {
+                    continue;
+                }
+                writer.write_all(&(k.len() as u32).to_be_bytes())?;
+                writer.write_all(&k)?;
+                writer.write_all(&(v.len() as u32).to_be_bytes())?;
+                writer.write_all(&v)?;
+                entries += 1;
+            }
+            writer.write_all(&SNAPSHOT_END_OF_CF.to_be_bytes())?;
+        }
+        writer.write_all(&[0])?;
+
+        let checksum = writer.hasher.finalize();
+        writer.inner.write_all(checksum.as_slice())?;
+        writer.inner.flush()?;
+        Ok(entries)
+    }
+
+    /// Creates a new database at `path` from a snapshot written by `export_snapshot`.
+    /// Indexing resumes from the latest blocks of the snapshot. Nothing is left at
+    /// `path` if the snapshot is invalid.
+    pub fn import_snapshot<P: AsRef<Path>, R: Read>(
+        path: P,
+        reader: R,
+    ) -> Result<(Self, SnapshotImport)> {
+        if path.as_ref().exists() {
+            return Err(eyre!(
+                "Can't import a snapshot into {:?}, it already exists",
+                path.as_ref()
+            ));
+        }
+        let forest = Self::open(&path)?;
+        match forest.import_entries(reader) {
+            Ok(import) => Ok((forest, import)),
+            Err(e) => {
+                drop(forest);
+                std::fs::remove_dir_all(&path)?;
+                Err(e)
+            }
+        }
+    }
+
+    fn import_entries<R: Read>(&self, reader: R) -> Result<SnapshotImport> {
+        let mut reader = HashingReader {
+            inner: reader,
+            hasher: Keccak256::new(),
+        };
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != *SNAPSHOT_MAGIC {
+            return Err(eyre!("Not a snapshot"));
+        }
+        let version = u32::from_be_bytes(read_array(&mut reader)?);
+        if version != SNAPSHOT_VERSION {
+            return Err(eyre!(
+                "Unsupported snapshot version {} != {}",
+                version,
+                SNAPSHOT_VERSION
+            ));
+        }
+        let [events] = read_array(&mut reader)?;
+        let mut import = SnapshotImport {
+            events: events != 0,
+            entries: Vec::new(),
+        };
+
+        let mut batch = WriteBatch::default();
+        loop {
+            let [name_len] = read_array(&mut reader)?;
+            if name_len == 0 {
+                break;
+            }
+            let mut name = vec![0u8; name_len as usize];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name)?;
+            let cf = *SNAPSHOT_TREE_CFS
+                .iter()
+                .chain(SNAPSHOT_EVENT_CFS.iter())
+                .find(|cf| **cf == name)
+                .ok_or_else(|| eyre!("Unknown column family '{}' in snapshot", name))?;
+            let mut entries = 0u64;
+            loop {
+                let key_len = u32::from_be_bytes(read_array(&mut reader)?);
+                if key_len == SNAPSHOT_END_OF_CF {
+                    break;
+                }
+                let mut key = vec![0u8; key_len as usize];
+                reader.read_exact(&mut key)?;
+                let value_len = u32::from_be_bytes(read_array(&mut reader)?);
+                let mut value = vec![0u8; value_len as usize];
+                reader.read_exact(&mut value)?;
+                batch.put_cf(cf, key, value);
+                entries += 1;
+                if batch.len() >= SNAPSHOT_BATCH_SIZE {
+                    self.db.write(std::mem::take(&mut batch))?;
+                }
+            }
+            import.entries.push((cf, entries));
+        }
+
+        let checksum = reader.hasher.finalize();
+        let expected: [u8; 32] = read_array(&mut reader.inner)?;
+        if checksum != expected {
+            return Err(eyre!(
+                "Snapshot checksum mismatch: {} != {}",
+                checksum,
+                B256::from(expected)
+            ));
+        }
+        self.db.write(batch)?;
+
+        for tree_type in self.get_trees()? {
+            self.rebuild_nodes(tree_type)?;
+        }
+        Ok(import)
+    }
+
+    // Writes every inner node of a tree, computed from its leaves.
+    fn rebuild_nodes(&self, tree_type: TreeType) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        let mut nodes = self.get_level(tree_type, 0)?;
+        for level in 1..=DEPTH {
+            nodes = self.parent_level(&nodes, level - 1);
+            for (index, node) in &nodes {
+                self.put_level(&mut batch, tree_type, level, *index, node)?;
+            }
+            if batch.len() >= SNAPSHOT_BATCH_SIZE {
+                self.db.write(std::mem::take(&mut batch))?;
+            }
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+}
+
+#[inline(always)]
+fn hash(l: &FixedBytes<32>, r: &FixedBytes<32>) -> FixedBytes<32> {
+    let mut buf = [0u8; 64];
+    buf[..32].copy_from_slice(l.as_slice());
+    buf[32..].copy_from_slice(r.as_slice());
+    keccak256(buf)
+}
 
 pub fn calculate_merkle_root(
     leaf: &FixedBytes<32>,
@@ -525,7 +2638,7 @@ pub fn calculate_merkle_root(
     let mut idx = index;
 
     for sibling_hash in proof {
-        if idx % 2 == 0 {
+        if idx.is_multiple_of(2) {
             current_hash = hash(&current_hash, sibling_hash);
         } else {
             current_hash = hash(sibling_hash, &current_hash);
@@ -536,6 +2649,17 @@ pub fn calculate_merkle_root(
     current_hash
 }
 
+// Same as `verifyMerkleProof` in the bridge contract: whether `leaf` is at `index` in the
+// tree with `root`.
+pub fn verify_merkle_proof(
+    leaf: &FixedBytes<32>,
+    proof: &[FixedBytes<32>; DEPTH],
+    index: u32,
+    root: &FixedBytes<32>,
+) -> bool {
+    calculate_merkle_root(leaf, proof, index as u64) == *root
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,91 @@
+use alloy::primitives::Address;
+
+// A rollup type registered in the RollupManager with AddNewRollupType. Rollups created
+// from it take its fork ID and verifier type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RollupType {
+    pub rollup_type_id: u32,
+    pub fork_id: u64,
+    pub verifier_type: u8,
+}
+
+impl RollupType {
+    const ENCODED_LEN: usize = 13;
+
+    // Encoding: rollup_type_id BE | fork_id BE | verifier_type
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut buf = [0u8; Self::ENCODED_LEN];
+        buf[0..4].copy_from_slice(&self.rollup_type_id.to_be_bytes());
+        buf[4..12].copy_from_slice(&self.fork_id.to_be_bytes());
+        buf[12] = self.verifier_type;
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> eyre::Result<Self> {
+        if bytes.len() != Self::ENCODED_LEN {
+            return Err(eyre::eyre!(
+                "Invalid rollup type length {} != {}",
+                bytes.len(),
+                Self::ENCODED_LEN
+            ));
+        }
+        Ok(Self {
+            rollup_type_id: u32::from_be_bytes(bytes[0..4].try_into()?),
+            fork_id: u64::from_be_bytes(bytes[4..12].try_into()?),
+            verifier_type: bytes[12],
+        })
+    }
+}
+
+// An aggchain attached to the RollupManager, either created from a rollup type
+// (CreateNewRollup, CreateNewAggchain) or added as it was (AddExistingRollup).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RollupInfo {
+    pub rollup_id: u32,
+    pub chain_id: u64,
+    pub rollup_address: Address,
+    pub fork_id: u64,
+    // Rollup type it was created from or updated to. 0 if it was added as it was.
+    pub rollup_type_id: u32,
+    // rollupVerifierType, or rollupCompatibilityID for the rollups added before the
+    // verifier type existed (old layout of AddExistingRollup).
+    pub verifier_type: u8,
+    pub block_number: u64,
+}
+
+impl RollupInfo {
+    const ENCODED_LEN: usize = 53;
+
+    // Encoding: rollup_id BE | chain_id BE | rollup_address | fork_id BE |
+    // rollup_type_id BE | verifier_type | block_number BE
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut buf = [0u8; Self::ENCODED_LEN];
+        buf[0..4].copy_from_slice(&self.rollup_id.to_be_bytes());
+        buf[4..12].copy_from_slice(&self.chain_id.to_be_bytes());
+        buf[12..32].copy_from_slice(self.rollup_address.as_slice());
+        buf[32..40].copy_from_slice(&self.fork_id.to_be_bytes());
+        buf[40..44].copy_from_slice(&self.rollup_type_id.to_be_bytes());
+        buf[44] = self.verifier_type;
+        buf[45..53].copy_from_slice(&self.block_number.to_be_bytes());
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> eyre::Result<Self> {
+        if bytes.len() != Self::ENCODED_LEN {
+            return Err(eyre::eyre!(
+                "Invalid rollup info length {} != {}",
+                bytes.len(),
+                Self::ENCODED_LEN
+            ));
+        }
+        Ok(Self {
+            rollup_id: u32::from_be_bytes(bytes[0..4].try_into()?),
+            chain_id: u64::from_be_bytes(bytes[4..12].try_into()?),
+            rollup_address: Address::from_slice(&bytes[12..32]),
+            fork_id: u64::from_be_bytes(bytes[32..40].try_into()?),
+            rollup_type_id: u32::from_be_bytes(bytes[40..44].try_into()?),
+            verifier_type: bytes[44],
+            block_number: u64::from_be_bytes(bytes[45..53].try_into()?),
+        })
+    }
+}
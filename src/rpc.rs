@@ -0,0 +1,268 @@
+use alloy::providers::fillers::{
+    BlobGasFiller, ChainIdFiller, FillProvider, GasFiller, JoinFill, NonceFiller,
+};
+use alloy::providers::{Identity, Provider, ProviderBuilder, RootProvider};
+use alloy::rpc::client::RpcClient;
+use alloy::transports::http::reqwest::Url;
+use alloy::transports::layers::{RetryBackoffLayer, RetryPolicy};
+use alloy::transports::{RpcError, TransportError, TransportErrorKind};
+use eyre::{Result, eyre};
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// TODO: Ugly
+pub type ProviderStack = Arc<
+    FillProvider<
+        JoinFill<
+            Identity,
+            JoinFill<GasFiller, JoinFill<BlobGasFiller, JoinFill<NonceFiller, ChainIdFiller>>>,
+        >,
+        RootProvider,
+    >,
+>;
+
+// Parts of the messages returned by RPC providers when an `eth_getLogs` query spans too
+// many blocks or returns too many logs. Each provider words them differently, and they are
+// specific enough not to match other errors, e.g. "invalid block range" or rate limits.
+const RANGE_LIMIT_ERRORS: [&str; 9] = [
+    "query returned more than",
+    "block range is too wide",
+    "range too large",
+    "range is too large",
+    "exceed maximum block range",
+    "exceeds max block range",
+    "ranges over",
+    "response size exceeded",
+    "getlogs is limited to a",
+];
+
+// Phrases followed by the seconds to wait in the messages of rate limited responses,
+// e.g. "Too many requests, retry after 5 seconds".
+const RETRY_AFTER_PHRASES: [&str; 3] = ["retry after", "retry-after", "try again in"];
+
+/// Whether the RPC rejected a query because of the size of its block range or result, so
+/// that querying fewer blocks would succeed.
+pub fn is_range_limit_error(error: &TransportError) -> bool {
+    let message = match error {
+        RpcError::ErrorResp(payload) => payload.message.to_lowercase(),
+        // Some providers answer with an HTTP error instead.
+        RpcError::Transport(TransportErrorKind::HttpError(err)) => err.body.to_lowercase(),
+        _ => return false,
+    };
+    RANGE_LIMIT_ERRORS
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+// Parts of the messages of rate limited JSON-RPC responses, the same ones alloy retries.
+const RATE_LIMIT_ERRORS: [&str; 5] = [
+    "rate limit",
+    "rate exceeded",
+    "too many requests",
+    "credits limited",
+    "request limit",
+];
+
+// 429 is used by Alchemy and -32005 by Infura. Range limits use -32005 too, so they are
+// told apart first.
+fn is_rate_limit(code: i64, message: &str) -> bool {
+    let message = message.to_lowercase();
+    code == 429
+        || code == -32005
+        || RATE_LIMIT_ERRORS
+            .iter()
+            .any(|pattern| message.contains(pattern))
+}
+
+// Parts of the messages of nodes asked for the state of a block they already pruned.
+const STATE_UNAVAILABLE_ERRORS: [&str; 4] = [
+    "missing trie node",
+    "historical state",
+    "state is not available",
+    "state not available",
+];
+
+/// Whether a call at a past block failed because the node doesn't keep the state of that
+/// block anymore. Only archive nodes can answer it.
+pub fn is_state_unavailable(error: &TransportError) -> bool {
+    match error {
+        RpcError::ErrorResp(payload) => {
+            let message = payload.message.to_lowercase();
+            STATE_UNAVAILABLE_ERRORS
+                .iter()
+                .any(|pattern| message.contains(pattern))
+        }
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcErrorClass {
+    /// The provider asks to slow down. Retried, after the delay it asks for if any.
+    RateLimited,
+    /// Network errors and unavailable or overloaded nodes. Retried.
+    Transient,
+    /// Too many blocks or logs for an `eth_getLogs`. Not retried, the indexer splits
+    /// the query instead.
+    RangeLimit,
+    /// Invalid requests, unsupported methods, reverted calls, pruned state or an endpoint
+    /// that doesn't speak JSON-RPC. Retrying would fail the same way.
+    Fatal,
+}
+
+pub fn classify(error: &TransportError) -> RpcErrorClass {
+    if is_range_limit_error(error) {
+        return RpcErrorClass::RangeLimit;
+    }
+    if is_state_unavailable(error) {
+        return RpcErrorClass::Fatal;
+    }
+    match error {
+        RpcError::ErrorResp(payload) => {
+            if is_rate_limit(payload.code, &payload.message) {
+                return RpcErrorClass::RateLimited;
+            }
+            // Anything else that isn't the request's fault is retried as transient, e.g.
+            // "header not found" from nodes behind a load balancer lagging behind.
+            match payload.code {
+                // Parse error, invalid request, method not found and invalid params.
+                -32700 | -32600 | -32601 | -32602 => RpcErrorClass::Fatal,
+                // Reverted calls.
+                3 => RpcErrorClass::Fatal,
+                _ if payload.message.contains("execution reverted") => RpcErrorClass::Fatal,
+                _ => RpcErrorClass::Transient,
+            }
+        }
+        RpcError::Transport(TransportErrorKind::HttpError(err)) => match err.status {
+            429 => RpcErrorClass::RateLimited,
+            408 => RpcErrorClass::Transient,
+            // Wrong path or API key.
+            400..=499 => RpcErrorClass::Fatal,
+            _ => RpcErrorClass::Transient,
+        },
+        RpcError::Transport(TransportErrorKind::Custom(err))
+            if err.to_string().contains("429 Too Many Requests") =>
+        {
+            RpcErrorClass::RateLimited
+        }
+        // Connection errors and timeouts, also dropped responses.
+        RpcError::Transport(_) | RpcError::NullResp => RpcErrorClass::Transient,
+        // The response is not JSON-RPC, e.g. an HTML page.
+        _ => RpcErrorClass::Fatal,
+    }
+}
+
+/// Seconds to wait before retrying a rate limited request, if the provider says so. Infura
+/// puts them in the error data, others in the message. The HTTP transport doesn't expose
+/// the response headers, so a `Retry-After` header is only seen if repeated in the body.
+pub fn retry_after(error: &TransportError) -> Option<Duration> {
+    let text = match error {
+        RpcError::ErrorResp(payload) => {
+            if let Some(Ok(data)) = payload.try_data_as::<serde_json::Value>() {
+                for hint in [
+                    &data["rate"]["backoff_seconds"],
+                    &data["retry_after"],
+                    &data["retryAfter"],
+                ] {
+                    if let Some(seconds) = hint.as_f64() {
+                        return Duration::try_from_secs_f64(seconds).ok();
+                    }
+                }
+            }
+            payload.message.to_lowercase()
+        }
+        RpcError::Transport(TransportErrorKind::HttpError(err)) => err.body.to_lowercase(),
+        _ => return None,
+    };
+    RETRY_AFTER_PHRASES.iter().find_map(|phrase| {
+        let after = &text[text.find(phrase)? + phrase.len()..];
+        let digits: String = after
+            .trim_start_matches([' ', ':'])
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        Some(Duration::from_secs(digits.parse().ok()?))
+    })
+}
+
+/// Failed requests of an RPC by class, surfaced in `/rpc-stats`.
+#[derive(Debug, Default, Serialize)]
+pub struct RpcStats {
+    pub rate_limited: AtomicU64,
+    pub transient: AtomicU64,
+    pub range_limit: AtomicU64,
+    pub fatal: AtomicU64,
+}
+
+impl RpcStats {
+    pub fn record(&self, class: RpcErrorClass) {
+        let counter = match class {
+            RpcErrorClass::RateLimited => &self.rate_limited,
+            RpcErrorClass::Transient => &self.transient,
+            RpcErrorClass::RangeLimit => &self.range_limit,
+            RpcErrorClass::Fatal => &self.fatal,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Retries rate limited and transient errors only, see `classify`.
+#[derive(Debug, Clone, Default)]
+pub struct RpcRetryPolicy {
+    pub stats: Arc<RpcStats>,
+}
+
+impl RetryPolicy for RpcRetryPolicy {
+    fn should_retry(&self, error: &TransportError) -> bool {
+        let class = classify(error);
+        self.stats.record(class);
+        matches!(class, RpcErrorClass::RateLimited | RpcErrorClass::Transient)
+    }
+
+    fn backoff_hint(&self, error: &TransportError) -> Option<Duration> {
+        retry_after(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryConfig {
+    // Retries of a request before giving up.
+    pub max_retries: u32,
+    // Milliseconds to wait between retries, unless the provider says how long in the body
+    // of the response. The `Retry-After` header is not read, the HTTP transport doesn't
+    // expose the response headers.
+    pub backoff: u64,
+    // Budget of the provider, queued requests wait longer when it's exceeded.
+    pub compute_units_per_second: u64,
+}
+
+/// Connects to an RPC, retrying failed requests according to `config`. Errors are counted
+/// in `stats`.
+pub fn connect(url: Url, config: &RetryConfig, stats: Arc<RpcStats>) -> ProviderStack {
+    let retry_layer = RetryBackoffLayer::new_with_policy(
+        config.max_retries,
+        config.backoff,
+        config.compute_units_per_second,
+        RpcRetryPolicy { stats },
+    );
+    Arc::new(
+        ProviderBuilder::new().connect_client(RpcClient::builder().layer(retry_layer).http(url)),
+    )
+}
+
+/// Fails if the RPC can't be reached or doesn't answer JSON-RPC requests, without the
+/// retries of `connect` that would hide a misconfigured URL. Being rate limited is fine.
+pub async fn check_connection(url: &Url) -> Result<()> {
+    let provider = ProviderBuilder::new().connect_http(url.clone());
+    match provider.get_chain_id().await {
+        Ok(_) => Ok(()),
+        Err(err) if classify(&err) == RpcErrorClass::RateLimited => Ok(()),
+        Err(err) => Err(eyre!(
+            "RPC {} is unreachable or misconfigured: {}",
+            url,
+            err
+        )),
+    }
+}
@@ -0,0 +1,572 @@
+use eyre::{Result, eyre};
+use rocksdb::{DB, WriteOptions};
+use std::collections::{BTreeMap, HashMap};
+use std::iter::Peekable;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex, RwLock};
+
+// Key-value storage of the MerkleForest. Data is split in column families, identified by
+// name, and keys are sorted bytewise within each of them (the default RocksDB ordering).
+// Two backends exist: RocksDB, and an in-memory one for tests and embedding applications
+// that don't need the trees to persist. Writes can also be kept in memory on top of any
+// of them, and written at once later, see `OverlayStorage`.
+
+pub type KeyValue = (Box<[u8]>, Box<[u8]>);
+pub type StorageIterator<'a> = Box<dyn Iterator<Item = Result<KeyValue>> + 'a>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// Where iteration starts. `From` with `Reverse` starts at the last key lower than or
+/// equal to the given one.
+#[derive(Clone, Copy, Debug)]
+pub enum IteratorMode<'a> {
+    Start,
+    End,
+    From(&'a [u8], Direction),
+}
+
+enum BatchOp {
+    Put {
+        cf: &'static str,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Delete {
+        cf: &'static str,
+        key: Vec<u8>,
+    },
+    // Deletes the keys in [from, to).
+    DeleteRange {
+        cf: &'static str,
+        from: Vec<u8>,
+        to: Vec<u8>,
+    },
+}
+
+/// Writes applied atomically by `Storage::write`, in order.
+#[derive(Default)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    pub fn put_cf<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, cf: &'static str, key: K, value: V) {
+        self.ops.push(BatchOp::Put {
+            cf,
+            key: key.as_ref().to_vec(),
+            value: value.as_ref().to_vec(),
+        });
+    }
+
+    pub fn delete_cf<K: AsRef<[u8]>>(&mut self, cf: &'static str, key: K) {
+        self.ops.push(BatchOp::Delete {
+            cf,
+            key: key.as_ref().to_vec(),
+        });
+    }
+
+    pub fn delete_range_cf<K: AsRef<[u8]>>(&mut self, cf: &'static str, from: K, to: K) {
+        self.ops.push(BatchOp::DeleteRange {
+            cf,
+            from: from.as_ref().to_vec(),
+            to: to.as_ref().to_vec(),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+pub trait ReadStorage {
+    fn get_cf(&self, cf: &str, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    fn iterator_cf<'a>(&'a self, cf: &str, mode: IteratorMode) -> StorageIterator<'a>;
+}
+
+pub trait Storage: ReadStorage + Send + Sync {
+    fn write(&self, batch: WriteBatch) -> Result<()>;
+
+    /// Consistent view of the storage, not affected by later writes.
+    fn snapshot(&self) -> Result<Box<dyn ReadStorage + '_>>;
+
+    /// Makes every previous write durable, so it survives a crash.
+    fn flush(&self) -> Result<()>;
+}
+
+pub struct RocksDbStorage {
+    db: DB,
+}
+
+impl RocksDbStorage {
+    /// The database must have been opened with every column family used.
+    pub fn new(db: DB) -> Self {
+        Self { db }
+    }
+
+    fn cf(&self, cf: &str) -> Result<&rocksdb::ColumnFamily> {
+        self.db
+            .cf_handle(cf)
+            .ok_or_else(|| eyre!("CF '{}' not found", cf))
+    }
+}
+
+fn rocksdb_mode(mode: IteratorMode) -> rocksdb::IteratorMode {
+    match mode {
+        IteratorMode::Start => rocksdb::IteratorMode::Start,
+        IteratorMode::End => rocksdb::IteratorMode::End,
+        IteratorMode::From(key, Direction::Forward) => {
+            rocksdb::IteratorMode::From(key, rocksdb::Direction::Forward)
+        }
+        IteratorMode::From(key, Direction::Reverse) => {
+            rocksdb::IteratorMode::From(key, rocksdb::Direction::Reverse)
+        }
+    }
+}
+
+impl ReadStorage for RocksDbStorage {
+    fn get_cf(&self, cf: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get_cf(self.cf(cf)?, key)?)
+    }
+
+    fn iterator_cf<'a>(&'a self, cf: &str, mode: IteratorMode) -> StorageIterator<'a> {
+        match self.cf(cf) {
+            Ok(cf) => Box::new(
+                self.db
+                    .iterator_cf(cf, rocksdb_mode(mode))
+                    .map(|item| item.map_err(Into::into)),
+            ),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+}
+
+impl Storage for RocksDbStorage {
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let mut rocksdb_batch = rocksdb::WriteBatch::default();
+        for op in batch.ops {
+            match op {
+                BatchOp::Put { cf, key, value } => rocksdb_batch.put_cf(self.cf(cf)?, key, value),
+                BatchOp::Delete { cf, key } => rocksdb_batch.delete_cf(self.cf(cf)?, key),
+                BatchOp::DeleteRange { cf, from, to } => {
+                    rocksdb_batch.delete_range_cf(self.cf(cf)?, from, to)
+                }
+            }
+        }
+        // Writes are not synced, a crash may lose the latest ones. See `check_tree` and
+        // `flush`.
+        let mut write_opts = WriteOptions::default();
+        write_opts.set_sync(false);
+        self.db.write_opt(rocksdb_batch, &write_opts)?;
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn ReadStorage + '_>> {
+        Ok(Box::new(RocksDbSnapshot {
+            storage: self,
+            snapshot: self.db.snapshot(),
+        }))
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.db.flush_wal(true)?;
+        Ok(())
+    }
+}
+
+struct RocksDbSnapshot<'a> {
+    storage: &'a RocksDbStorage,
+    snapshot: rocksdb::Snapshot<'a>,
+}
+
+impl ReadStorage for RocksDbSnapshot<'_> {
+    fn get_cf(&self, cf: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.snapshot.get_cf(self.storage.cf(cf)?, key)?)
+    }
+
+    fn iterator_cf<'a>(&'a self, cf: &str, mode: IteratorMode) -> StorageIterator<'a> {
+        match self.storage.cf(cf) {
+            Ok(cf) => Box::new(
+                self.snapshot
+                    .iterator_cf(cf, rocksdb_mode(mode))
+                    .map(|item| item.map_err(Into::into)),
+            ),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+}
+
+type ColumnFamilies = HashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>;
+
+/// Storage keeping everything in memory. Column families are created on first write.
+#[derive(Default)]
+pub struct MemoryStorage {
+    cfs: RwLock<ColumnFamilies>,
+}
+
+impl MemoryStorage {
+    fn read(&self) -> Result<std::sync::RwLockReadGuard<'_, ColumnFamilies>> {
+        self.cfs
+            .read()
+            .map_err(|_| eyre!("Memory storage lock poisoned"))
+    }
+}
+
+impl ReadStorage for MemoryStorage {
+    fn get_cf(&self, cf: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.read()?.get(cf).and_then(|m| m.get(key)).cloned())
+    }
+
+    fn iterator_cf<'a>(&'a self, cf: &str, mode: IteratorMode) -> StorageIterator<'a> {
+        let (bound, direction) = match mode {
+            IteratorMode::Start => (Bound::Unbounded, Direction::Forward),
+            IteratorMode::End => (Bound::Unbounded, Direction::Reverse),
+            IteratorMode::From(key, direction) => (Bound::Included(key.to_vec()), direction),
+        };
+        Box::new(MemoryIterator {
+            storage: self,
+            cf: cf.to_string(),
+            bound,
+            direction,
+        })
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let mut cfs = self
+            .cfs
+            .write()
+            .map_err(|_| eyre!("Memory storage lock poisoned"))?;
+        for op in batch.ops {
+            match op {
+                BatchOp::Put { cf, key, value } => {
+                    cfs.entry(cf.to_string()).or_default().insert(key, value);
+                }
+                BatchOp::Delete { cf, key } => {
+                    if let Some(m) = cfs.get_mut(cf) {
+                        m.remove(&key);
+                    }
+                }
+                BatchOp::DeleteRange { cf, from, to } => {
+                    if let Some(m) = cfs.get_mut(cf) {
+                        let keys: Vec<Vec<u8>> =
+                            m.range(from..to).map(|(k, _)| k.clone()).collect();
+                        for k in keys {
+                            m.remove(&k);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn ReadStorage + '_>> {
+        let cfs = self.read()?.clone();
+        Ok(Box::new(MemoryStorage {
+            cfs: RwLock::new(cfs),
+        }))
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Looks up the next key on every step, so that the lock isn't held between steps.
+struct MemoryIterator<'a> {
+    storage: &'a MemoryStorage,
+    cf: String,
+    // Bound of the remaining keys: lower bound going forward, upper bound in reverse.
+    bound: Bound<Vec<u8>>,
+    direction: Direction,
+}
+
+impl Iterator for MemoryIterator<'_> {
+    type Item = Result<KeyValue>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cfs = match self.storage.read() {
+            Ok(cfs) => cfs,
+            Err(e) => return Some(Err(e)),
+        };
+        let m = cfs.get(&self.cf)?;
+        let bound = (self.bound.as_ref().map(Vec::as_slice), Bound::Unbounded);
+        let (k, v) = match self.direction {
+            Direction::Forward => m.range::<[u8], _>(bound).next()?,
+            Direction::Reverse => m.range::<[u8], _>((bound.1, bound.0)).next_back()?,
+        };
+        self.bound = Bound::Excluded(k.clone());
+        Some(Ok((
+            k.clone().into_boxed_slice(),
+            v.clone().into_boxed_slice(),
+        )))
+    }
+}
+
+// Keys in [from, to).
+type KeyRange = (Vec<u8>, Vec<u8>);
+
+// Writes not in the base storage yet. Deleted keys are kept as None, so that they hide
+// the keys of the base.
+#[derive(Clone, Default)]
+struct Changes {
+    cfs: HashMap<String, BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+    // Deleted ranges, hiding the keys of the base in them.
+    deleted_ranges: HashMap<String, Vec<KeyRange>>,
+}
+
+impl Changes {
+    fn apply(&mut self, op: &BatchOp) {
+        match op {
+            BatchOp::Put { cf, key, value } => {
+                self.cfs
+                    .entry(cf.to_string())
+                    .or_default()
+                    .insert(key.clone(), Some(value.clone()));
+            }
+            BatchOp::Delete { cf, key } => {
+                self.cfs
+                    .entry(cf.to_string())
+                    .or_default()
+                    .insert(key.clone(), None);
+            }
+            BatchOp::DeleteRange { cf, from, to } => {
+                if let Some(m) = self.cfs.get_mut(*cf) {
+                    let keys: Vec<Vec<u8>> = m
+                        .range::<[u8], _>((Bound::Included(&from[..]), Bound::Excluded(&to[..])))
+                        .map(|(k, _)| k.clone())
+                        .collect();
+                    for k in keys {
+                        m.remove(&k);
+                    }
+                }
+                self.deleted_ranges
+                    .entry(cf.to_string())
+                    .or_default()
+                    .push((from.clone(), to.clone()));
+            }
+        }
+    }
+
+    // Some if the key was written, with None if it was deleted.
+    fn get(&self, cf: &str, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        if let Some(value) = self.cfs.get(cf).and_then(|m| m.get(key)) {
+            return Some(value.clone());
+        }
+        self.deleted_ranges
+            .get(cf)
+            .is_some_and(|ranges| {
+                ranges
+                    .iter()
+                    .any(|(from, to)| from.as_slice() <= key && key < to.as_slice())
+            })
+            .then_some(None)
+    }
+}
+
+fn read_changes(changes: &RwLock<Changes>) -> Result<std::sync::RwLockReadGuard<'_, Changes>> {
+    changes
+        .read()
+        .map_err(|_| eyre!("Overlay storage lock poisoned"))
+}
+
+// Reads `changes` on top of `base`.
+fn layered_get(
+    base: &dyn ReadStorage,
+    changes: &RwLock<Changes>,
+    cf: &str,
+    key: &[u8],
+) -> Result<Option<Vec<u8>>> {
+    if let Some(value) = read_changes(changes)?.get(cf, key) {
+        return Ok(value);
+    }
+    base.get_cf(cf, key)
+}
+
+fn layered_iterator<'a>(
+    base: &'a dyn ReadStorage,
+    changes: &'a RwLock<Changes>,
+    cf: &str,
+    mode: IteratorMode,
+) -> StorageIterator<'a> {
+    let (bound, direction) = match mode {
+        IteratorMode::Start => (Bound::Unbounded, Direction::Forward),
+        IteratorMode::End => (Bound::Unbounded, Direction::Reverse),
+        IteratorMode::From(key, direction) => (Bound::Included(key.to_vec()), direction),
+    };
+    Box::new(LayeredIterator {
+        changes,
+        cf: cf.to_string(),
+        bound,
+        direction,
+        base: base.iterator_cf(cf, mode).peekable(),
+    })
+}
+
+// Merges the keys of the base with the changes. Like `MemoryIterator`, the next change is
+// looked up on every step.
+struct LayeredIterator<'a> {
+    changes: &'a RwLock<Changes>,
+    cf: String,
+    // Bound of the remaining changes: lower bound going forward, upper bound in reverse.
+    bound: Bound<Vec<u8>>,
+    direction: Direction,
+    base: Peekable<StorageIterator<'a>>,
+}
+
+impl Iterator for LayeredIterator<'_> {
+    type Item = Result<KeyValue>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let changes = match read_changes(self.changes) {
+            Ok(changes) => changes,
+            Err(e) => return Some(Err(e)),
+        };
+        loop {
+            // Keys of the base that were changed are replaced by the change.
+            while let Some(Ok((k, _))) = self.base.peek() {
+                if changes.get(&self.cf, k).is_none() {
+                    break;
+                }
+                self.base.next();
+            }
+            if let Some(Err(_)) = self.base.peek() {
+                return self.base.next();
+            }
+
+            let bound = (self.bound.as_ref().map(Vec::as_slice), Bound::Unbounded);
+            let change = changes
+                .cfs
+                .get(&self.cf)
+                .and_then(|m| match self.direction {
+                    Direction::Forward => m.range::<[u8], _>(bound).next(),
+                    Direction::Reverse => m.range::<[u8], _>((bound.1, bound.0)).next_back(),
+                });
+
+            let change_first = match (change, self.base.peek()) {
+                (None, _) => false,
+                (Some(_), None) => true,
+                (Some((k, _)), Some(Ok((base_key, _)))) => match self.direction {
+                    Direction::Forward => k.as_slice() < &base_key[..],
+                    Direction::Reverse => k.as_slice() > &base_key[..],
+                },
+                (Some(_), Some(Err(_))) => unreachable!(),
+            };
+            if !change_first {
+                return self.base.next();
+            }
+
+            let (k, v) = change?;
+            self.bound = Bound::Excluded(k.clone());
+            if let Some(v) = v {
+                return Some(Ok((
+                    k.clone().into_boxed_slice(),
+                    v.clone().into_boxed_slice(),
+                )));
+            }
+        }
+    }
+}
+
+/// Storage keeping writes in memory on top of another storage, which is not modified
+/// until `commit`. Reads see both.
+pub struct OverlayStorage {
+    base: Arc<dyn Storage>,
+    changes: RwLock<Changes>,
+    // Every write, to apply them to the base in the same order.
+    batch: Mutex<WriteBatch>,
+}
+
+impl OverlayStorage {
+    pub fn new(base: Arc<dyn Storage>) -> Self {
+        Self {
+            base,
+            changes: RwLock::new(Changes::default()),
+            batch: Mutex::new(WriteBatch::default()),
+        }
+    }
+
+    fn lock(
+        &self,
+    ) -> Result<(
+        std::sync::RwLockWriteGuard<'_, Changes>,
+        std::sync::MutexGuard<'_, WriteBatch>,
+    )> {
+        let changes = self
+            .changes
+            .write()
+            .map_err(|_| eyre!("Overlay storage lock poisoned"))?;
+        let batch = self
+            .batch
+            .lock()
+            .map_err(|_| eyre!("Overlay storage lock poisoned"))?;
+        Ok((changes, batch))
+    }
+
+    /// Writes everything to the base storage in a single batch.
+    pub fn commit(&self) -> Result<()> {
+        let (mut changes, mut batch) = self.lock()?;
+        self.base.write(std::mem::take(&mut batch))?;
+        *changes = Changes::default();
+        Ok(())
+    }
+}
+
+impl ReadStorage for OverlayStorage {
+    fn get_cf(&self, cf: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        layered_get(&*self.base, &self.changes, cf, key)
+    }
+
+    fn iterator_cf<'a>(&'a self, cf: &str, mode: IteratorMode) -> StorageIterator<'a> {
+        layered_iterator(&*self.base, &self.changes, cf, mode)
+    }
+}
+
+impl Storage for OverlayStorage {
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let (mut changes, mut pending) = self.lock()?;
+        for op in batch.ops {
+            changes.apply(&op);
+            pending.ops.push(op);
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn ReadStorage + '_>> {
+        let changes = read_changes(&self.changes)?.clone();
+        Ok(Box::new(OverlaySnapshot {
+            base: self.base.snapshot()?,
+            changes: RwLock::new(changes),
+        }))
+    }
+
+    // Writes that were not committed are not in the base, so they are not made durable.
+    fn flush(&self) -> Result<()> {
+        self.base.flush()
+    }
+}
+
+struct OverlaySnapshot<'a> {
+    base: Box<dyn ReadStorage + 'a>,
+    changes: RwLock<Changes>,
+}
+
+impl ReadStorage for OverlaySnapshot<'_> {
+    fn get_cf(&self, cf: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        layered_get(&*self.base, &self.changes, cf, key)
+    }
+
+    fn iterator_cf<'a>(&'a self, cf: &str, mode: IteratorMode) -> StorageIterator<'a> {
+        layered_iterator(&*self.base, &self.changes, cf, mode)
+    }
+}
@@ -0,0 +1,433 @@
+#[cfg(test)]
+mod tests {
+    use aggkit_rust::claim_proof::{
+        Claim, ClaimStatus, ClaimVerification, GlobalIndex, claim_proof, claim_status,
+        global_index, verify_claim,
+    };
+    use aggkit_rust::contracts::PolygonZkEVMBridge::ClaimEvent as ClaimEventV1;
+    use aggkit_rust::contracts::PolygonZkEVMBridgeV2::BridgeEvent;
+    use aggkit_rust::leaf_bridge::{
+        BridgeEvents, LeafBridge, RawBridgeEvent, RawClaimEvent, UnsetClaim,
+    };
+    use aggkit_rust::leaf_l1infotree::L1InfoLeafData;
+    use aggkit_rust::merkle_tree::{
+        MerkleForest, TreeType, calculate_merkle_root, verify_merkle_proof,
+    };
+    use alloy::primitives::{B256, Bytes, U256, address};
+    use alloy::rpc::types::Log;
+
+    fn deposit(network_id: u32, deposit_count: u32) -> LeafBridge {
+        LeafBridge::new(BridgeEvent {
+            leafType: 0,
+            originNetwork: network_id,
+            originAddress: address!("0x1111111111111111111111111111111111111111"),
+            destinationNetwork: 5,
+            destinationAddress: address!("0x2222222222222222222222222222222222222222"),
+            amount: U256::from(deposit_count),
+            metadata: Bytes::new(),
+            depositCount: deposit_count,
+        })
+    }
+
+    fn info_leaf(mer: B256, rer: B256, block: u64) -> L1InfoLeafData {
+        L1InfoLeafData {
+            mer,
+            rer,
+            prev_block_hash: B256::repeat_byte(0xaa),
+            timestamp: 1_700_000_000 + block,
+            block,
+        }
+    }
+
+    #[test]
+    fn test_global_index() {
+        assert_eq!(global_index(0, 5), (U256::from(1) << 64) + U256::from(5));
+        assert_eq!(global_index(1, 5), U256::from(5));
+        assert_eq!(global_index(3, 7), (U256::from(2) << 32) + U256::from(7));
+
+        for (network_id, deposit_count) in [(0, 0), (0, u32::MAX), (1, 5), (7, 123456)] {
+            let decoded = GlobalIndex::decode(global_index(network_id, deposit_count));
+            assert_eq!(decoded.mainnet_flag, network_id == 0);
+            assert_eq!(decoded.source_network(), network_id);
+            assert_eq!(decoded.local_index, deposit_count);
+        }
+
+        // The rollup index is ignored for mainnet deposits.
+        let decoded = GlobalIndex::decode((U256::from(1) << 64) | (U256::from(3) << 32));
+        assert_eq!(decoded.rollup_index, 3);
+        assert_eq!(decoded.source_network(), 0);
+    }
+
+    #[test]
+    fn test_claim_v1() {
+        let log = |index| Log {
+            inner: alloy::primitives::Log {
+                address: address!("0x2a3DD3EB832aF982ec71669E178424b10Dca2EDe"),
+                data: ClaimEventV1 {
+                    index,
+                    originNetwork: 0,
+                    originAddress: address!("0x1111111111111111111111111111111111111111"),
+                    destinationAddress: address!("0x2222222222222222222222222222222222222222"),
+                    amount: U256::from(10),
+                },
+            },
+            block_hash: None,
+            block_number: Some(100),
+            block_timestamp: None,
+            transaction_hash: Some(B256::repeat_byte(1)),
+            transaction_index: None,
+            log_index: Some(3),
+            removed: false,
+        };
+
+        // Claims in mainnet come from rollup 1, and claims in rollup 1 from mainnet.
+        let claim = RawClaimEvent::from_log_v1(&log(4), 0).unwrap();
+        assert_eq!(GlobalIndex::decode(claim.global_index).source_network(), 1);
+        let claim = RawClaimEvent::from_log_v1(&log(4), 1).unwrap();
+        assert_eq!(claim.global_index, global_index(0, 4));
+
+        let decoded = RawClaimEvent::from_bytes(&claim.to_bytes()).unwrap();
+        assert_eq!(decoded.to_bytes(), claim.to_bytes());
+        assert!(RawClaimEvent::from_bytes(&claim.to_bytes()[1..]).is_err());
+    }
+
+    #[test]
+    fn test_mainnet_claim_proof() {
+        let t = MerkleForest::in_memory();
+        let mainnet = TreeType::LocalExitTree(0);
+
+        let mut mers = vec![B256::ZERO];
+        for i in 0..10 {
+            t.append_events(0, &[(deposit(0, i), i as u64)]).unwrap();
+            mers.push(t.get_root(&mainnet).unwrap().unwrap());
+        }
+
+        // Leaves of the L1 Info Tree with 0, 3, 3, 7 and 10 mainnet deposits.
+        let rer = B256::repeat_byte(0xbb);
+        for (i, count) in [0, 3, 3, 7, 10].iter().enumerate() {
+            t.append_l1info_leaf(&info_leaf(mers[*count], rer, i as u64))
+                .unwrap();
+        }
+
+        // Each deposit is proven against the first leaf including it.
+        for (deposit_count, expected_index) in [(0, 1), (2, 1), (3, 3), (6, 3), (7, 4), (9, 4)] {
+            let proof = claim_proof(&t, 0, deposit_count).unwrap().unwrap();
+            assert_eq!(proof.l1_info_tree_index, expected_index);
+            assert_eq!(proof.global_index, global_index(0, deposit_count));
+            assert_eq!(proof.proof_rollup_exit_root, [B256::ZERO; 32]);
+
+            let leaf = t.get_l1info_leaf(expected_index).unwrap().unwrap();
+            assert_eq!(proof.l1_info_leaf, leaf);
+            assert_eq!(
+                calculate_merkle_root(
+                    &deposit(0, deposit_count).hashed_leaf(),
+                    &proof.proof_local_exit_root,
+                    deposit_count as u64
+                ),
+                leaf.mer
+            );
+        }
+
+        // Deposits not in the L1 Info Tree yet, or not indexed, can't be claimed.
+        t.append_events(0, &[(deposit(0, 10), 10)]).unwrap();
+        assert!(claim_proof(&t, 0, 10).unwrap().is_none());
+        assert!(claim_proof(&t, 0, 11).unwrap().is_none());
+
+        // Neither while the L1 Info Tree is indexed ahead of the bridge.
+        t.append_l1info_leaf(&info_leaf(B256::repeat_byte(0x11), rer, 5))
+            .unwrap();
+        assert!(claim_proof(&t, 0, 10).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rollup_claim_proof() {
+        let t = MerkleForest::in_memory();
+        let rollup = TreeType::LocalExitTree(2);
+
+        for i in 0..5 {
+            t.append_events(2, &[(deposit(2, i), i as u64)]).unwrap();
+        }
+        let ler = t.get_root(&rollup).unwrap().unwrap();
+
+        // No exit root of the rollup was verified yet.
+        assert!(claim_proof(&t, 2, 0).unwrap().is_none());
+
+        t.set_rollup_leaf(1, &B256::repeat_byte(0xcc), 1, 0)
+            .unwrap();
+        t.set_rollup_leaf(2, &ler, 2, 0).unwrap();
+        let rer = t.get_root(&TreeType::RollupExitTree).unwrap().unwrap();
+
+        // Verified, but the new rollup exit root is not in the L1 Info Tree.
+        t.append_l1info_leaf(&info_leaf(B256::ZERO, B256::ZERO, 1))
+            .unwrap();
+        assert!(claim_proof(&t, 2, 0).unwrap().is_none());
+
+        t.append_l1info_leaf(&info_leaf(B256::ZERO, rer, 2))
+            .unwrap();
+        for deposit_count in 0..5 {
+            let proof = claim_proof(&t, 2, deposit_count).unwrap().unwrap();
+            assert_eq!(proof.l1_info_tree_index, 1);
+            assert_eq!(proof.global_index, global_index(2, deposit_count));
+            assert_eq!(proof.l1_info_leaf.rer, rer);
+
+            let local_exit_root = calculate_merkle_root(
+                &deposit(2, deposit_count).hashed_leaf(),
+                &proof.proof_local_exit_root,
+                deposit_count as u64,
+            );
+            assert_eq!(local_exit_root, ler);
+            assert_eq!(
+                calculate_merkle_root(&local_exit_root, &proof.proof_rollup_exit_root, 1),
+                rer
+            );
+        }
+
+        // Or an exit root of the rollup that is indexed.
+        let t_behind = MerkleForest::in_memory();
+        t_behind.append_events(2, &[(deposit(2, 0), 0)]).unwrap();
+        t_behind
+            .set_rollup_leaf(2, &B256::repeat_byte(0xdd), 1, 0)
+            .unwrap();
+        let rer_ahead = t_behind
+            .get_root(&TreeType::RollupExitTree)
+            .unwrap()
+            .unwrap();
+        t_behind
+            .append_l1info_leaf(&info_leaf(B256::ZERO, rer_ahead, 1))
+            .unwrap();
+        assert!(claim_proof(&t_behind, 2, 0).unwrap().is_none());
+
+        // New deposits need a new verified exit root first.
+        t.append_events(2, &[(deposit(2, 5), 5)]).unwrap();
+        assert!(claim_proof(&t, 2, 5).unwrap().is_none());
+
+        // Older deposits are still proven against the first leaf including them, with the
+        // Rollup Exit Tree of that leaf.
+        let ler_2 = t.get_root(&rollup).unwrap().unwrap();
+        t.set_rollup_leaf(2, &ler_2, 3, 0).unwrap();
+        let rer_2 = t.get_root(&TreeType::RollupExitTree).unwrap().unwrap();
+        t.append_l1info_leaf(&info_leaf(B256::ZERO, rer_2, 3))
+            .unwrap();
+
+        let proof = claim_proof(&t, 2, 4).unwrap().unwrap();
+        assert_eq!(proof.l1_info_tree_index, 1);
+        assert_eq!(
+            calculate_merkle_root(&ler, &proof.proof_rollup_exit_root, 1),
+            rer
+        );
+        let proof = claim_proof(&t, 2, 5).unwrap().unwrap();
+        assert_eq!(proof.l1_info_tree_index, 2);
+        assert_eq!(
+            calculate_merkle_root(&ler_2, &proof.proof_rollup_exit_root, 1),
+            rer_2
+        );
+    }
+
+    fn claims(claims: Vec<RawClaimEvent>) -> BridgeEvents {
+        BridgeEvents {
+            claims,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_claim_status() {
+        let t = MerkleForest::in_memory();
+
+        // A mainnet deposit to network 5.
+        let raw = RawBridgeEvent {
+            bridge_event: deposit(0, 0).bridge_event,
+            tx_hash: B256::repeat_byte(1),
+            block_number: 10,
+            log_index: 0,
+        };
+        let status = || claim_status(&t, 0, 0).unwrap().map(|(_, status)| status);
+        assert!(status().is_none());
+        t.append_bridge_events(
+            0,
+            &BridgeEvents {
+                deposits: vec![raw.clone()],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(matches!(status(), Some(ClaimStatus::Pending)));
+        assert_eq!(claim_status(&t, 0, 0).unwrap().unwrap().0, 5);
+
+        let mer = t.get_root(&TreeType::LocalExitTree(0)).unwrap().unwrap();
+        t.append_l1info_leaf(&info_leaf(mer, B256::ZERO, 11))
+            .unwrap();
+        assert!(matches!(status(), Some(ClaimStatus::ReadyToClaim)));
+
+        // Claims in other networks or of other deposits don't count.
+        let claim = |network_id, deposit_count, block| RawClaimEvent {
+            global_index: global_index(network_id, deposit_count),
+            origin_network: 0,
+            origin_address: address!("0x1111111111111111111111111111111111111111"),
+            destination_address: address!("0x2222222222222222222222222222222222222222"),
+            amount: U256::from(deposit_count),
+            tx_hash: B256::repeat_byte(block as u8),
+            block_number: block,
+            log_index: 0,
+        };
+        t.append_bridge_events(4, &claims(vec![claim(0, 0, 20)]))
+            .unwrap();
+        t.append_bridge_events(5, &claims(vec![claim(0, 1, 20), claim(1, 0, 21)]))
+            .unwrap();
+        assert!(matches!(status(), Some(ClaimStatus::ReadyToClaim)));
+
+        t.append_bridge_events(5, &claims(vec![claim(0, 0, 22)]))
+            .unwrap();
+        match status() {
+            Some(ClaimStatus::Claimed(c)) => {
+                assert_eq!(c.tx_hash, B256::repeat_byte(22));
+                assert_eq!(c.block_number, 22);
+            }
+            _ => panic!("deposit should be claimed"),
+        }
+
+        // Sovereign chains can unset a claim.
+        let unset = UnsetClaim {
+            source_network: 0,
+            leaf_index: 0,
+            block_number: 23,
+            log_index: 0,
+        };
+        t.append_bridge_events(
+            5,
+            &BridgeEvents {
+                unset_claims: vec![unset.clone()],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(matches!(status(), Some(ClaimStatus::ReadyToClaim)));
+        assert_eq!(t.get_unset_claims(5).unwrap(), vec![unset]);
+        t.rollback_to_block(TreeType::LocalExitTree(5), 22).unwrap();
+        assert!(t.get_unset_claims(5).unwrap().is_empty());
+        assert!(matches!(status(), Some(ClaimStatus::Claimed(_))));
+
+        // A reorg of the destination network can undo the claim.
+        t.rollback_to_block(TreeType::LocalExitTree(5), 21).unwrap();
+        assert!(matches!(status(), Some(ClaimStatus::ReadyToClaim)));
+        assert!(t.get_claim(5, 1, 0).unwrap().is_some());
+        t.rollback_to_block(TreeType::LocalExitTree(5), 20).unwrap();
+        assert!(t.get_claim(5, 1, 0).unwrap().is_none());
+        assert!(t.get_claim(5, 0, 1).unwrap().is_some());
+        assert!(t.get_claim(4, 0, 0).unwrap().is_some());
+
+        // Pending while the L1 Info Tree is indexed ahead of the bridge.
+        let t = MerkleForest::in_memory();
+        t.append_bridge_events(
+            0,
+            &BridgeEvents {
+                deposits: vec![raw],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        t.append_l1info_leaf(&info_leaf(B256::repeat_byte(0x11), B256::ZERO, 11))
+            .unwrap();
+        assert!(matches!(
+            claim_status(&t, 0, 0).unwrap(),
+            Some((5, ClaimStatus::Pending))
+        ));
+    }
+
+    #[test]
+    fn test_verify_claim() {
+        let t = MerkleForest::in_memory();
+        for i in 0..3 {
+            t.append_events(0, &[(deposit(0, i), i as u64)]).unwrap();
+            t.append_events(2, &[(deposit(2, i), i as u64)]).unwrap();
+        }
+        let ler = t.get_root(&TreeType::LocalExitTree(2)).unwrap().unwrap();
+        t.set_rollup_leaf(2, &ler, 3, 0).unwrap();
+        let mer = t.get_root(&TreeType::LocalExitTree(0)).unwrap().unwrap();
+        let rer = t.get_root(&TreeType::RollupExitTree).unwrap().unwrap();
+        t.append_l1info_leaf(&info_leaf(B256::ZERO, B256::ZERO, 1))
+            .unwrap();
+        t.append_l1info_leaf(&info_leaf(mer, rer, 2)).unwrap();
+        let l1_info_root = t.get_root(&TreeType::L1InfoTree).unwrap().unwrap();
+
+        let claim = |network_id, deposit_count| {
+            let proof = claim_proof(&t, network_id, deposit_count).unwrap().unwrap();
+            Claim {
+                leaf: deposit(network_id, deposit_count).hashed_leaf(),
+                proof_local_exit_root: proof.proof_local_exit_root,
+                proof_rollup_exit_root: proof.proof_rollup_exit_root,
+                global_index: proof.global_index,
+                mainnet_exit_root: proof.l1_info_leaf.mer,
+                rollup_exit_root: proof.l1_info_leaf.rer,
+            }
+        };
+        let valid = ClaimVerification::Valid {
+            l1_info_tree_index: 1,
+            global_exit_root: info_leaf(mer, rer, 2).ger(),
+            l1_info_root,
+        };
+
+        // Proofs built by `claim_proof` are valid.
+        for (network_id, deposit_count) in [(0, 0), (0, 2), (2, 0), (2, 1)] {
+            assert_eq!(
+                verify_claim(&t, &claim(network_id, deposit_count), l1_info_root, 2).unwrap(),
+                valid
+            );
+        }
+
+        let invalid = |claim: &Claim| {
+            matches!(
+                verify_claim(&t, claim, l1_info_root, 2).unwrap(),
+                ClaimVerification::Invalid(_)
+            )
+        };
+
+        // Another deposit.
+        let mut c = claim(0, 1);
+        c.leaf = deposit(0, 2).hashed_leaf();
+        assert!(invalid(&c));
+
+        // Wrong index, or unused bits of the global index set.
+        let mut c = claim(2, 1);
+        c.global_index = global_index(2, 0);
+        assert!(invalid(&c));
+        let mut c = claim(0, 1);
+        c.global_index |= U256::from(1) << 40;
+        assert!(invalid(&c));
+
+        // The global exit root was added after the onchain root.
+        let old_root = t.get_root_at(TreeType::L1InfoTree, 1).unwrap().unwrap();
+        assert!(matches!(
+            verify_claim(&t, &claim(0, 0), old_root, 1).unwrap(),
+            ClaimVerification::Invalid(_)
+        ));
+
+        // The local tree doesn't match the onchain one.
+        assert!(verify_claim(&t, &claim(0, 0), B256::repeat_byte(1), 2).is_err());
+
+        // Rollup deposit with a bad proof against the rollup exit root.
+        let mut c = claim(2, 1);
+        c.proof_rollup_exit_root[0] = B256::repeat_byte(1);
+        assert!(invalid(&c));
+
+        // Valid against its roots, but those are not in the L1 Info Tree.
+        let mut c = claim(0, 0);
+        c.rollup_exit_root = B256::repeat_byte(1);
+        assert!(invalid(&c));
+    }
+
+    #[test]
+    fn test_verify_merkle_proof() {
+        let t = MerkleForest::in_memory();
+        for i in 0..5 {
+            t.append_events(0, &[(deposit(0, i), i as u64)]).unwrap();
+        }
+        let root = t.get_root(&TreeType::LocalExitTree(0)).unwrap().unwrap();
+
+        let leaf = deposit(0, 3).hashed_leaf();
+        let proof = t.merkle_proof(TreeType::LocalExitTree(0), 3).unwrap();
+        assert!(verify_merkle_proof(&leaf, &proof, 3, &root));
+        assert!(!verify_merkle_proof(&leaf, &proof, 2, &root));
+        assert!(!verify_merkle_proof(&leaf, &proof, 3, &B256::ZERO));
+    }
+}
@@ -0,0 +1,39 @@
+#[cfg(test)]
+mod tests {
+    use aggkit_rust::indexer::BlockRange;
+
+    #[test]
+    fn test_block_range() {
+        let range = BlockRange::new(10000);
+        assert_eq!(range.get(), 10000);
+
+        // Halves the failed range, concurrent failures don't shrink it twice.
+        assert_eq!(range.shrink(10000), 5000);
+        assert_eq!(range.shrink(10000), 5000);
+        assert_eq!(range.shrink(5000), 2500);
+        assert_eq!(range.shrink(1), 1);
+        assert_eq!(range.get(), 1);
+
+        // Doubles after enough successes, up to the configured range.
+        let mut grown = vec![];
+        for _ in 0..(BlockRange::GROW_AFTER * 20) {
+            grown.extend(range.record_success());
+        }
+        assert_eq!(
+            grown,
+            vec![
+                2, 4, 8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 8192, 10000
+            ]
+        );
+        assert_eq!(range.get(), 10000);
+
+        // A failure resets the count.
+        range.shrink(10000);
+        for _ in 1..BlockRange::GROW_AFTER {
+            assert_eq!(range.record_success(), None);
+        }
+        range.shrink(10000);
+        assert_eq!(range.record_success(), None);
+        assert_eq!(range.get(), 5000);
+    }
+}
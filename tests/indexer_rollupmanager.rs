@@ -0,0 +1,81 @@
+#[cfg(test)]
+mod tests {
+    use aggkit_rust::contracts::PolygonRollupManager;
+    use aggkit_rust::contracts::PolygonRollupManager::VerifyPessimisticStateTransition;
+    use aggkit_rust::indexer::EventProcessor;
+    use aggkit_rust::indexer_rollupmanager::RollupManagerEventProcessor;
+    use aggkit_rust::merkle_tree::{MerkleForest, TreeType};
+    use alloy::primitives::{Address, B256, address};
+    use alloy::providers::ProviderBuilder;
+    use alloy::providers::mock::Asserter;
+    use alloy::rpc::types::Log;
+    use alloy::sol_types::SolEvent;
+    use std::sync::Arc;
+
+    fn verify_pessimistic(rollup_id: u32, local_exit_root: B256, block: u64) -> Log {
+        Log {
+            inner: alloy::primitives::Log {
+                address: address!("0x5132A183E9F3CB7C848b0AAC5Ae0c4f0491B7aB2"),
+                data: VerifyPessimisticStateTransition {
+                    rollupID: rollup_id,
+                    prevPessimisticRoot: B256::ZERO,
+                    newPessimisticRoot: B256::repeat_byte(0x11),
+                    prevLocalExitRoot: B256::ZERO,
+                    newLocalExitRoot: local_exit_root,
+                    l1InfoRoot: B256::repeat_byte(0x22),
+                    trustedAggregator: Address::ZERO,
+                }
+                .encode_log_data(),
+            },
+            block_hash: None,
+            block_number: Some(block),
+            block_timestamp: None,
+            transaction_hash: Some(B256::repeat_byte(1)),
+            transaction_index: None,
+            log_index: Some(0),
+            removed: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_pessimistic_state_transition() {
+        let asserter = Asserter::new();
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        let processor = RollupManagerEventProcessor {
+            rollup_manager: PolygonRollupManager::new(
+                address!("0x5132A183E9F3CB7C848b0AAC5Ae0c4f0491B7aB2"),
+                Arc::new(provider),
+            ),
+        };
+
+        let t = MerkleForest::in_memory();
+        let ler = B256::repeat_byte(0x33);
+        let expected = MerkleForest::in_memory();
+        expected.set_rollup_leaf(2, &ler, 100, 0).unwrap();
+        let rer = expected
+            .get_root(&TreeType::RollupExitTree)
+            .unwrap()
+            .unwrap();
+
+        // The Rollup Exit Root is checked against the onchain one after the chunk.
+        asserter.push_success(&rer);
+        processor
+            .process_events(&t, 100..=100, &[verify_pessimistic(2, ler, 100)])
+            .await
+            .unwrap();
+        assert_eq!(t.get_rollup_leaf(2).unwrap(), Some(ler));
+        assert_eq!(t.get_root(&TreeType::RollupExitTree).unwrap(), Some(rer));
+
+        // A different onchain root fails the chunk.
+        asserter.push_success(&B256::repeat_byte(0x44));
+        let err = processor
+            .process_events(
+                &t,
+                101..=101,
+                &[verify_pessimistic(2, B256::repeat_byte(0x55), 101)],
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Rollup Exit Root mismatch"));
+    }
+}
@@ -7,6 +7,9 @@ mod leaf_tests {
     use aggkit_rust::contracts::PolygonZkEVMGlobalExitRootV2::UpdateL1InfoTree;
     use aggkit_rust::contracts::PolygonZkEVMGlobalExitRootV2::UpdateL1InfoTreeV2;
     use aggkit_rust::leaf_bridge::LeafBridge;
+    use aggkit_rust::leaf_bridge::RawBridgeEvent;
+    use aggkit_rust::leaf_bridge::WrappedToken;
+    use aggkit_rust::leaf_bridge::{SovereignChange, SovereignEvent};
     use alloy::hex::FromHex;
     use alloy::primitives::B256;
     use alloy::primitives::Bytes;
@@ -42,4 +45,96 @@ mod leaf_tests {
             hex!("0x350216a4120cc1547aa7dabd5a7f5428f74cf70930efd6f76bee6a36b5e39f34")
         );
     }
+
+    #[test]
+    fn test_raw_bridge_event_roundtrip() {
+        let raw = RawBridgeEvent {
+            bridge_event: BridgeEvent {
+                leafType: 1,
+                originNetwork: 3,
+                originAddress: address!("0x1111111111111111111111111111111111111111"),
+                destinationNetwork: 2,
+                destinationAddress: address!("0x2222222222222222222222222222222222222222"),
+                amount: uint!(6_666_666_U256),
+                metadata: Bytes::from_static(b"some metadata"),
+                depositCount: 7,
+            },
+            tx_hash: B256::repeat_byte(0xab),
+            block_number: 19229151,
+            log_index: 42,
+        };
+
+        let decoded = RawBridgeEvent::from_bytes(&raw.to_bytes()).unwrap();
+        assert_eq!(decoded.to_bytes(), raw.to_bytes());
+        assert_eq!(decoded.bridge_event.metadata, raw.bridge_event.metadata);
+        assert_eq!(decoded.bridge_event.amount, raw.bridge_event.amount);
+        assert_eq!(decoded.tx_hash, raw.tx_hash);
+        assert_eq!(decoded.block_number, 19229151);
+        assert_eq!(decoded.log_index, 42);
+        assert_eq!(decoded.leaf().hashed_leaf(), raw.leaf().hashed_leaf());
+
+        // Empty metadata is fine, but a truncated header is not.
+        let mut empty = raw.clone();
+        empty.bridge_event.metadata = Bytes::new();
+        assert_eq!(
+            RawBridgeEvent::from_bytes(&empty.to_bytes())
+                .unwrap()
+                .bridge_event
+                .metadata,
+            Bytes::new()
+        );
+        assert!(RawBridgeEvent::from_bytes(&empty.to_bytes()[1..]).is_err());
+    }
+
+    #[test]
+    fn test_wrapped_token_roundtrip() {
+        let token = WrappedToken {
+            origin_network: 0,
+            origin_address: address!("0x1111111111111111111111111111111111111111"),
+            wrapped_address: address!("0x2222222222222222222222222222222222222222"),
+            metadata: Bytes::from_static(b"some metadata"),
+            block_number: 19229151,
+            log_index: 3,
+        };
+        assert_eq!(WrappedToken::from_bytes(&token.to_bytes()).unwrap(), token);
+        assert!(WrappedToken::from_bytes(&token.to_bytes()[..59]).is_err());
+    }
+
+    #[test]
+    fn test_sovereign_event_roundtrip() {
+        let changes = vec![
+            SovereignChange::SetToken {
+                origin_network: 3,
+                origin_address: address!("0x1111111111111111111111111111111111111111"),
+                sovereign_address: address!("0x2222222222222222222222222222222222222222"),
+                is_not_mintable: true,
+            },
+            SovereignChange::MigrateLegacyToken {
+                sender: address!("0x3333333333333333333333333333333333333333"),
+                legacy_address: address!("0x2222222222222222222222222222222222222222"),
+                updated_address: address!("0x4444444444444444444444444444444444444444"),
+                amount: Uint::from(1000),
+            },
+            SovereignChange::RemoveLegacyToken {
+                sovereign_address: address!("0x2222222222222222222222222222222222222222"),
+            },
+            SovereignChange::SetWeth {
+                sovereign_address: address!("0x5555555555555555555555555555555555555555"),
+                is_not_mintable: false,
+            },
+            SovereignChange::SetBridgeManager {
+                bridge_manager: address!("0x6666666666666666666666666666666666666666"),
+            },
+        ];
+        for (i, change) in changes.into_iter().enumerate() {
+            let event = SovereignEvent {
+                change,
+                block_number: 100 + i as u64,
+                log_index: i as u64,
+            };
+            let bytes = event.to_bytes();
+            assert_eq!(SovereignEvent::from_bytes(&bytes).unwrap(), event);
+            assert!(SovereignEvent::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        }
+    }
 }
@@ -2,6 +2,7 @@
 mod leaf_tests {
     use aggkit_rust::contracts::PolygonZkEVMGlobalExitRootV2::UpdateL1InfoTree;
     use aggkit_rust::contracts::PolygonZkEVMGlobalExitRootV2::UpdateL1InfoTreeV2;
+    use aggkit_rust::leaf_l1infotree::L1InfoLeafData;
     use aggkit_rust::leaf_l1infotree::L2EventOrBlock;
     use aggkit_rust::leaf_l1infotree::LeafL1InfoTree;
     use alloy::hex::FromHex;
@@ -61,4 +62,43 @@ mod leaf_tests {
             hex!("0x53876e8afa7a663aa40a380be957c481841f080b5a4ac17f0873b64f39cb66f9")
         );
     }
+
+    #[test]
+    fn test_leaf_data() {
+        // Tests the persisted leaf data leads to the same leaf and survives a roundtrip.
+        let leaf = LeafL1InfoTree::new(
+            UpdateL1InfoTree {
+                mainnetExitRoot: hex!(
+                    "0x0af758850c3a010370afa0f780d091a9e72007f43e9f147505ca709e0f7d9b1c"
+                ),
+                rollupExitRoot: hex!(
+                    "0xdbf6a41b961855c5c76e0fa2264fb104706925d2b73f6f5261ded3ff6cb1798f"
+                ),
+            },
+            L2EventOrBlock::V2Event(UpdateL1InfoTreeV2 {
+                blockhash: hex!(
+                    "0x40ce3a02825dc9bd7aacb530d64071f91d4f50fcad523bd5779d81d535420060"
+                )
+                .into(),
+                leafCount: 1,
+                minTimestamp: 1707911747,
+                currentL1InfoRoot: B256::ZERO,
+            }),
+            Log {
+                block_number: Some(19229151),
+                ..Default::default()
+            },
+        );
+
+        let data = leaf.data().unwrap();
+        assert_eq!(data.block, 19229151);
+        assert_eq!(data.ger(), leaf.ger());
+        assert_eq!(data.info_root_leaf(), leaf.info_root_leaf());
+        assert_eq!(L1InfoLeafData::from_bytes(&data.to_bytes()).unwrap(), data);
+        assert!(L1InfoLeafData::from_bytes(&data.to_bytes()[1..]).is_err());
+
+        // The block is needed.
+        let leaf = LeafL1InfoTree::new(leaf.v1_event, leaf.v2_event_or_block, Log::default());
+        assert!(leaf.data().is_err());
+    }
 }
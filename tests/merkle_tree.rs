@@ -7,20 +7,34 @@ mod tests {
     };
 
     use aggkit_rust::leaf_bridge::LeafBridge;
+    use aggkit_rust::leaf_bridge::{
+        BridgeEvents, RawBridgeEvent, SovereignChange, SovereignConfig, SovereignEvent,
+        SovereignToken, WrappedToken,
+    };
+    use aggkit_rust::leaf_l1infotree::L1InfoLeafData;
 
     use aggkit_rust::merkle_tree::calculate_merkle_root;
-    use aggkit_rust::merkle_tree::{MerkleForest, TreeType};
+    use aggkit_rust::merkle_tree::{Inconsistency, MerkleForest, TreeType};
+    use aggkit_rust::rollup::{RollupInfo, RollupType};
+    use aggkit_rust::storage::{
+        IteratorMode, MemoryStorage, ReadStorage, Storage, StorageIterator,
+        WriteBatch as StorageWriteBatch,
+    };
     use alloy::hex::FromHex;
+    use alloy::primitives::Address;
     use alloy::primitives::B256;
     use alloy::primitives::Bytes;
     use alloy::primitives::FixedBytes;
     use alloy::primitives::Uint;
     use alloy::primitives::address;
+    use alloy::primitives::keccak256;
     use rocksdb::{
         ColumnFamily, ColumnFamilyDescriptor, DB, DBCompressionType, Options, WriteBatch,
     };
 
     use alloy::uint;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     // Util macro to avoid boilerplate code
     macro_rules! hex {
@@ -29,6 +43,24 @@ mod tests {
         };
     }
 
+    fn deposits(deposits: &[RawBridgeEvent]) -> BridgeEvents {
+        BridgeEvents {
+            deposits: deposits.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    // Arbitrary but different L1 Info Tree leaf for each seed.
+    fn info_leaf(seed: u64, block: u64) -> L1InfoLeafData {
+        L1InfoLeafData {
+            mer: keccak256(seed.to_be_bytes()),
+            rer: B256::repeat_byte(1),
+            prev_block_hash: B256::repeat_byte(2),
+            timestamp: 1_700_000_000 + seed,
+            block,
+        }
+    }
+
     #[test]
     fn test_playing() {
         let leaves = vec![1, 2, 3, 7];
@@ -41,8 +73,7 @@ mod tests {
 
     #[test]
     fn test_merkle_proofs() {
-        let _ = std::fs::remove_dir_all("db_test");
-        let mut t = MerkleForest::open("db_test").unwrap();
+        let mut t = MerkleForest::in_memory();
         // TODO:;
     }
 
@@ -77,8 +108,7 @@ mod tests {
     #[test]
     fn test_todo() {
         // TODO: test that one by one it works. and also that both at the same time.
-        let _ = std::fs::remove_dir_all("db_test");
-        let mut t = MerkleForest::open("db_test").unwrap();
+        let mut t = MerkleForest::in_memory();
 
         let leaf1 = LeafBridge::new(BridgeEvent {
             leafType: 1,
@@ -102,7 +132,7 @@ mod tests {
             depositCount: 1, // its not really used.
         });
 
-        t.append_events(1, &[leaf1.clone()], 1).unwrap();
+        t.append_events(1, &[(leaf1.clone(), 1)]).unwrap();
         assert_eq!(
             t.get_root(&TreeType::LocalExitTree(1)).unwrap(),
             Some(hex!(
@@ -110,7 +140,7 @@ mod tests {
             ))
         );
 
-        t.append_events(1, &[leaf2.clone()], 2).unwrap();
+        t.append_events(1, &[(leaf2.clone(), 2)]).unwrap();
         assert_eq!(
             t.get_root(&TreeType::LocalExitTree(1)).unwrap(),
             Some(hex!(
@@ -121,16 +151,14 @@ mod tests {
 
     #[test]
     fn test_get_root_empty_tree() -> Result<(), eyre::Error> {
-        let _ = std::fs::remove_dir_all("db_test");
-        let t = MerkleForest::open("db_test").unwrap();
+        let t = MerkleForest::in_memory();
         println!("root: {:?}", t.get_root(&TreeType::RollupExitTree).unwrap());
         Ok(())
     }
 
     #[test]
     fn test_todo_testingsometuff() -> Result<(), eyre::Error> {
-        let _ = std::fs::remove_dir_all("db_test");
-        let mut t = MerkleForest::open("db_test").unwrap();
+        let mut t = MerkleForest::in_memory();
 
         let rollup_exit_roots = vec![
             (
@@ -217,7 +245,7 @@ mod tests {
 
         for (rollup_id, exit_root) in rollup_exit_roots {
             // note that rollup 1 is placed at 0, etc.
-            t.set_rollup_leaf(rollup_id, &exit_root, 1).unwrap();
+            t.set_rollup_leaf(rollup_id, &exit_root, 1, 0).unwrap();
         }
         println!("root: {:?}", t.get_root(&TreeType::RollupExitTree).unwrap());
         assert_eq!(
@@ -332,8 +360,7 @@ mod tests {
     #[test]
     fn test_anothertodo() -> Result<(), eyre::Error> {
         // test also that leafs can be modified ok.
-        let _ = std::fs::remove_dir_all("db_test");
-        let mut t = MerkleForest::open("db_test").unwrap();
+        let mut t = MerkleForest::in_memory();
 
         let rollup_exit_roots = vec![
             (
@@ -420,18 +447,18 @@ mod tests {
 
         for (rollup_id, exit_root) in &rollup_exit_roots {
             // note that rollup 1 is placed at 0, etc.
-            t.set_rollup_leaf(*rollup_id, &exit_root, 1).unwrap();
+            t.set_rollup_leaf(*rollup_id, &exit_root, 1, 0).unwrap();
         }
 
         for i in [1, 3, 5, 6] {
             // modify some random leafes
-            t.set_rollup_leaf(i, &B256::default(), 1).unwrap();
+            t.set_rollup_leaf(i, &B256::default(), 1, 0).unwrap();
         }
 
         // Reset all leafs to the same value
         for (rollup_id, exit_root) in &rollup_exit_roots {
             // note that rollup 1 is placed at 0, etc.
-            t.set_rollup_leaf(*rollup_id, &exit_root, 1).unwrap();
+            t.set_rollup_leaf(*rollup_id, &exit_root, 1, 0).unwrap();
         }
         println!("root: {:?}", t.get_root(&TreeType::RollupExitTree).unwrap());
         assert_eq!(
@@ -444,8 +471,1492 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_rollback_to_block() -> Result<(), eyre::Error> {
+        let t = MerkleForest::in_memory();
+
+        let leaf = |i: u32| {
+            LeafBridge::new(BridgeEvent {
+                leafType: 0,
+                originNetwork: 0,
+                originAddress: address!("0x1111111111111111111111111111111111111111"),
+                destinationNetwork: 1,
+                destinationAddress: address!("0x2222222222222222222222222222222222222222"),
+                amount: Uint::from(i),
+                metadata: Bytes::new(),
+                depositCount: i,
+            })
+        };
+
+        // Two leaves per block, in blocks 10, 11, 12...
+        let mut roots = Vec::new();
+        for i in 0..20u32 {
+            t.append_events(1, &[(leaf(i), 10 + (i / 2) as u64)])
+                .unwrap();
+            roots.push(t.get_root(&TreeType::LocalExitTree(1)).unwrap());
+        }
+
+        // Block 15 contains leaves 10 and 11.
+        t.rollback_to_block(TreeType::LocalExitTree(1), 15).unwrap();
+        assert_eq!(t.get_leaf_count(&TreeType::LocalExitTree(1)).unwrap(), 12);
+        assert_eq!(t.get_root(&TreeType::LocalExitTree(1)).unwrap(), roots[11]);
+        assert_eq!(
+            t.get_latest_block(TreeType::LocalExitTree(1)).unwrap(),
+            Some(15)
+        );
+
+        // Inserting the same leaves again leads to the same roots.
+        for i in 12..20u32 {
+            t.append_events(1, &[(leaf(i), 10 + (i / 2) as u64)])
+                .unwrap();
+            assert_eq!(
+                t.get_root(&TreeType::LocalExitTree(1)).unwrap(),
+                roots[i as usize]
+            );
+        }
+
+        // Before the first leaf, the tree is empty.
+        t.rollback_to_block(TreeType::LocalExitTree(1), 9).unwrap();
+        assert_eq!(t.get_leaf_count(&TreeType::LocalExitTree(1)).unwrap(), 0);
+        assert_eq!(t.get_root(&TreeType::LocalExitTree(1)).unwrap(), None);
+
+        // The Rollup Exit Tree is not append-only, it's rebuilt from its past leaves
+        // instead. See test_rollup_exit_tree_history.
+        t.rollback_to_block(TreeType::RollupExitTree, 9).unwrap();
+        assert_eq!(t.get_leaf_count(&TreeType::RollupExitTree).unwrap(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback_old_store() {
+        let path = "db_test_old_store";
+        let _ = std::fs::remove_dir_all(path);
+        let let1 = TreeType::LocalExitTree(1);
+
+        {
+            let t = MerkleForest::open(path).unwrap();
+            for i in 0..4u32 {
+                let leaf = LeafBridge::new(BridgeEvent {
+                    leafType: 0,
+                    originNetwork: 0,
+                    originAddress: Address::ZERO,
+                    destinationNetwork: 1,
+                    destinationAddress: Address::repeat_byte(2),
+                    amount: Uint::from(i),
+                    metadata: Bytes::new(),
+                    depositCount: i,
+                });
+                t.append_events(1, &[(leaf, 10 + i as u64)]).unwrap();
+            }
+        }
+
+        // Stores created before the blocks of the leaves were recorded don't have them.
+        {
+            let cfs = DB::list_cf(&Options::default(), path).unwrap();
+            let db = DB::open_cf(&Options::default(), path, cfs).unwrap();
+            let cf = db.cf_handle("metadata").unwrap();
+            let mut batch = WriteBatch::default();
+            for item in db.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+                let (k, _) = item.unwrap();
+                if k.len() == 10 && k[4] == 2 {
+                    batch.delete_cf(cf, k);
+                }
+            }
+            db.write(batch).unwrap();
+        }
+
+        let t = MerkleForest::open(path).unwrap();
+        let root = t.get_root(&let1).unwrap();
+        for result in [t.rollback_to_block(let1, 11), t.truncate_to(let1, 2)] {
+            let err = result.unwrap_err().to_string();
+            assert!(err.contains("Resync required"), "{}", err);
+        }
+        assert_eq!(t.get_leaf_count(&let1).unwrap(), 4);
+        assert_eq!(t.get_root(&let1).unwrap(), root);
+        assert_eq!(t.get_latest_block(let1).unwrap(), Some(13));
+
+        drop(t);
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn test_truncate_to() -> Result<(), eyre::Error> {
+        let t = MerkleForest::in_memory();
+        let let_tree = TreeType::LocalExitTree(7);
+
+        let leaves: Vec<LeafBridge> = (0..70u32)
+            .map(|i| {
+                LeafBridge::new(BridgeEvent {
+                    leafType: 0,
+                    originNetwork: 0,
+                    originAddress: address!("0x3333333333333333333333333333333333333333"),
+                    destinationNetwork: 7,
+                    destinationAddress: address!("0x4444444444444444444444444444444444444444"),
+                    amount: Uint::from(i * 1000),
+                    metadata: Bytes::from(format!("metadata_{}", i).into_bytes()),
+                    depositCount: i,
+                })
+            })
+            .collect();
+
+        // Leaf i is inserted in block 100 + i. roots[i] is the root with i + 1 leaves.
+        let mut roots = Vec::new();
+        for (i, leaf) in leaves.iter().enumerate() {
+            t.append_events(7, &[(leaf.clone(), 100 + i as u64)])
+                .unwrap();
+            roots.push(t.get_root(&let_tree).unwrap().unwrap());
+        }
+
+        // Cover power of two boundaries and arbitrary counts.
+        for count in [65u32, 64, 63, 33, 32, 17, 2, 1] {
+            t.truncate_to(let_tree, count).unwrap();
+            assert_eq!(t.get_leaf_count(&let_tree).unwrap(), count);
+            assert_eq!(
+                t.get_root(&let_tree).unwrap(),
+                Some(roots[count as usize - 1])
+            );
+            assert_eq!(
+                t.get_latest_block(let_tree).unwrap(),
+                Some(100 + count as u64 - 1)
+            );
+
+            // Proofs of the remaining leaves are valid against the restored root.
+            for i in 0..count {
+                let proof = t.merkle_proof(let_tree, i as u64).unwrap();
+                assert_eq!(
+                    calculate_merkle_root(&leaves[i as usize].hashed_leaf(), &proof, i as u64),
+                    roots[count as usize - 1]
+                );
+            }
+        }
+
+        // Growing the tree again leads to the same roots.
+        for i in 1..70 {
+            t.append_events(7, &[(leaves[i].clone(), 100 + i as u64)])
+                .unwrap();
+            assert_eq!(t.get_root(&let_tree).unwrap(), Some(roots[i]));
+        }
+
+        // Can't truncate to more leaves than the tree has.
+        assert!(t.truncate_to(let_tree, 71).is_err());
+
+        // Without leaves, there is no latest processed block.
+        t.truncate_to(let_tree, 0).unwrap();
+        assert_eq!(t.get_leaf_count(&let_tree).unwrap(), 0);
+        assert_eq!(t.get_root(&let_tree).unwrap(), None);
+        assert_eq!(t.get_latest_block(let_tree).unwrap(), None);
+
+        // The L1 Info Tree behaves the same way.
+        let mut info_roots = Vec::new();
+        for i in 0..10u64 {
+            t.append_l1info_leaf(&info_leaf(i, 200 + i)).unwrap();
+            info_roots.push(t.get_root(&TreeType::L1InfoTree).unwrap());
+        }
+        t.truncate_to(TreeType::L1InfoTree, 5).unwrap();
+        assert_eq!(t.get_root(&TreeType::L1InfoTree).unwrap(), info_roots[4]);
+        assert_eq!(t.get_latest_block(TreeType::L1InfoTree).unwrap(), Some(204));
+        assert_eq!(t.get_l1info_leaf(4).unwrap(), Some(info_leaf(4, 204)));
+        assert_eq!(t.get_l1info_leaf(5).unwrap(), None);
+
+        // The Rollup Exit Tree is not append-only, so it can't be truncated.
+        t.set_rollup_leaf(1, &B256::repeat_byte(1), 1, 0).unwrap();
+        assert!(t.truncate_to(TreeType::RollupExitTree, 0).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_events_batch() -> Result<(), eyre::Error> {
+        let t = MerkleForest::in_memory();
+
+        let leaves: Vec<(LeafBridge, u64)> = (0..300u32)
+            .map(|i| {
+                let leaf = LeafBridge::new(BridgeEvent {
+                    leafType: 0,
+                    originNetwork: 0,
+                    originAddress: address!("0x5555555555555555555555555555555555555555"),
+                    destinationNetwork: 1,
+                    destinationAddress: address!("0x6666666666666666666666666666666666666666"),
+                    amount: Uint::from(i),
+                    metadata: Bytes::from(format!("metadata_{}", i).into_bytes()),
+                    depositCount: i,
+                });
+                (leaf, 1000 + (i / 3) as u64)
+            })
+            .collect();
+
+        // Reference tree, inserting leaves one by one.
+        let reference = TreeType::LocalExitTree(1);
+        for leaf in &leaves {
+            t.append_events(1, std::slice::from_ref(leaf)).unwrap();
+        }
+
+        // Same leaves, inserted in batches of different sizes.
+        let batched = TreeType::LocalExitTree(2);
+        let mut inserted = 0;
+        for size in [1, 2, 3, 5, 8, 13, 21, 34, 55, 89, 69] {
+            t.append_events(2, &leaves[inserted..inserted + size])
+                .unwrap();
+            inserted += size;
+        }
+        assert_eq!(inserted, leaves.len());
+
+        assert_eq!(t.get_leaf_count(&batched).unwrap(), 300);
+        assert_eq!(
+            t.get_root(&batched).unwrap(),
+            t.get_root(&reference).unwrap()
+        );
+        assert_eq!(t.get_latest_block(batched).unwrap(), Some(1099));
+        for (i, (leaf, block)) in leaves.iter().enumerate() {
+            assert_eq!(t.get_leaf_block(&batched, i as u32).unwrap(), Some(*block));
+            assert_eq!(
+                t.merkle_proof(batched, i as u64).unwrap(),
+                t.merkle_proof(reference, i as u64).unwrap()
+            );
+            let proof = t.merkle_proof(batched, i as u64).unwrap();
+            assert_eq!(
+                calculate_merkle_root(&leaf.hashed_leaf(), &proof, i as u64),
+                t.get_root(&batched).unwrap().unwrap()
+            );
+        }
+
+        // Every intermediate root is indexed, also within a batch.
+        let roots: Vec<FixedBytes<32>> = (1..=300)
+            .map(|count| t.get_root_at(batched, count).unwrap().unwrap())
+            .collect();
+        for (i, root) in roots.iter().enumerate() {
+            assert_eq!(
+                t.get_exit_root_leaf_count(2, root).unwrap(),
+                Some(i as u32 + 1)
+            );
+        }
+        assert_eq!(t.get_exit_root_leaf_count(2, &B256::ZERO).unwrap(), Some(0));
+        assert_eq!(
+            t.get_exit_root_leaf_count(2, &B256::repeat_byte(9))
+                .unwrap(),
+            None
+        );
+
+        // Batches can be rolled back leaf by leaf.
+        t.rollback_to_block(batched, 1050).unwrap();
+        t.rollback_to_block(reference, 1050).unwrap();
+        assert_eq!(t.get_leaf_count(&batched).unwrap(), 153);
+        assert_eq!(
+            t.get_root(&batched).unwrap(),
+            t.get_root(&reference).unwrap()
+        );
+
+        assert_eq!(
+            t.get_exit_root_leaf_count(2, &roots[152]).unwrap(),
+            Some(153)
+        );
+        assert_eq!(t.get_exit_root_leaf_count(2, &roots[153]).unwrap(), None);
+
+        // Invalid batches are rejected without writing anything.
+        let root = t.get_root(&batched).unwrap();
+        assert!(t.append_events(2, &[]).is_err());
+        assert!(t.append_events(2, &leaves[154..160]).is_err());
+        assert!(
+            t.append_events(2, &[leaves[153].clone(), leaves[155].clone()])
+                .is_err()
+        );
+        assert!(
+            t.append_events(2, &[leaves[153].clone(), (leaves[154].0.clone(), 1000)])
+                .is_err()
+        );
+        assert!(
+            t.append_events(2, &[(leaves[153].0.clone(), 1000)])
+                .is_err()
+        );
+        assert_eq!(t.get_leaf_count(&batched).unwrap(), 153);
+        assert_eq!(t.get_root(&batched).unwrap(), root);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_root_and_proof_at() -> Result<(), eyre::Error> {
+        let t = MerkleForest::in_memory();
+        let let_tree = TreeType::LocalExitTree(3);
+
+        let leaves: Vec<LeafBridge> = (0..40u32)
+            .map(|i| {
+                LeafBridge::new(BridgeEvent {
+                    leafType: 0,
+                    originNetwork: 0,
+                    originAddress: address!("0x7777777777777777777777777777777777777777"),
+                    destinationNetwork: 3,
+                    destinationAddress: address!("0x8888888888888888888888888888888888888888"),
+                    amount: Uint::from(i),
+                    metadata: Bytes::new(),
+                    depositCount: i,
+                })
+            })
+            .collect();
+
+        // roots[i] and proofs[i] are taken when the tree has i + 1 leaves.
+        let mut roots = Vec::new();
+        let mut proofs = Vec::new();
+        for (i, leaf) in leaves.iter().enumerate() {
+            t.append_events(3, &[(leaf.clone(), i as u64)]).unwrap();
+            roots.push(t.get_root(&let_tree).unwrap().unwrap());
+            proofs.push(
+                (0..=i)
+                    .map(|j| t.merkle_proof(let_tree, j as u64).unwrap())
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        assert_eq!(t.get_root_at(let_tree, 0).unwrap(), None);
+        for count in 1..=40u32 {
+            let root = roots[count as usize - 1];
+            assert_eq!(t.get_root_at(let_tree, count).unwrap(), Some(root));
+
+            for index in 0..count {
+                let proof = t.merkle_proof_at(let_tree, index, count).unwrap();
+                assert_eq!(proof, proofs[count as usize - 1][index as usize]);
+                assert_eq!(
+                    calculate_merkle_root(
+                        &leaves[index as usize].hashed_leaf(),
+                        &proof,
+                        index as u64
+                    ),
+                    root
+                );
+            }
+        }
+
+        // Leaves and counts beyond the tree can't be queried.
+        assert!(t.get_root_at(let_tree, 41).is_err());
+        assert!(t.merkle_proof_at(let_tree, 10, 10).is_err());
+        assert!(t.merkle_proof_at(let_tree, 0, 41).is_err());
+
+        // Same for the L1 Info Tree.
+        let mut info_roots = Vec::new();
+        for i in 0..20u64 {
+            t.append_l1info_leaf(&info_leaf(i, i)).unwrap();
+            info_roots.push(t.get_root(&TreeType::L1InfoTree).unwrap());
+        }
+        for count in 1..=20u32 {
+            assert_eq!(
+                t.get_root_at(TreeType::L1InfoTree, count).unwrap(),
+                info_roots[count as usize - 1]
+            );
+        }
+
+        // The Rollup Exit Tree is modified in place, so its history is lost.
+        assert!(t.get_root_at(TreeType::RollupExitTree, 0).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_l1info_leaf_lookups() -> Result<(), eyre::Error> {
+        let t = MerkleForest::in_memory();
+
+        // Leaves in blocks 0, 0, 0, 2, 5, 5.
+        let blocks = [0u64, 0, 0, 2, 5, 5];
+        let leaves: Vec<L1InfoLeafData> = blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| info_leaf(i as u64, *block))
+            .collect();
+        for leaf in &leaves {
+            t.append_l1info_leaf(leaf).unwrap();
+        }
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            assert_eq!(t.get_l1info_leaf(i as u32).unwrap(), Some(leaf.clone()));
+            assert_eq!(
+                t.get_l1info_leaf_by_ger(&leaf.ger()).unwrap(),
+                Some((i as u32, leaf.clone()))
+            );
+        }
+        assert_eq!(t.get_l1info_leaf(6).unwrap(), None);
+        assert_eq!(
+            t.get_l1info_leaf_by_ger(&B256::repeat_byte(3)).unwrap(),
+            None
+        );
+
+        let indexes = |block| -> Vec<u32> {
+            t.get_l1info_leaves_by_block(block)
+                .unwrap()
+                .iter()
+                .map(|(index, _)| *index)
+                .collect()
+        };
+        assert_eq!(indexes(0), vec![0, 1, 2]);
+        assert_eq!(indexes(1), Vec::<u32>::new());
+        assert_eq!(indexes(2), vec![3]);
+        assert_eq!(indexes(5), vec![4, 5]);
+        assert_eq!(indexes(6), Vec::<u32>::new());
+
+        // Rolled back leaves can't be found anymore.
+        t.rollback_to_block(TreeType::L1InfoTree, 2).unwrap();
+        assert_eq!(t.get_l1info_leaf(4).unwrap(), None);
+        assert_eq!(t.get_l1info_leaf_by_ger(&leaves[4].ger()).unwrap(), None);
+        assert_eq!(
+            t.get_l1info_leaf_by_ger(&leaves[3].ger()).unwrap(),
+            Some((3, leaves[3].clone()))
+        );
+        assert_eq!(indexes(5), Vec::<u32>::new());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_bridge_events() -> Result<(), eyre::Error> {
+        let t = MerkleForest::in_memory();
+
+        let alice = address!("0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+        let bob = address!("0xbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb");
+
+        // Two deposits per transaction, to alternating destinations.
+        let raw = |aggchain_id: u32, i: u32| RawBridgeEvent {
+            bridge_event: BridgeEvent {
+                leafType: 0,
+                originNetwork: aggchain_id,
+                originAddress: address!("0x1111111111111111111111111111111111111111"),
+                destinationNetwork: 9,
+                destinationAddress: if i % 2 == 0 { alice } else { bob },
+                amount: Uint::from(i),
+                metadata: Bytes::from(vec![i as u8; i as usize]),
+                depositCount: i,
+            },
+            tx_hash: B256::left_padding_from(&[aggchain_id as u8, (i / 2) as u8]),
+            block_number: 100 + i as u64,
+            log_index: i as u64,
+        };
+
+        let events_1: Vec<RawBridgeEvent> = (0..6).map(|i| raw(1, i)).collect();
+        let events_2: Vec<RawBridgeEvent> = (0..3).map(|i| raw(2, i)).collect();
+        t.append_bridge_events(1, &deposits(&events_1[..4]))
+            .unwrap();
+        t.append_bridge_events(1, &deposits(&events_1[4..]))
+            .unwrap();
+        t.append_bridge_events(2, &deposits(&events_2)).unwrap();
+
+        // The tree is the same as when only inserting the leaves.
+        let leaves: Vec<(LeafBridge, u64)> = events_1
+            .iter()
+            .map(|e| (e.leaf(), e.block_number))
+            .collect();
+        t.append_events(3, &leaves).unwrap();
+        assert_eq!(
+            t.get_root(&TreeType::LocalExitTree(1)).unwrap(),
+            t.get_root(&TreeType::LocalExitTree(3)).unwrap()
+        );
+
+        for event in &events_1 {
+            let stored = t
+                .get_bridge_event(1, event.bridge_event.depositCount)
+                .unwrap()
+                .unwrap();
+            assert_eq!(stored.to_bytes(), event.to_bytes());
+        }
+        assert!(t.get_bridge_event(1, 6).unwrap().is_none());
+        assert!(t.get_bridge_event(3, 0).unwrap().is_none());
+
+        let found = |events: Vec<(u32, RawBridgeEvent)>| -> Vec<(u32, u32)> {
+            events
+                .iter()
+                .map(|(aggchain_id, e)| (*aggchain_id, e.bridge_event.depositCount))
+                .collect()
+        };
+        assert_eq!(
+            found(t.get_bridge_events_by_tx(&raw(1, 2).tx_hash).unwrap()),
+            vec![(1, 2), (1, 3)]
+        );
+        assert_eq!(
+            found(t.get_bridge_events_by_tx(&raw(2, 2).tx_hash).unwrap()),
+            vec![(2, 2)]
+        );
+        assert_eq!(
+            found(t.get_bridge_events_by_destination(&alice).unwrap()),
+            vec![(1, 0), (1, 2), (1, 4), (2, 0), (2, 2)]
+        );
+        assert_eq!(
+            found(t.get_bridge_events_by_destination(&bob).unwrap()),
+            vec![(1, 1), (1, 3), (1, 5), (2, 1)]
+        );
+        assert!(
+            t.get_bridge_events_by_tx(&B256::repeat_byte(7))
+                .unwrap()
+                .is_empty()
+        );
+
+        // Rolled back events are gone, including from the indexes.
+        t.rollback_to_block(TreeType::LocalExitTree(1), 102)
+            .unwrap();
+        assert!(t.get_bridge_event(1, 3).unwrap().is_none());
+        assert!(t.get_bridge_event(1, 2).unwrap().is_some());
+        assert_eq!(
+            found(t.get_bridge_events_by_tx(&raw(1, 2).tx_hash).unwrap()),
+            vec![(1, 2)]
+        );
+        assert_eq!(
+            found(t.get_bridge_events_by_destination(&bob).unwrap()),
+            vec![(1, 1), (2, 1)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrapped_tokens() -> Result<(), eyre::Error> {
+        let t = MerkleForest::in_memory();
+
+        let token = |origin_network: u32, i: u8, block: u64| WrappedToken {
+            origin_network,
+            origin_address: Address::repeat_byte(i),
+            wrapped_address: Address::repeat_byte(0x80 + i),
+            metadata: Bytes::from(vec![i; i as usize]),
+            block_number: block,
+            log_index: i as u64,
+        };
+        let wrapped = |tokens: Vec<WrappedToken>| BridgeEvents {
+            wrapped_tokens: tokens,
+            ..Default::default()
+        };
+
+        t.append_bridge_events(1, &wrapped(vec![token(0, 1, 10), token(0, 2, 10)]))
+            .unwrap();
+        t.append_bridge_events(1, &wrapped(vec![token(3, 3, 12)]))
+            .unwrap();
+        // Same origin token, wrapped by another bridge.
+        t.append_bridge_events(2, &wrapped(vec![token(0, 1, 11)]))
+            .unwrap();
+
+        assert_eq!(
+            t.get_wrapped_token(1, 0, &Address::repeat_byte(1)).unwrap(),
+            Some(token(0, 1, 10))
+        );
+        assert_eq!(
+            t.get_wrapped_token(2, 0, &Address::repeat_byte(1)).unwrap(),
+            Some(token(0, 1, 11))
+        );
+        assert_eq!(
+            t.get_wrapped_token(1, 3, &Address::repeat_byte(1)).unwrap(),
+            None
+        );
+        assert_eq!(
+            t.get_wrapped_token_by_address(1, &Address::repeat_byte(0x83))
+                .unwrap(),
+            Some(token(3, 3, 12))
+        );
+        assert_eq!(
+            t.get_wrapped_token_by_address(2, &Address::repeat_byte(0x83))
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            t.get_wrapped_tokens(1).unwrap(),
+            vec![token(0, 1, 10), token(0, 2, 10), token(3, 3, 12)]
+        );
+
+        // Tokens deployed in rolled back blocks are gone, also by address.
+        t.rollback_to_block(TreeType::LocalExitTree(1), 11).unwrap();
+        assert_eq!(
+            t.get_wrapped_tokens(1).unwrap(),
+            vec![token(0, 1, 10), token(0, 2, 10)]
+        );
+        assert_eq!(
+            t.get_wrapped_token_by_address(1, &Address::repeat_byte(0x83))
+                .unwrap(),
+            None
+        );
+        assert_eq!(t.get_wrapped_tokens(2).unwrap(), vec![token(0, 1, 11)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sovereign_config() -> Result<(), eyre::Error> {
+        let t = MerkleForest::in_memory();
+
+        let set_token = |i: u8, sovereign: u8, block: u64| SovereignEvent {
+            change: SovereignChange::SetToken {
+                origin_network: 0,
+                origin_address: Address::repeat_byte(i),
+                sovereign_address: Address::repeat_byte(sovereign),
+                is_not_mintable: false,
+            },
+            block_number: block,
+            log_index: 0,
+        };
+        let sovereign = |events: Vec<SovereignEvent>| BridgeEvents {
+            sovereign_events: events,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            t.get_sovereign_config(7).unwrap(),
+            SovereignConfig::default()
+        );
+        t.append_bridge_events(
+            7,
+            &sovereign(vec![
+                SovereignEvent {
+                    change: SovereignChange::SetBridgeManager {
+                        bridge_manager: Address::repeat_byte(0xee),
+                    },
+                    block_number: 10,
+                    log_index: 1,
+                },
+                set_token(1, 0x81, 10),
+            ]),
+        )
+        .unwrap();
+        t.append_bridge_events(
+            7,
+            &sovereign(vec![
+                set_token(2, 0x82, 11),
+                // Replaces the first one, which becomes a legacy token.
+                set_token(1, 0x91, 12),
+                SovereignEvent {
+                    change: SovereignChange::SetWeth {
+                        sovereign_address: Address::repeat_byte(0xaa),
+                        is_not_mintable: true,
+                    },
+                    block_number: 12,
+                    log_index: 1,
+                },
+            ]),
+        )
+        .unwrap();
+        t.append_bridge_events(
+            7,
+            &sovereign(vec![SovereignEvent {
+                change: SovereignChange::RemoveLegacyToken {
+                    sovereign_address: Address::repeat_byte(0x81),
+                },
+                block_number: 13,
+                log_index: 0,
+            }]),
+        )
+        .unwrap();
+
+        let token = |i: u8, sovereign: u8| SovereignToken {
+            origin_network: 0,
+            origin_address: Address::repeat_byte(i),
+            sovereign_address: Address::repeat_byte(sovereign),
+            is_not_mintable: false,
+        };
+        let config = t.get_sovereign_config(7).unwrap();
+        assert_eq!(config.tokens, vec![token(1, 0x91), token(2, 0x82)]);
+        assert!(config.legacy_tokens.is_empty());
+        assert_eq!(config.weth_address, Some(Address::repeat_byte(0xaa)));
+        assert!(config.weth_not_mintable);
+        assert_eq!(config.bridge_manager, Some(Address::repeat_byte(0xee)));
+        assert_eq!(t.get_sovereign_events(7).unwrap().len(), 6);
+        assert_eq!(
+            t.get_sovereign_config(8).unwrap(),
+            SovereignConfig::default()
+        );
+
+        // Rolling back undoes the changes made after the block.
+        t.rollback_to_block(TreeType::LocalExitTree(7), 12).unwrap();
+        let config = t.get_sovereign_config(7).unwrap();
+        assert_eq!(config.legacy_tokens, vec![Address::repeat_byte(0x81)]);
+        t.rollback_to_block(TreeType::LocalExitTree(7), 11).unwrap();
+        let config = t.get_sovereign_config(7).unwrap();
+        assert_eq!(config.tokens, vec![token(1, 0x81), token(2, 0x82)]);
+        assert!(config.legacy_tokens.is_empty());
+        assert_eq!(config.weth_address, None);
+        assert_eq!(config.bridge_manager, Some(Address::repeat_byte(0xee)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollup_registry() -> Result<(), eyre::Error> {
+        let t = MerkleForest::in_memory();
+
+        let rollup_type = RollupType {
+            rollup_type_id: 3,
+            fork_id: 12,
+            verifier_type: 1,
+        };
+        assert_eq!(t.get_rollup_type(3).unwrap(), None);
+        t.put_rollup_type(&rollup_type, 100, 0).unwrap();
+        assert_eq!(t.get_rollup_type(3).unwrap(), Some(rollup_type));
+
+        let rollup = |rollup_id: u32, chain_id: u64, i: u8| RollupInfo {
+            rollup_id,
+            chain_id,
+            rollup_address: Address::repeat_byte(i),
+            fork_id: 9,
+            rollup_type_id: 0,
+            verifier_type: 0,
+            block_number: 100 + rollup_id as u64,
+        };
+        t.put_rollup(&rollup(2, 2442, 2), 102, 0).unwrap();
+        t.put_rollup(&rollup(1, 1101, 1), 101, 0).unwrap();
+
+        assert_eq!(t.get_rollup(1).unwrap(), Some(rollup(1, 1101, 1)));
+        assert_eq!(t.get_rollup(3).unwrap(), None);
+        assert_eq!(
+            t.get_rollup_by_chain_id(2442).unwrap(),
+            Some(rollup(2, 2442, 2))
+        );
+        assert_eq!(
+            t.get_rollup_by_address(&Address::repeat_byte(1)).unwrap(),
+            Some(rollup(1, 1101, 1))
+        );
+        assert_eq!(t.get_rollup_by_chain_id(1).unwrap(), None);
+        assert_eq!(
+            t.get_rollups().unwrap(),
+            vec![rollup(1, 1101, 1), rollup(2, 2442, 2)]
+        );
+
+        // Replacing a rollup updates its indexes.
+        let updated = RollupInfo {
+            fork_id: 12,
+            rollup_type_id: 3,
+            ..rollup(2, 2443, 5)
+        };
+        t.put_rollup(&updated, 103, 0).unwrap();
+        assert_eq!(t.get_rollup_by_chain_id(2442).unwrap(), None);
+        assert_eq!(
+            t.get_rollup_by_address(&Address::repeat_byte(2)).unwrap(),
+            None
+        );
+        assert_eq!(t.get_rollup_by_chain_id(2443).unwrap(), Some(updated));
+        assert_eq!(t.get_rollups().unwrap().len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback_rollup_registry() {
+        let t = MerkleForest::in_memory();
+        let rer_tree = TreeType::RollupExitTree;
+
+        let old_type = RollupType {
+            rollup_type_id: 1,
+            fork_id: 9,
+            verifier_type: 0,
+        };
+        let new_type = RollupType {
+            rollup_type_id: 2,
+            fork_id: 12,
+            verifier_type: 1,
+        };
+        let rollup = RollupInfo {
+            rollup_id: 1,
+            chain_id: 1101,
+            rollup_address: Address::repeat_byte(1),
+            fork_id: 9,
+            rollup_type_id: 1,
+            verifier_type: 0,
+            block_number: 10,
+        };
+        t.put_rollup_type(&old_type, 10, 0).unwrap();
+        t.put_rollup(&rollup, 10, 1).unwrap();
+        t.set_rollup_leaf(1, &B256::repeat_byte(1), 10, 2).unwrap();
+
+        // AddNewRollupType and UpdateRollup after the block rolled back to.
+        t.put_rollup_type(&new_type, 12, 0).unwrap();
+        let updated = RollupInfo {
+            fork_id: 12,
+            rollup_type_id: 2,
+            verifier_type: 1,
+            ..rollup.clone()
+        };
+        t.put_rollup(&updated, 12, 1).unwrap();
+        t.put_rollup(
+            &RollupInfo {
+                chain_id: 1102,
+                ..updated
+            },
+            13,
+            0,
+        )
+        .unwrap();
+
+        t.rollback_to_block(rer_tree, 11).unwrap();
+        assert_eq!(t.get_rollup(1).unwrap(), Some(rollup.clone()));
+        assert_eq!(t.get_rollup_by_chain_id(1101).unwrap(), Some(rollup));
+        assert_eq!(t.get_rollup_by_chain_id(1102).unwrap(), None);
+        assert_eq!(t.get_rollup_type(1).unwrap(), Some(old_type));
+        assert_eq!(t.get_rollup_type(2).unwrap(), None);
+
+        // Writes up to the block are kept.
+        t.rollback_to_block(rer_tree, 10).unwrap();
+        assert!(t.get_rollup(1).unwrap().is_some());
+        t.rollback_to_block(rer_tree, 9).unwrap();
+        assert_eq!(t.get_rollup(1).unwrap(), None);
+        assert_eq!(
+            t.get_rollup_by_address(&Address::repeat_byte(1)).unwrap(),
+            None
+        );
+        assert_eq!(t.get_rollup_type(1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_rollup_exit_tree_history() -> Result<(), eyre::Error> {
+        let t = MerkleForest::in_memory();
+        let rer_tree = TreeType::RollupExitTree;
+
+        // (rollup id, local exit root, block, log index)
+        let updates = [
+            (1, B256::repeat_byte(1), 10, 0),
+            (3, B256::repeat_byte(2), 10, 4),
+            (1, B256::repeat_byte(3), 11, 1),
+            (2, B256::repeat_byte(4), 12, 0),
+            (3, B256::repeat_byte(5), 12, 2),
+        ];
+        let mut roots = Vec::new();
+        for (rollup_id, ler, block, log_index) in updates {
+            t.set_rollup_leaf(rollup_id, &ler, block, log_index)
+                .unwrap();
+            roots.push(t.get_root(&rer_tree).unwrap().unwrap());
+        }
+
+        let mut empty_root = B256::ZERO;
+        for _ in 0..32 {
+            empty_root = alloy::primitives::keccak256([empty_root, empty_root].concat());
+        }
+        assert_eq!(t.get_rollup_exit_root_at_block(9).unwrap(), empty_root);
+        assert_eq!(t.get_rollup_exit_root_at_block(10).unwrap(), roots[1]);
+        assert_eq!(t.get_rollup_exit_root_at_block(11).unwrap(), roots[2]);
+        assert_eq!(t.get_rollup_exit_root_at_block(20).unwrap(), roots[4]);
+
+        // Every past root can be proven, with the leaves it had.
+        for (i, root) in roots.iter().enumerate() {
+            for rollup_id in 1..=3 {
+                let ler = updates[..=i]
+                    .iter()
+                    .rev()
+                    .find(|u| u.0 == rollup_id)
+                    .map_or(B256::ZERO, |u| u.1);
+                assert_eq!(
+                    t.get_rollup_leaf_at_root(root, rollup_id).unwrap(),
+                    Some(ler)
+                );
+                let proof = t
+                    .rollup_merkle_proof_at_root(root, rollup_id)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(
+                    calculate_merkle_root(&ler, &proof, rollup_id as u64 - 1),
+                    *root
+                );
+            }
+        }
+        assert_eq!(
+            t.rollup_merkle_proof_at_root(&B256::repeat_byte(9), 1)
+                .unwrap(),
+            None
+        );
+
+        t.append_l1info_leaf(&L1InfoLeafData {
+            mer: B256::ZERO,
+            rer: roots[2],
+            prev_block_hash: B256::ZERO,
+            timestamp: 0,
+            block: 11,
+        })
+        .unwrap();
+        assert_eq!(
+            t.rollup_merkle_proof_at_l1info_index(0, 2).unwrap(),
+            t.rollup_merkle_proof_at_root(&roots[2], 2).unwrap()
+        );
+
+        // Rolling back restores the tree as it was at the end of the block.
+        t.put_rollup(
+            &RollupInfo {
+                rollup_id: 3,
+                chain_id: 3,
+                rollup_address: Address::repeat_byte(3),
+                fork_id: 0,
+                rollup_type_id: 0,
+                verifier_type: 0,
+                block_number: 12,
+            },
+            12,
+            1,
+        )
+        .unwrap();
+        t.rollback_to_block(rer_tree, 11).unwrap();
+        assert_eq!(t.get_root(&rer_tree).unwrap(), Some(roots[2]));
+        assert_eq!(t.get_leaf_count(&rer_tree).unwrap(), 3);
+        assert_eq!(t.get_latest_block(rer_tree).unwrap(), Some(11));
+        assert_eq!(t.get_rollup_leaf(2).unwrap(), None);
+        assert_eq!(t.get_rollup_leaf(3).unwrap(), Some(B256::repeat_byte(2)));
+        assert_eq!(t.get_rollup_leaf_at_root(&roots[4], 1).unwrap(), None);
+        assert!(!t.has_rollup_exit_root(&roots[4]).unwrap());
+        assert!(t.has_rollup_exit_root(&roots[2]).unwrap());
+        assert!(t.has_rollup_exit_root(&B256::ZERO).unwrap());
+        assert_eq!(t.get_rollup(3).unwrap(), None);
+
+        let proof = t.merkle_proof(rer_tree, 3).unwrap();
+        assert_eq!(
+            calculate_merkle_root(&B256::repeat_byte(2), &proof, 2),
+            roots[2]
+        );
+
+        // And the tree can go on from there.
+        t.set_rollup_leaf(2, &B256::repeat_byte(4), 12, 0).unwrap();
+        t.set_rollup_leaf(3, &B256::repeat_byte(5), 12, 2).unwrap();
+        assert_eq!(t.get_root(&rer_tree).unwrap(), Some(roots[4]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback_repeated_rollup_exit_root() {
+        let t = MerkleForest::in_memory();
+        let rer_tree = TreeType::RollupExitTree;
+
+        t.set_rollup_leaf(1, &B256::repeat_byte(1), 10, 0).unwrap();
+        t.set_rollup_leaf(2, &B256::repeat_byte(2), 11, 0).unwrap();
+        let root = t.get_root(&rer_tree).unwrap().unwrap();
+
+        // Verified again without new deposits, the root repeats.
+        t.set_rollup_leaf(2, &B256::repeat_byte(2), 12, 0).unwrap();
+        assert_eq!(t.get_root(&rer_tree).unwrap(), Some(root));
+
+        // Rolling back the second verification keeps the root.
+        t.rollback_to_block(rer_tree, 11).unwrap();
+        assert!(t.has_rollup_exit_root(&root).unwrap());
+        let proof = t.rollup_merkle_proof_at_root(&root, 2).unwrap().unwrap();
+        assert_eq!(
+            calculate_merkle_root(&B256::repeat_byte(2), &proof, 1),
+            root
+        );
+        t.append_l1info_leaf(&L1InfoLeafData {
+            mer: B256::ZERO,
+            rer: root,
+            prev_block_hash: B256::ZERO,
+            timestamp: 0,
+            block: 11,
+        })
+        .unwrap();
+        assert_eq!(
+            t.rollup_merkle_proof_at_l1info_index(0, 2).unwrap(),
+            Some(proof)
+        );
+
+        // Unlike rolling back the first one.
+        t.rollback_to_block(rer_tree, 10).unwrap();
+        assert!(!t.has_rollup_exit_root(&root).unwrap());
+    }
+
     // TODO: test if tree is full what happens.
 
+    #[test]
+    fn test_check_tree() {
+        let path = "db_test_check";
+        let _ = std::fs::remove_dir_all(path);
+        let let1 = TreeType::LocalExitTree(1);
+
+        let mut hashes = Vec::new();
+        let (let_root, rer_root) = {
+            let t = MerkleForest::open(path).unwrap();
+            for i in 0..5u32 {
+                let leaf = LeafBridge::new(BridgeEvent {
+                    leafType: 0,
+                    originNetwork: 0,
+                    originAddress: Address::ZERO,
+                    destinationNetwork: 2,
+                    destinationAddress: Address::repeat_byte(2),
+                    amount: uint!(1_U256),
+                    metadata: Bytes::new(),
+                    depositCount: i,
+                });
+                hashes.push(leaf.hashed_leaf());
+                t.append_events(1, &[(leaf, 10 + i as u64)]).unwrap();
+            }
+            t.set_rollup_leaf(1, &B256::repeat_byte(1), 20, 0).unwrap();
+            t.set_rollup_leaf(3, &B256::repeat_byte(3), 21, 0).unwrap();
+            t.append_l1info_leaf(&info_leaf(0, 30)).unwrap();
+
+            // Key order: by aggchain ID, then tree type.
+            let trees = t.get_trees().unwrap();
+            assert_eq!(
+                trees,
+                vec![TreeType::RollupExitTree, TreeType::L1InfoTree, let1]
+            );
+            for tree in trees {
+                assert_eq!(t.check_tree(tree, false).unwrap(), vec![]);
+            }
+            (
+                t.get_root(&let1).unwrap().unwrap(),
+                t.get_root(&TreeType::RollupExitTree).unwrap().unwrap(),
+            )
+        };
+
+        // Corrupt the database as an unsynced write could: a wrong node, a node that
+        // shouldn't exist, a lost node, a stale leaf count and a stale latest block.
+        let node_key = |tree: TreeType, level: u8, index: u32| {
+            let mut k = tree.aggchain_id().to_be_bytes().to_vec();
+            k.extend_from_slice(&[0, tree.tree_type(), level]);
+            k.extend_from_slice(&index.to_be_bytes());
+            k
+        };
+        let meta_key = |tree: TreeType, tag: u8| {
+            let mut k = tree.aggchain_id().to_be_bytes().to_vec();
+            k.extend_from_slice(&[1, tree.tree_type(), tag]);
+            k
+        };
+        {
+            let cfs = DB::list_cf(&Options::default(), path).unwrap();
+            let db = DB::open_cf(&Options::default(), path, cfs).unwrap();
+            let trees = db.cf_handle("tree_levels").unwrap();
+            let meta = db.cf_handle("metadata").unwrap();
+            db.put_cf(trees, node_key(let1, 1, 1), B256::repeat_byte(9))
+                .unwrap();
+            db.put_cf(trees, node_key(let1, 1, 7), B256::repeat_byte(9))
+                .unwrap();
+            db.delete_cf(trees, node_key(TreeType::RollupExitTree, 32, 0))
+                .unwrap();
+            db.put_cf(meta, meta_key(let1, 0), 4u32.to_be_bytes())
+                .unwrap();
+            db.put_cf(
+                meta,
+                meta_key(TreeType::RollupExitTree, 1),
+                5u64.to_be_bytes(),
+            )
+            .unwrap();
+        }
+
+        let t = MerkleForest::open(path).unwrap();
+        let found = t.check_tree(let1, false).unwrap();
+        assert_eq!(
+            found,
+            vec![
+                Inconsistency::LeafCount {
+                    tree_type: let1,
+                    stored: 4,
+                    expected: 5
+                },
+                Inconsistency::Node {
+                    tree_type: let1,
+                    level: 1,
+                    index: 1,
+                    stored: Some(B256::repeat_byte(9)),
+                    expected: Some(keccak256([hashes[2], hashes[3]].concat())),
+                },
+                Inconsistency::Node {
+                    tree_type: let1,
+                    level: 1,
+                    index: 7,
+                    stored: Some(B256::repeat_byte(9)),
+                    expected: None,
+                },
+            ]
+        );
+        assert_eq!(
+            t.check_tree(TreeType::RollupExitTree, false).unwrap(),
+            vec![
+                Inconsistency::Node {
+                    tree_type: TreeType::RollupExitTree,
+                    level: 32,
+                    index: 0,
+                    stored: None,
+                    expected: Some(rer_root),
+                },
+                Inconsistency::LatestBlock {
+                    tree_type: TreeType::RollupExitTree,
+                    stored: Some(5),
+                    expected: 21,
+                },
+            ]
+        );
+
+        // Without repair nothing changes, with repair the trees match their leaves again.
+        assert_eq!(t.check_tree(let1, false).unwrap().len(), 3);
+        assert_eq!(t.check_tree(let1, true).unwrap().len(), 3);
+        assert_eq!(
+            t.check_tree(TreeType::RollupExitTree, true).unwrap().len(),
+            2
+        );
+        assert_eq!(t.check_tree(let1, false).unwrap(), vec![]);
+        assert_eq!(
+            t.check_tree(TreeType::RollupExitTree, false).unwrap(),
+            vec![]
+        );
+        assert_eq!(t.get_leaf_count(&let1).unwrap(), 5);
+        assert_eq!(t.get_root(&let1).unwrap(), Some(let_root));
+        assert_eq!(
+            t.get_root(&TreeType::RollupExitTree).unwrap(),
+            Some(rer_root)
+        );
+        assert_eq!(
+            t.get_latest_block(TreeType::RollupExitTree).unwrap(),
+            Some(21)
+        );
+
+        // A lost leaf can only be reported.
+        drop(t);
+        {
+            let cfs = DB::list_cf(&Options::default(), path).unwrap();
+            let db = DB::open_cf(&Options::default(), path, cfs).unwrap();
+            let trees = db.cf_handle("tree_levels").unwrap();
+            db.delete_cf(trees, node_key(let1, 0, 2)).unwrap();
+        }
+        let t = MerkleForest::open(path).unwrap();
+        assert!(
+            t.check_tree(let1, true)
+                .unwrap()
+                .contains(&Inconsistency::MissingLeaf {
+                    tree_type: let1,
+                    index: 2
+                })
+        );
+        drop(t);
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let imported = "db_test_snapshot_imported";
+        let _ = std::fs::remove_dir_all(imported);
+        let let1 = TreeType::LocalExitTree(1);
+
+        let raw = |i: u32| RawBridgeEvent {
+            bridge_event: BridgeEvent {
+                leafType: 0,
+                originNetwork: 1,
+                originAddress: Address::repeat_byte(1),
+                destinationNetwork: 0,
+                destinationAddress: Address::repeat_byte(2),
+                amount: Uint::from(i),
+                metadata: Bytes::new(),
+                depositCount: i,
+            },
+            tx_hash: B256::repeat_byte(i as u8),
+            block_number: 100 + i as u64,
+            log_index: 0,
+        };
+        let t = MerkleForest::in_memory();
+        let events: Vec<RawBridgeEvent> = (0..7).map(raw).collect();
+        t.append_bridge_events(1, &deposits(&events)).unwrap();
+        t.set_rollup_leaf(1, &B256::repeat_byte(1), 20, 0).unwrap();
+        t.set_rollup_leaf(2, &B256::repeat_byte(2), 21, 0).unwrap();
+        for i in 0..3 {
+            t.append_l1info_leaf(&info_leaf(i, 30 + i)).unwrap();
+        }
+        t.put_indexer_cursor("l1infotree-indexer", 40).unwrap();
+
+        let mut with_events = Vec::new();
+        let mut without_events = Vec::new();
+        let exported = t.export_snapshot(&mut with_events, true).unwrap();
+        t.export_snapshot(&mut without_events, false).unwrap();
+        assert!(without_events.len() < with_events.len());
+
+        // The imported trees are identical, and indexing resumes from the same blocks.
+        let (i, import) = MerkleForest::import_snapshot(imported, with_events.as_slice()).unwrap();
+        assert!(import.events);
+        assert_eq!(import.entries.iter().map(|(_, n)| n).sum::<u64>(), exported);
+        for tree in [let1, TreeType::RollupExitTree, TreeType::L1InfoTree] {
+            assert_eq!(i.get_root(&tree).unwrap(), t.get_root(&tree).unwrap());
+            assert_eq!(
+                i.get_leaf_count(&tree).unwrap(),
+                t.get_leaf_count(&tree).unwrap()
+            );
+            assert_eq!(
+                i.get_latest_block(tree).unwrap(),
+                t.get_latest_block(tree).unwrap()
+            );
+            assert_eq!(i.check_tree(tree, false).unwrap(), vec![]);
+        }
+        assert_eq!(
+            i.merkle_proof(let1, 3).unwrap(),
+            t.merkle_proof(let1, 3).unwrap()
+        );
+        assert_eq!(
+            i.rollup_merkle_proof_at_l1info_index(1, 2).unwrap(),
+            t.rollup_merkle_proof_at_l1info_index(1, 2).unwrap()
+        );
+        assert_eq!(
+            i.get_indexer_cursor("l1infotree-indexer").unwrap(),
+            Some(40)
+        );
+        assert!(i.get_bridge_event(1, 3).unwrap().is_some());
+        drop(i);
+        std::fs::remove_dir_all(imported).unwrap();
+
+        // Without events, only the bridge events are missing.
+        let (i, import) =
+            MerkleForest::import_snapshot(imported, without_events.as_slice()).unwrap();
+        assert!(!import.events);
+        assert_eq!(i.get_root(&let1).unwrap(), t.get_root(&let1).unwrap());
+        assert!(i.get_bridge_event(1, 3).unwrap().is_none());
+        drop(i);
+
+        // Existing databases are never overwritten.
+        assert!(MerkleForest::import_snapshot(imported, with_events.as_slice()).is_err());
+        std::fs::remove_dir_all(imported).unwrap();
+
+        // A corrupted or truncated snapshot is rejected and leaves nothing behind.
+        let mut corrupted = with_events.clone();
+        let middle = corrupted.len() / 2;
+        corrupted[middle] ^= 1;
+        assert!(MerkleForest::import_snapshot(imported, corrupted.as_slice()).is_err());
+        assert!(!std::path::Path::new(imported).exists());
+        let truncated = &with_events[..with_events.len() - 1];
+        assert!(MerkleForest::import_snapshot(imported, truncated).is_err());
+        assert!(!std::path::Path::new(imported).exists());
+
+        // Snapshots of another version are rejected.
+        let mut other_version = with_events.clone();
+        other_version[11] = 2;
+        let Err(err) = MerkleForest::import_snapshot(imported, other_version.as_slice()) else {
+            panic!("Imported a snapshot of another version");
+        };
+        assert!(err.to_string().contains("version"));
+    }
+
+    #[test]
+    fn test_indexer_cursor() {
+        let t = MerkleForest::in_memory();
+        assert_eq!(t.get_indexer_cursor("l1-bridge-indexer").unwrap(), None);
+
+        // Cursors are independent of the trees and of each other.
+        t.put_indexer_cursor("l1-bridge-indexer", 100).unwrap();
+        t.put_indexer_cursor("l1-bridge-indexer", 200).unwrap();
+        t.put_indexer_cursor("l1infotree-indexer", 50).unwrap();
+        assert_eq!(
+            t.get_indexer_cursor("l1-bridge-indexer").unwrap(),
+            Some(200)
+        );
+        assert_eq!(
+            t.get_indexer_cursor("l1infotree-indexer").unwrap(),
+            Some(50)
+        );
+        assert_eq!(
+            t.get_latest_block(TreeType::LocalExitTree(0)).unwrap(),
+            None
+        );
+
+        // Moved back on reorgs.
+        t.put_indexer_cursor("l1-bridge-indexer", 150).unwrap();
+        assert_eq!(
+            t.get_indexer_cursor("l1-bridge-indexer").unwrap(),
+            Some(150)
+        );
+    }
+
+    #[test]
+    fn test_transaction() {
+        let t = MerkleForest::in_memory();
+        let reference = MerkleForest::in_memory();
+        let info = TreeType::L1InfoTree;
+        for i in 0..3 {
+            t.append_l1info_leaf(&info_leaf(i, 10 + i)).unwrap();
+            reference.append_l1info_leaf(&info_leaf(i, 10 + i)).unwrap();
+        }
+        t.set_rollup_leaf(1, &B256::repeat_byte(1), 10, 0).unwrap();
+
+        // Writes are only seen through the transaction until committed.
+        let tx = t.transaction().unwrap();
+        for i in 3..6 {
+            tx.append_l1info_leaf(&info_leaf(i, 10 + i)).unwrap();
+            reference.append_l1info_leaf(&info_leaf(i, 10 + i)).unwrap();
+        }
+        tx.set_rollup_leaf(2, &B256::repeat_byte(2), 15, 0).unwrap();
+        tx.put_indexer_cursor("l1infotree-indexer", 20).unwrap();
+        assert_eq!(tx.get_leaf_count(&info).unwrap(), 6);
+        assert_eq!(
+            tx.get_root(&info).unwrap(),
+            reference.get_root(&info).unwrap()
+        );
+        assert_eq!(
+            tx.get_l1info_leaves_by_block(14).unwrap(),
+            reference.get_l1info_leaves_by_block(14).unwrap()
+        );
+        assert_eq!(t.get_leaf_count(&info).unwrap(), 3);
+        assert!(t.get_l1info_leaves_by_block(14).unwrap().is_empty());
+        assert_eq!(t.get_indexer_cursor("l1infotree-indexer").unwrap(), None);
+
+        // Rolling back in the transaction hides the leaves written before it.
+        tx.rollback_to_block(info, 13).unwrap();
+        assert_eq!(tx.get_leaf_count(&info).unwrap(), 4);
+        assert!(tx.get_l1info_leaf(4).unwrap().is_none());
+        assert!(tx.get_l1info_leaf(3).unwrap().is_some());
+        tx.append_l1info_leaf(&info_leaf(4, 14)).unwrap();
+        tx.append_l1info_leaf(&info_leaf(5, 15)).unwrap();
+
+        let rer = tx.get_root(&TreeType::RollupExitTree).unwrap();
+        tx.commit().unwrap();
+        assert_eq!(
+            t.get_root(&info).unwrap(),
+            reference.get_root(&info).unwrap()
+        );
+        assert_eq!(t.get_root(&TreeType::RollupExitTree).unwrap(), rer);
+        assert_eq!(
+            t.get_indexer_cursor("l1infotree-indexer").unwrap(),
+            Some(20)
+        );
+        assert_eq!(t.check_tree(info, false).unwrap(), vec![]);
+        assert_eq!(
+            t.check_tree(TreeType::RollupExitTree, false).unwrap(),
+            vec![]
+        );
+
+        // Dropped transactions leave nothing behind, not even in the frontier cache.
+        let tx = t.transaction().unwrap();
+        tx.append_l1info_leaf(&info_leaf(100, 20)).unwrap();
+        drop(tx);
+        assert_eq!(t.get_leaf_count(&info).unwrap(), 6);
+        t.append_l1info_leaf(&info_leaf(6, 20)).unwrap();
+        reference.append_l1info_leaf(&info_leaf(6, 20)).unwrap();
+        assert_eq!(
+            t.get_root(&info).unwrap(),
+            reference.get_root(&info).unwrap()
+        );
+    }
+
+    // Counts the reads of tree nodes, to check that appends don't read any.
+    struct CountingStorage {
+        inner: MemoryStorage,
+        node_reads: Arc<AtomicUsize>,
+    }
+
+    impl ReadStorage for CountingStorage {
+        fn get_cf(&self, cf: &str, key: &[u8]) -> eyre::Result<Option<Vec<u8>>> {
+            if cf == "tree_levels" {
+                self.node_reads.fetch_add(1, Ordering::Relaxed);
+            }
+            self.inner.get_cf(cf, key)
+        }
+
+        fn iterator_cf<'a>(&'a self, cf: &str, mode: IteratorMode) -> StorageIterator<'a> {
+            self.inner.iterator_cf(cf, mode)
+        }
+    }
+
+    impl Storage for CountingStorage {
+        fn write(&self, batch: StorageWriteBatch) -> eyre::Result<()> {
+            self.inner.write(batch)
+        }
+
+        fn snapshot(&self) -> eyre::Result<Box<dyn ReadStorage + '_>> {
+            self.inner.snapshot()
+        }
+
+        fn flush(&self) -> eyre::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    #[test]
+    fn test_frontier_cache() {
+        let node_reads = Arc::new(AtomicUsize::new(0));
+        let t = MerkleForest::with_storage(Box::new(CountingStorage {
+            inner: MemoryStorage::default(),
+            node_reads: node_reads.clone(),
+        }));
+        let reference = MerkleForest::in_memory();
+        let let1 = TreeType::LocalExitTree(1);
+
+        let leaf = |i: u32, amount: u32| {
+            LeafBridge::new(BridgeEvent {
+                leafType: 0,
+                originNetwork: 0,
+                originAddress: Address::ZERO,
+                destinationNetwork: 1,
+                destinationAddress: Address::repeat_byte(2),
+                amount: Uint::from(amount),
+                metadata: Bytes::new(),
+                depositCount: i,
+            })
+        };
+        let chunk = |from: u32, to: u32, amount: u32| -> Vec<(LeafBridge, u64)> {
+            (from..to).map(|i| (leaf(i, amount), i as u64)).collect()
+        };
+
+        // The first append reads the frontier from the tree, the next ones don't read
+        // any node.
+        t.append_events(1, &chunk(0, 5, 0)).unwrap();
+        t.append_l1info_leaf(&info_leaf(0, 0)).unwrap();
+        let reads = node_reads.load(Ordering::Relaxed);
+        t.append_events(1, &chunk(5, 13, 0)).unwrap();
+        for i in 1..5 {
+            t.append_l1info_leaf(&info_leaf(i, i)).unwrap();
+        }
+        // Neither through transactions, whose frontiers are kept once committed.
+        let tx = t.transaction().unwrap();
+        tx.append_events(1, &chunk(13, 14, 0)).unwrap();
+        tx.append_l1info_leaf(&info_leaf(5, 5)).unwrap();
+        tx.commit().unwrap();
+        for i in 6..10 {
+            t.append_l1info_leaf(&info_leaf(i, i)).unwrap();
+        }
+        assert_eq!(node_reads.load(Ordering::Relaxed), reads);
+
+        reference.append_events(1, &chunk(0, 14, 0)).unwrap();
+        for i in 0..10 {
+            reference.append_l1info_leaf(&info_leaf(i, i)).unwrap();
+        }
+        for tree in [let1, TreeType::L1InfoTree] {
+            assert_eq!(
+                t.get_root(&tree).unwrap(),
+                reference.get_root(&tree).unwrap()
+            );
+            assert_eq!(t.check_tree(tree, false).unwrap(), vec![]);
+        }
+        assert_eq!(
+            t.merkle_proof(let1, 6).unwrap(),
+            reference.merkle_proof(let1, 6).unwrap()
+        );
+
+        // After a rollback, other leaves at the same indexes lead to other roots.
+        t.rollback_to_block(let1, 9).unwrap();
+        t.append_events(1, &chunk(10, 14, 1)).unwrap();
+        let other = MerkleForest::in_memory();
+        other.append_events(1, &chunk(0, 10, 0)).unwrap();
+        other.append_events(1, &chunk(10, 14, 1)).unwrap();
+        assert_eq!(t.get_root(&let1).unwrap(), other.get_root(&let1).unwrap());
+        assert_eq!(t.check_tree(let1, false).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_append_leaf_hash() {
+        let t = MerkleForest::in_memory();
+        let reference = MerkleForest::in_memory();
+        let let1 = TreeType::LocalExitTree(1);
+
+        // Same trees and exit roots as when appending the bridge events.
+        for i in 0..6u32 {
+            let leaf = LeafBridge::new(BridgeEvent {
+                leafType: 0,
+                originNetwork: 0,
+                originAddress: Address::ZERO,
+                destinationNetwork: 1,
+                destinationAddress: Address::repeat_byte(2),
+                amount: Uint::from(i),
+                metadata: Bytes::new(),
+                depositCount: i,
+            });
+            let index = t.append_leaf_hash(let1, &leaf.hashed_leaf(), 10).unwrap();
+            assert_eq!(index, i);
+            reference.append_events(1, &[(leaf, 10)]).unwrap();
+
+            let root = t.get_root(&let1).unwrap().unwrap();
+            assert_eq!(Some(root), reference.get_root(&let1).unwrap());
+            assert_eq!(t.get_exit_root_leaf_count(1, &root).unwrap(), Some(i + 1));
+        }
+        // The L1 Info Tree can't be rolled back without the data of its leaves.
+        let leaf = info_leaf(0, 20);
+        assert!(
+            t.append_leaf_hash(TreeType::L1InfoTree, &leaf.info_root_leaf(), leaf.block)
+                .is_err()
+        );
+        assert_eq!(t.get_leaf_count(&TreeType::L1InfoTree).unwrap(), 0);
+
+        // The checks are the same for every caller.
+        assert!(t.append_leaf_hash(let1, &B256::ZERO, 9).is_err());
+        assert!(
+            t.append_leaf_hash(TreeType::RollupExitTree, &B256::ZERO, 30)
+                .is_err()
+        );
+        assert_eq!(t.get_leaf_count(&let1).unwrap(), 6);
+    }
+
     #[test]
     fn persistent_tree_roundtrip() -> Result<(), eyre::Error> {
         let _ = std::fs::remove_dir_all("db_test");
@@ -475,7 +1986,7 @@ mod tests {
                     depositCount: i,
                 });
 
-                t.append_events(1, &[leaf.clone()], 1).unwrap();
+                t.append_events(1, &[(leaf.clone(), 1)]).unwrap();
 
                 let hashed_leaf = leaf.hashed_leaf();
 
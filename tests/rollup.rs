@@ -0,0 +1,30 @@
+#[cfg(test)]
+mod tests {
+    use aggkit_rust::rollup::{RollupInfo, RollupType};
+    use alloy::primitives::address;
+
+    #[test]
+    fn test_rollup_roundtrip() {
+        let rollup_type = RollupType {
+            rollup_type_id: 7,
+            fork_id: 12,
+            verifier_type: 1,
+        };
+        assert_eq!(
+            RollupType::from_bytes(&rollup_type.to_bytes()).unwrap(),
+            rollup_type
+        );
+
+        let rollup = RollupInfo {
+            rollup_id: 1,
+            chain_id: 1101,
+            rollup_address: address!("0x519E42c24163192Dca44CD3fBDCEBF6be9130987"),
+            fork_id: 12,
+            rollup_type_id: 0,
+            verifier_type: 0,
+            block_number: 19218658,
+        };
+        assert_eq!(RollupInfo::from_bytes(&rollup.to_bytes()).unwrap(), rollup);
+        assert!(RollupInfo::from_bytes(&rollup.to_bytes()[..52]).is_err());
+    }
+}
@@ -0,0 +1,243 @@
+#[cfg(test)]
+mod tests {
+    use aggkit_rust::rpc::{
+        RpcErrorClass, RpcRetryPolicy, classify, is_range_limit_error, is_state_unavailable,
+        retry_after,
+    };
+    use alloy::rpc::json_rpc::ErrorPayload;
+    use alloy::transports::layers::RetryPolicy;
+    use alloy::transports::{HttpError, TransportError, TransportErrorKind};
+    use serde_json::value::RawValue;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    fn error_resp(code: i64, message: &'static str, data: Option<&str>) -> TransportError {
+        TransportError::ErrorResp(ErrorPayload {
+            code,
+            message: message.into(),
+            data: data.map(|data| RawValue::from_string(data.to_string()).unwrap()),
+        })
+    }
+
+    fn http_error(status: u16, body: &str) -> TransportError {
+        TransportError::Transport(TransportErrorKind::HttpError(HttpError {
+            status,
+            body: body.to_string(),
+        }))
+    }
+
+    #[test]
+    fn test_is_range_limit_error() {
+        assert!(is_range_limit_error(&error_resp(
+            -32005,
+            "query returned more than 10000 results",
+            None
+        )));
+        assert!(is_range_limit_error(&error_resp(
+            -32000,
+            "Block range is too wide, maximum is 3000",
+            None
+        )));
+        assert!(is_range_limit_error(&http_error(
+            413,
+            "Log response size exceeded."
+        )));
+        assert!(!is_range_limit_error(&error_resp(
+            3,
+            "execution reverted",
+            None
+        )));
+        assert!(is_range_limit_error(&error_resp(
+            -32602,
+            "eth_getLogs is limited to a 10,000 range",
+            None
+        )));
+        assert!(!is_range_limit_error(&error_resp(
+            -32602,
+            "invalid block range params",
+            None
+        )));
+        assert!(!is_range_limit_error(&error_resp(
+            -32005,
+            "rate limited to a maximum of 10 requests per second",
+            None
+        )));
+        assert!(!is_range_limit_error(&TransportError::Transport(
+            TransportErrorKind::BackendGone
+        )));
+    }
+
+    #[test]
+    fn test_is_state_unavailable() {
+        assert!(is_state_unavailable(&error_resp(
+            -32000,
+            "missing trie node 1a2b (path ) state 0x1a2b is not available",
+            None
+        )));
+        assert!(is_state_unavailable(&error_resp(
+            -32000,
+            "historical state 0x1a2b is not available",
+            None
+        )));
+        assert!(!is_state_unavailable(&error_resp(
+            -32000,
+            "header not found",
+            None
+        )));
+        assert!(!is_state_unavailable(&http_error(503, "")));
+    }
+
+    #[test]
+    fn test_classify() {
+        let cases = [
+            // Infura uses the same code for both.
+            (
+                error_resp(-32005, "query returned more than 10000 results", None),
+                RpcErrorClass::RangeLimit,
+            ),
+            (
+                error_resp(-32005, "project ID request rate exceeded", None),
+                RpcErrorClass::RateLimited,
+            ),
+            (
+                error_resp(429, "Your app has exceeded its compute units", None),
+                RpcErrorClass::RateLimited,
+            ),
+            // Rate limits are not range limits, even if worded alike.
+            (
+                error_resp(
+                    -32005,
+                    "rate limited to a maximum of 10 requests per second",
+                    None,
+                ),
+                RpcErrorClass::RateLimited,
+            ),
+            (
+                error_resp(-32602, "invalid block range params", None),
+                RpcErrorClass::Fatal,
+            ),
+            (http_error(429, ""), RpcErrorClass::RateLimited),
+            (http_error(503, ""), RpcErrorClass::Transient),
+            (http_error(502, "Bad Gateway"), RpcErrorClass::Transient),
+            (
+                error_resp(-32603, "internal error", None),
+                RpcErrorClass::Transient,
+            ),
+            // Retried by alloy too, but it's a lagging node rather than a rate limit.
+            (
+                error_resp(-32000, "header not found", None),
+                RpcErrorClass::Transient,
+            ),
+            (
+                error_resp(-32016, "Your IP has exceeded its rate limit", None),
+                RpcErrorClass::RateLimited,
+            ),
+            (TransportError::NullResp, RpcErrorClass::Transient),
+            (
+                TransportError::Transport(TransportErrorKind::BackendGone),
+                RpcErrorClass::Transient,
+            ),
+            // Pruned nodes won't have the state on retries either.
+            (
+                error_resp(
+                    -32000,
+                    "missing trie node 1a2b (path ) state 0x1a2b is not available",
+                    None,
+                ),
+                RpcErrorClass::Fatal,
+            ),
+            (http_error(401, "invalid project id"), RpcErrorClass::Fatal),
+            (http_error(404, "Not Found"), RpcErrorClass::Fatal),
+            (
+                error_resp(-32601, "the method eth_foo does not exist", None),
+                RpcErrorClass::Fatal,
+            ),
+            (
+                error_resp(-32602, "invalid params", None),
+                RpcErrorClass::Fatal,
+            ),
+            (
+                error_resp(3, "execution reverted", None),
+                RpcErrorClass::Fatal,
+            ),
+            (
+                TransportError::deser_err(
+                    serde_json::from_str::<u64>("x").unwrap_err(),
+                    "<html>Not an RPC</html>",
+                ),
+                RpcErrorClass::Fatal,
+            ),
+        ];
+        for (error, class) in cases {
+            assert_eq!(classify(&error), class, "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_retry_after() {
+        assert_eq!(
+            retry_after(&error_resp(
+                -32005,
+                "daily request count exceeded, request rate limited",
+                Some(r#"{"rate": {"backoff_seconds": 30}}"#)
+            )),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            retry_after(&error_resp(
+                429,
+                "rate limited",
+                Some(r#"{"retryAfter": 1.5}"#)
+            )),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            retry_after(&error_resp(
+                -32016,
+                "rate limit exceeded, retry after 5 seconds",
+                None
+            )),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            retry_after(&http_error(429, "Too Many Requests. Retry-After: 12")),
+            Some(Duration::from_secs(12))
+        );
+        assert_eq!(retry_after(&http_error(429, "Too Many Requests")), None);
+        assert_eq!(
+            retry_after(&error_resp(-32005, "rate limited", Some("null"))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RpcRetryPolicy::default();
+        assert!(policy.should_retry(&http_error(429, "")));
+        assert!(policy.should_retry(&http_error(503, "")));
+        assert!(policy.should_retry(&http_error(503, "")));
+        assert!(!policy.should_retry(&error_resp(
+            -32005,
+            "query returned more than 10000 results",
+            None
+        )));
+        assert!(!policy.should_retry(&http_error(404, "")));
+        assert_eq!(
+            policy.backoff_hint(&error_resp(
+                429,
+                "rate limited",
+                Some(r#"{"retry_after": 2}"#)
+            )),
+            Some(Duration::from_secs(2))
+        );
+
+        assert_eq!(policy.stats.rate_limited.load(Ordering::Relaxed), 1);
+        assert_eq!(policy.stats.transient.load(Ordering::Relaxed), 2);
+        assert_eq!(policy.stats.range_limit.load(Ordering::Relaxed), 1);
+        assert_eq!(policy.stats.fatal.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            serde_json::to_value(&*policy.stats).unwrap(),
+            serde_json::json!({"rate_limited": 1, "transient": 2, "range_limit": 1, "fatal": 1})
+        );
+    }
+}
@@ -0,0 +1,130 @@
+#[cfg(test)]
+mod tests {
+    use aggkit_rust::storage::{
+        Direction, IteratorMode, MemoryStorage, OverlayStorage, ReadStorage, RocksDbStorage,
+        Storage, WriteBatch,
+    };
+    use rocksdb::{DB, Options};
+    use std::sync::Arc;
+
+    const CF: &str = "cf";
+
+    fn keys(storage: &dyn Storage, mode: IteratorMode) -> Vec<u8> {
+        storage
+            .iterator_cf(CF, mode)
+            .map(|item| item.unwrap().0[0])
+            .collect()
+    }
+
+    // Both backends must behave the same, the trees are tested in memory.
+    fn check_storage(storage: &dyn Storage) {
+        let mut batch = WriteBatch::default();
+        for k in [1u8, 3, 5, 7, 9] {
+            batch.put_cf(CF, [k], [k * 10]);
+        }
+        storage.write(batch).unwrap();
+
+        assert_eq!(storage.get_cf(CF, &[3]).unwrap(), Some(vec![30]));
+        assert_eq!(storage.get_cf(CF, &[4]).unwrap(), None);
+        assert_eq!(keys(storage, IteratorMode::Start), vec![1, 3, 5, 7, 9]);
+        assert_eq!(keys(storage, IteratorMode::End), vec![9, 7, 5, 3, 1]);
+        assert_eq!(
+            keys(storage, IteratorMode::From(&[4], Direction::Forward)),
+            vec![5, 7, 9]
+        );
+        assert_eq!(
+            keys(storage, IteratorMode::From(&[5], Direction::Reverse)),
+            vec![5, 3, 1]
+        );
+        assert_eq!(
+            keys(storage, IteratorMode::From(&[6], Direction::Reverse)),
+            vec![5, 3, 1]
+        );
+
+        // Later writes aren't seen by a snapshot.
+        let snapshot = storage.snapshot().unwrap();
+        let mut batch = WriteBatch::default();
+        batch.delete_cf(CF, [1]);
+        batch.delete_range_cf(CF, [5], [9]);
+        batch.put_cf(CF, [3], [31]);
+        storage.write(batch).unwrap();
+
+        assert_eq!(keys(storage, IteratorMode::Start), vec![3, 9]);
+        assert_eq!(storage.get_cf(CF, &[3]).unwrap(), Some(vec![31]));
+        assert_eq!(
+            snapshot
+                .iterator_cf(CF, IteratorMode::Start)
+                .map(|item| item.unwrap().0[0])
+                .collect::<Vec<u8>>(),
+            vec![1, 3, 5, 7, 9]
+        );
+        assert_eq!(snapshot.get_cf(CF, &[3]).unwrap(), Some(vec![30]));
+
+        storage.flush().unwrap();
+        assert_eq!(storage.get_cf(CF, &[3]).unwrap(), Some(vec![31]));
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        check_storage(&MemoryStorage::default());
+    }
+
+    #[test]
+    fn test_rocksdb_storage() {
+        let path = "db_test_storage";
+        let _ = std::fs::remove_dir_all(path);
+        {
+            let mut opts = Options::default();
+            opts.create_if_missing(true);
+            opts.create_missing_column_families(true);
+            let db = DB::open_cf(&opts, path, [CF]).unwrap();
+            check_storage(&RocksDbStorage::new(db));
+        }
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn test_overlay_storage() {
+        check_storage(&OverlayStorage::new(Arc::new(MemoryStorage::default())));
+
+        let base = Arc::new(MemoryStorage::default());
+        let mut batch = WriteBatch::default();
+        for k in [1u8, 3, 5, 7, 9] {
+            batch.put_cf(CF, [k], [k * 10]);
+        }
+        base.write(batch).unwrap();
+
+        // Changes replace and hide the keys of the base.
+        let overlay = OverlayStorage::new(base.clone());
+        let mut batch = WriteBatch::default();
+        batch.put_cf(CF, [4], [40]);
+        batch.delete_cf(CF, [3]);
+        batch.delete_range_cf(CF, [5], [8]);
+        batch.put_cf(CF, [6], [60]);
+        batch.put_cf(CF, [9], [91]);
+        overlay.write(batch).unwrap();
+
+        assert_eq!(overlay.get_cf(CF, &[3]).unwrap(), None);
+        assert_eq!(overlay.get_cf(CF, &[5]).unwrap(), None);
+        assert_eq!(overlay.get_cf(CF, &[6]).unwrap(), Some(vec![60]));
+        assert_eq!(overlay.get_cf(CF, &[9]).unwrap(), Some(vec![91]));
+        assert_eq!(overlay.get_cf(CF, &[1]).unwrap(), Some(vec![10]));
+        assert_eq!(keys(&overlay, IteratorMode::Start), vec![1, 4, 6, 9]);
+        assert_eq!(keys(&overlay, IteratorMode::End), vec![9, 6, 4, 1]);
+        assert_eq!(
+            keys(&overlay, IteratorMode::From(&[5], Direction::Forward)),
+            vec![6, 9]
+        );
+        assert_eq!(
+            keys(&overlay, IteratorMode::From(&[8], Direction::Reverse)),
+            vec![6, 4, 1]
+        );
+
+        // The base is only written on commit, in the same order.
+        assert_eq!(keys(&*base, IteratorMode::Start), vec![1, 3, 5, 7, 9]);
+        overlay.commit().unwrap();
+        assert_eq!(keys(&*base, IteratorMode::Start), vec![1, 4, 6, 9]);
+        assert_eq!(base.get_cf(CF, &[9]).unwrap(), Some(vec![91]));
+        assert_eq!(keys(&overlay, IteratorMode::Start), vec![1, 4, 6, 9]);
+    }
+}